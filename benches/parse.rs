@@ -0,0 +1,34 @@
+use std::{env, fmt::Write, fs};
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use enw::{EnvSource, FileSource};
+
+/// A generated env file with a mix of plain, quoted and commented lines
+fn generated_env_file(lines: usize) -> String {
+    let mut doc = String::new();
+    for i in 0..lines {
+        match i % 4 {
+            0 => writeln!(doc, "PLAIN_{i}=value-{i}"),
+            1 => writeln!(doc, "SINGLE_{i}='single quoted {i}'"),
+            2 => writeln!(doc, "DOUBLE_{i}=\"double \\\"quoted\\\" {i}\" # comment"),
+            _ => writeln!(doc, "# comment {i}"),
+        }
+        .unwrap();
+    }
+    doc
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let doc = generated_env_file(5000);
+    let path = env::temp_dir().join(format!("enw-bench-{}.env", std::process::id()));
+    fs::write(&path, &doc).unwrap();
+    let source = FileSource::new(&path);
+    let mut group = c.benchmark_group("parse");
+    group.throughput(Throughput::Bytes(doc.len() as u64));
+    group.bench_function("5000 lines", |b| b.iter(|| source.load(&|_| None).unwrap()));
+    group.finish();
+    fs::remove_file(&path).unwrap();
+}
+
+criterion_group!(benches, bench_parse);
+criterion_main!(benches);
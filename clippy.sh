@@ -1,7 +1,16 @@
 #!/usr/bin/env bash
 
+set -e
+
 cargo clippy -- \
   --deny warnings \
   --allow clippy::new_without_default \
   --allow clippy::unneeded-field-pattern \
   --allow clippy::unit_arg
+
+# Parsing and resolution also build for targets without Unix, such as WASI
+if rustup target list --installed | grep -qx wasm32-wasip1; then
+  cargo check --no-default-features --features no-exec --target wasm32-wasip1
+else
+  echo "skipping the wasm32-wasip1 check, add the target with: rustup target add wasm32-wasip1" >&2
+fi
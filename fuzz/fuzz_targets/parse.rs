@@ -0,0 +1,26 @@
+//! Env files are loaded without panicking, and whatever is loaded from them can be written
+//! back with `to_dotenv_string` and loads the same again
+//!
+//! Run with `cargo fuzz run parse`. The dialect directives let the fuzzer reach every dialect.
+
+#![no_main]
+
+use std::{env, fs, process};
+
+use enw::{EnvSource, FileSource};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let path = env::temp_dir().join(format!("enw-fuzz-{}.env", process::id()));
+    let lookup = |key: &str| key.len().is_multiple_of(2).then(|| key.to_lowercase());
+    fs::write(&path, data).unwrap();
+    let Ok(vars) = FileSource::new(&path).load(&lookup) else {
+        return;
+    };
+    let Ok(doc) = enw::to_dotenv_string(vars.iter().map(|(key, value)| (key, value))) else {
+        return;
+    };
+    fs::write(&path, &doc).unwrap();
+    let reloaded = FileSource::new(&path).load(&lookup).unwrap();
+    assert_eq!(vars, reloaded, "{doc}");
+});
@@ -0,0 +1,132 @@
+//! The audit log of `--audit-log`, recording which secrets were passed to which command
+//!
+//! Each time COMMAND is started with variables from a secrets backend, such as a secrets
+//! service, an encrypted file or 1Password, a record is appended to the log as a line of JSON:
+//! the time, the user, the command, the names of the variables and their sources. Values are
+//! never recorded. With `syslog` as the log, records go to the authpriv facility instead.
+
+use std::{io::Write, path::PathBuf, time::SystemTime};
+
+use indexmap::IndexMap;
+use serde_json::json;
+
+use crate::{capture::format_timestamp, os::private_open_options, Error};
+
+/// The log that records go to in place of a file
+pub(crate) const SYSLOG: &str = "syslog";
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) enum AuditLog {
+    File(PathBuf),
+    Syslog,
+}
+
+impl AuditLog {
+    pub(crate) fn new(log: PathBuf) -> Self {
+        match log.as_os_str() == SYSLOG {
+            true => AuditLog::Syslog,
+            false => AuditLog::File(log),
+        }
+    }
+
+    /// Record that `command` is started with the variables of `secret_origins`, mapping the
+    /// names of the variables to the sources they come from. Nothing is recorded without any.
+    pub(crate) fn record(
+        &self,
+        command: &[String],
+        secret_origins: &IndexMap<String, String>,
+    ) -> Result<(), Error> {
+        if secret_origins.is_empty() {
+            return Ok(());
+        }
+        let record = format_record(SystemTime::now(), command, secret_origins);
+        match self {
+            AuditLog::File(path) => private_open_options()
+                .create(true)
+                .append(true)
+                .open(path)
+                // The whole line at once, as the log may be shared by several processes
+                .and_then(|mut file| file.write_all(format!("{record}\n").as_bytes()))
+                .map_err(|err| Error::io(path, err)),
+            AuditLog::Syslog => syslog(record),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn syslog(record: String) -> Result<(), Error> {
+    let message = std::ffi::CString::new(record).map_err(|_| "invalid audit record")?;
+    // SAFETY: the strings are NUL terminated, and the message is passed as an argument rather
+    // than as the format
+    unsafe {
+        libc::openlog(c"enw".as_ptr(), libc::LOG_PID, libc::LOG_AUTHPRIV);
+        libc::syslog(libc::LOG_INFO, c"%s".as_ptr(), message.as_ptr());
+        libc::closelog();
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn syslog(_: String) -> Result<(), Error> {
+    Err("--audit-log syslog is only supported on Unix".into())
+}
+
+/// The record as a line of JSON
+fn format_record(
+    time: SystemTime,
+    command: &[String],
+    secret_origins: &IndexMap<String, String>,
+) -> String {
+    let mut sources: Vec<&String> = secret_origins.values().collect();
+    sources.sort();
+    sources.dedup();
+    // SAFETY: plain system call
+    #[cfg(unix)]
+    let uid = Some(unsafe { libc::getuid() });
+    #[cfg(not(unix))]
+    let uid: Option<u32> = None;
+    json!({
+        "time": format_timestamp(time),
+        "uid": uid,
+        "pid": std::process::id(),
+        "command": command,
+        "keys": secret_origins.keys().collect::<Vec<_>>(),
+        "sources": sources,
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+
+    #[test]
+    fn test_format_record() {
+        let origins: IndexMap<String, String> = [
+            ("DB_PASSWORD", "https://vault/prod"),
+            ("API_KEY", ".env.gpg"),
+            ("TOKEN", "https://vault/prod"),
+        ]
+        .into_iter()
+        .map(|(key, source)| (key.to_owned(), source.to_owned()))
+        .collect();
+        let time = UNIX_EPOCH + Duration::from_secs(86400);
+        let record = format_record(time, &["app".to_owned(), "--serve".to_owned()], &origins);
+        let record: serde_json::Value = serde_json::from_str(&record).unwrap();
+        assert_eq!(record["time"], "1970-01-02T00:00:00.000Z");
+        assert_eq!(record["command"], json!(["app", "--serve"]));
+        assert_eq!(record["keys"], json!(["DB_PASSWORD", "API_KEY", "TOKEN"]));
+        assert_eq!(record["sources"], json!([".env.gpg", "https://vault/prod"]));
+    }
+
+    #[test]
+    fn test_new() {
+        assert_eq!(AuditLog::new(PathBuf::from("syslog")), AuditLog::Syslog);
+        assert_eq!(
+            AuditLog::new(PathBuf::from("./syslog")),
+            AuditLog::File(PathBuf::from("./syslog"))
+        );
+    }
+}
@@ -0,0 +1,185 @@
+//! Opt-in cache of parsed env files, enabled with `--cache`
+//!
+//! Entries are kept in `$XDG_CACHE_HOME/enw`, one JSON file per env file and set of parse
+//! options, and are used as long as the modification time and size of the file and of the
+//! files it includes are unchanged. Files whose variables depend on the environment, through
+//! expansion, passed through keys or command substitution, are not cached.
+//!
+//! Environments fetched from remotes are kept for the time to live of the remote.
+
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    edit::write_atomically,
+    log::log,
+    os::{os_to_string, private_dir_builder, string_to_os},
+    parse::ParseOptions,
+    Error,
+};
+
+/// Path of the cache below the XDG cache directory
+const CACHE_DIR: &str = "enw";
+
+/// Files modified more recently are not cached, as a change within the granularity of the
+/// modification time would go unnoticed
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+/// `$XDG_CACHE_HOME/enw`, by default in `~/.cache`
+pub(crate) fn cache_dir() -> Result<PathBuf, Error> {
+    let cache_home = match env::var_os("XDG_CACHE_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => env::var_os("HOME")
+            .map(|home| Path::new(&home).join(".cache"))
+            .ok_or("neither XDG_CACHE_HOME nor HOME is set")?,
+    };
+    Ok(cache_home.join(CACHE_DIR))
+}
+
+/// Remove all cache entries
+pub(crate) fn clear() -> Result<(), Error> {
+    let dir = cache_dir()?;
+    match fs::remove_dir_all(&dir) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(Error::io(dir, err)),
+        _ => Ok(()),
+    }
+}
+
+/// The cached variables of `path`, if they are still valid
+pub(crate) fn get(path: &Path, options: &ParseOptions) -> Option<Vec<(String, String)>> {
+    let entry_path = entry_path(path, options).ok()?;
+    let entry: Value = serde_json::from_slice(&fs::read(entry_path).ok()?).ok()?;
+    if entry["version"] != env!("CARGO_PKG_VERSION") {
+        return None;
+    }
+    for file in entry["files"].as_array()? {
+        let stamp = stamp(Path::new(&string_to_os(file["path"].as_str()?))).ok()?;
+        if file["stamp"] != json!(stamp) {
+            return None;
+        }
+    }
+    vars_of(&entry)
+}
+
+/// Cache the variables of `path`, parsed from `files`. Failures are only logged, as the cache
+/// is merely an optimization.
+pub(crate) fn put(
+    path: &Path,
+    options: &ParseOptions,
+    files: &[PathBuf],
+    vars: &[(String, String)],
+) {
+    if let Err(err) = write_entry(path, options, files, vars) {
+        log!(Info, "not caching {}: {err}", path.to_string_lossy());
+    }
+}
+
+fn write_entry(
+    path: &Path,
+    options: &ParseOptions,
+    files: &[PathBuf],
+    vars: &[(String, String)],
+) -> Result<(), Error> {
+    let mut stamps = Vec::new();
+    for file in files {
+        let stamp = stamp(file)?;
+        if SystemTime::now() < UNIX_EPOCH + Duration::from_nanos(stamp[0]) + SETTLE_TIME {
+            log!(
+                Debug,
+                "not caching {}: just modified",
+                path.to_string_lossy()
+            );
+            return Ok(());
+        }
+        stamps.push(json!({ "path": os_to_string(file.as_os_str()), "stamp": stamp }));
+    }
+    let entry = json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "files": stamps,
+        "vars": vars,
+    });
+    write_private(&entry_path(path, options)?, &entry)
+}
+
+/// The variables fetched from the remote `id` at most `ttl` ago
+pub(crate) fn get_fetched(id: &str, ttl: Duration) -> Option<Vec<(String, String)>> {
+    let entry_path = fetched_entry_path(id).ok()?;
+    let entry: Value = serde_json::from_slice(&fs::read(entry_path).ok()?).ok()?;
+    if entry["version"] != env!("CARGO_PKG_VERSION") {
+        return None;
+    }
+    let fetched = UNIX_EPOCH + Duration::from_secs(entry["fetched"].as_u64()?);
+    if SystemTime::now().duration_since(fetched).ok()? > ttl {
+        return None;
+    }
+    vars_of(&entry)
+}
+
+/// Cache the variables fetched from the remote `id`, only logging failures
+pub(crate) fn put_fetched(id: &str, vars: &[(String, String)]) {
+    let fetched = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let entry = json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "fetched": fetched,
+        "vars": vars,
+    });
+    if let Err(err) = fetched_entry_path(id).and_then(|path| write_private(&path, &entry)) {
+        log!(Info, "not caching {id}: {err}");
+    }
+}
+
+fn vars_of(entry: &Value) -> Option<Vec<(String, String)>> {
+    entry["vars"]
+        .as_array()?
+        .iter()
+        .map(|var| Some((var[0].as_str()?.to_owned(), var[1].as_str()?.to_owned())))
+        .collect()
+}
+
+/// Write a cache entry. Values may be secrets, so the cache is only readable by the user.
+fn write_private(path: &Path, entry: &Value) -> Result<(), Error> {
+    if let Some(dir) = path.parent() {
+        private_dir_builder()
+            .recursive(true)
+            .create(dir)
+            .map_err(|err| Error::io(dir, err))?;
+    }
+    write_atomically(path, entry.to_string().as_bytes()).map_err(|err| Error::io(path, err))
+}
+
+/// The modification time in nanoseconds and the size of `file`
+fn stamp(file: &Path) -> Result<[u64; 2], Error> {
+    let metadata = fs::metadata(file).map_err(|err| Error::io(file, err))?;
+    let modified = metadata
+        .modified()
+        .map_err(|err| Error::io(file, err))?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    Ok([
+        u64::try_from(modified.as_nanos()).unwrap_or(u64::MAX),
+        metadata.len(),
+    ])
+}
+
+/// The entry of `path` parsed with `options`, named by a hash of both
+fn entry_path(path: &Path, options: &ParseOptions) -> Result<PathBuf, Error> {
+    let canonical = path.canonicalize().map_err(|err| Error::io(path, err))?;
+    let mut hasher = Sha256::new();
+    hasher.update(os_to_string(canonical.as_os_str()));
+    hasher.update(format!("\0{options:?}"));
+    Ok(cache_dir()?.join(format!("{:x}.json", hasher.finalize())))
+}
+
+fn fetched_entry_path(id: &str) -> Result<PathBuf, Error> {
+    let hash = Sha256::digest(format!("remote\0{id}"));
+    Ok(cache_dir()?.join(format!("{hash:x}.json")))
+}
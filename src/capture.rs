@@ -0,0 +1,143 @@
+//! Capture of the output of COMMAND, with `--log-prefix` and `--log-file`
+//!
+//! The standard output and error of COMMAND are then read line by line by enw, which
+//! supervises COMMAND rather than executing it in its place. Each line is passed on with the
+//! prefix, and appended to the log file after a UTC timestamp.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Read, Write},
+    path::PathBuf,
+    process::{Command, ExitStatus, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::Error;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct OutputCapture {
+    prefix: Option<String>,
+    log_file: Option<PathBuf>,
+}
+
+impl OutputCapture {
+    pub(crate) fn new(prefix: Option<String>, log_file: Option<PathBuf>) -> Self {
+        OutputCapture { prefix, log_file }
+    }
+
+    /// Whether the output of COMMAND passes through enw
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.prefix.is_some() || self.log_file.is_some()
+    }
+
+    /// Run `cmd` to completion, capturing its output if enabled
+    pub(crate) fn status(&self, cmd: &mut Command) -> Result<ExitStatus, Error> {
+        let program = cmd.get_program().to_string_lossy().into_owned();
+        if !self.is_enabled() {
+            return cmd.status().map_err(|err| Error::exec(program, err));
+        }
+        let log_file = match &self.log_file {
+            Some(path) => Some(Arc::new(Mutex::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|err| Error::io(path, err))?,
+            ))),
+            None => None,
+        };
+        let mut child = cmd
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| Error::exec(program, err))?;
+        let prefix = self.prefix.clone().unwrap_or_default();
+        let stdout = child.stdout.take().map(|pipe| {
+            let (prefix, log_file) = (prefix.clone(), log_file.clone());
+            thread::spawn(move || relay(pipe, io::stdout(), &prefix, log_file.as_deref()))
+        });
+        let stderr = child.stderr.take().map(|pipe| {
+            thread::spawn(move || relay(pipe, io::stderr(), &prefix, log_file.as_deref()))
+        });
+        let status = child.wait()?;
+        for relay in stdout.into_iter().chain(stderr) {
+            relay.join().map_err(|_| "relaying output failed")??;
+        }
+        Ok(status)
+    }
+}
+
+/// Pass the lines read from `pipe` on to `out` with `prefix`, and to `log_file` with a
+/// timestamp as well. Each line is written at once, so that lines relayed from several pipes
+/// don't mix.
+pub(crate) fn relay(
+    pipe: impl Read,
+    mut out: impl Write,
+    prefix: &str,
+    log_file: Option<&Mutex<File>>,
+) -> io::Result<()> {
+    let mut reader = BufReader::new(pipe);
+    let mut line = prefix.as_bytes().to_vec();
+    while reader.read_until(b'\n', &mut line)? > 0 {
+        if !line.ends_with(b"\n") {
+            line.push(b'\n');
+        }
+        out.write_all(&line)?;
+        out.flush()?;
+        if let Some(log_file) = log_file {
+            let mut log_file = log_file.lock().unwrap_or_else(|err| err.into_inner());
+            write!(log_file, "{} ", format_timestamp(SystemTime::now()))?;
+            log_file.write_all(&line)?;
+        }
+        line.truncate(prefix.len());
+    }
+    Ok(())
+}
+
+/// Format `time` as an RFC 3339 timestamp in UTC with milliseconds
+pub(crate) fn format_timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = (secs / 86400, secs % 86400);
+    // Civil date from days since the epoch, after Howard Hinnant's days_from_civil inverse
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        let time = UNIX_EPOCH + Duration::from_millis(1_709_210_096_789);
+        assert_eq!(format_timestamp(time), "2024-02-29T12:34:56.789Z");
+    }
+
+    #[test]
+    fn test_relay() {
+        let mut out = Vec::new();
+        relay(&b"one\ntwo"[..], &mut out, "[api] ", None).unwrap();
+        assert_eq!(out, b"[api] one\n[api] two\n");
+    }
+}
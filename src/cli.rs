@@ -0,0 +1,1192 @@
+//! Command line grammar
+
+use std::{ffi::OsString, path::PathBuf, process};
+
+use clap::{parser::ValueSource, value_parser, Arg, ArgAction, ArgMatches, Command, ValueHint};
+
+use crate::{
+    os::{os_to_string, string_to_os},
+    output::{parse_separator, FORMAT_NAMES, SHELL_NAMES},
+    parse::{Lookup, DIALECT_NAMES},
+    retry::parse_duration,
+    wait::WaitTarget,
+    warning::WARNING_KINDS,
+    Error, EXIT_ERROR,
+};
+
+const ABOUT: &str =
+    "Similar to the GNU env command, but will automatically load an .env file, if found.";
+const USAGE: &str = "enw [OPTION]... [-] [NAME=VALUE]... [--] [COMMAND [ARGS]...]
+       enw <SUBCOMMAND> [OPTION]... [NAME=VALUE]... [ARGS]...";
+
+const OPTION_VARS_HELP: &str = "Options can also be set with variables named after them, such as \
+ENW_QUIET=1 for --quiet, ENW_VERBOSE=2 for -vv or ENW_ON_DUPLICATE=warn for --on-duplicate warn. \
+Repeated options take \
+lists, of files separated by : as in ENW_FILES=a.env:b.env, and of other values separated by \
+whitespace. Options given on the command line take precedence over the variables, which take \
+precedence over .enwrc. Options overriding safety checks, such as --allow-dangerous, --preserve-env \
+and --user, and those writing files or detaching, such as --save-env and --daemon, are only taken \
+from the command line.";
+
+/// The subcommands, each taking the same global options as the bare invocation
+pub(crate) const SUBCOMMANDS: [(&str, &str); 10] = [
+    (
+        "run",
+        "run COMMAND with the resolved environment, also one named like a subcommand",
+    ),
+    (
+        "print",
+        "print the resolved environment, or only the values of KEY..., as printenv does",
+    ),
+    (
+        "check",
+        "check that the .env files can be loaded, without printing anything",
+    ),
+    (
+        "diff",
+        "show how the resolved environment differs from the current one",
+    ),
+    ("get", "print the value of NAME in the resolved environment"),
+    (
+        "which",
+        "print the path of the executable COMMAND would run, found with the resolved PATH",
+    ),
+    (
+        "export",
+        "print the resolved environment as export statements for SHELL",
+    ),
+    (
+        "status",
+        "show whether the env file of the current directory is allowed and loaded by the shell hook",
+    ),
+    (
+        "shell",
+        "start an interactive $SHELL with the resolved environment and a marker on its prompt",
+    ),
+    (
+        "up",
+        "run the processes of a Procfile together with the resolved environment",
+    ),
+];
+
+/// The prefix of the variables setting options, as `ENW_QUIET=1` does `--quiet`
+pub(crate) const OPTION_VAR_PREFIX: &str = "ENW_";
+
+/// Options that can't be set with variables: those that change how the other arguments are
+/// read or that run commands of their own, `--shell`, as `ENW_SHELL` marks `enw shell`, those
+/// overriding safety checks, which a variable inherited unawares must not turn off, and those
+/// writing files or detaching, which would apply to every nested enw
+const NO_VAR_OPTIONS: [&str; 16] = [
+    "split_string",
+    "shell_cmd",
+    "posix",
+    "shell",
+    "allow_dangerous",
+    "allow_command_subst",
+    "preserve_env",
+    "lenient",
+    "user",
+    "group",
+    "no_perms_check",
+    "save_env",
+    "audit_log",
+    "log_file",
+    "daemon",
+    "pid_file",
+];
+
+/// Parse the arguments, with the options that aren't given set from `ENW_*` variables found
+/// with `lookup`, if any
+pub(crate) fn parse_arguments(
+    mut args: Vec<OsString>,
+    with_subcommands: bool,
+    lookup: Option<Lookup>,
+) -> ArgMatches {
+    let matches = parse(args.clone(), with_subcommands);
+    let var_args = lookup.map_or_else(Vec::new, |lookup| {
+        var_args(&build_cli(with_subcommands), &matches, lookup)
+    });
+    if var_args.is_empty() {
+        return matches;
+    }
+    // Before the arguments given, as the options of enw come first
+    args.splice(1.min(args.len())..1.min(args.len()), var_args);
+    parse(args, with_subcommands)
+}
+
+fn parse(args: Vec<OsString>, with_subcommands: bool) -> ArgMatches {
+    build_cli(with_subcommands)
+        .try_get_matches_from(args)
+        .unwrap_or_else(|err| {
+            // Usage errors exit like other errors of enw, while --help and --version succeed
+            if err.use_stderr() {
+                let _ = err.print();
+                process::exit(EXIT_ERROR);
+            }
+            err.exit()
+        })
+}
+
+/// The variable setting `arg`, e.g. `ENW_ON_DUPLICATE` for `--on-duplicate`. Repeated options
+/// taking files are set with lists, as `ENW_FILES` for `--file`.
+pub(crate) fn option_var(arg: &Arg) -> Option<String> {
+    let long = arg.get_long()?;
+    let mut var = format!(
+        "{OPTION_VAR_PREFIX}{}",
+        long.to_uppercase().replace('-', "_")
+    );
+    if is_list_of_files(arg) {
+        var.push('S');
+    }
+    Some(var)
+}
+
+fn is_list_of_files(arg: &Arg) -> bool {
+    matches!(arg.get_action(), ArgAction::Append)
+        && matches!(
+            arg.get_value_hint(),
+            ValueHint::FilePath | ValueHint::AnyPath
+        )
+}
+
+/// The arguments for the options set with `ENW_*` variables, except those given on the command
+/// line and those conflicting with the options given. Flags are set unless the value is empty,
+/// `0`, `false`, `no` or `off`. Repeated options take several values, files separated by `:`
+/// as in PATH and other values by whitespace as in `.enwrc`.
+fn var_args(cli: &Command, matches: &ArgMatches, lookup: Lookup) -> Vec<OsString> {
+    let sub_matches = matches.subcommand().map(|(_, matches)| matches);
+    let given = |id: &str| {
+        [Some(matches), sub_matches]
+            .into_iter()
+            .flatten()
+            // Only the common options are known to subcommands
+            .any(|matches| {
+                matches.try_get_raw(id).is_ok()
+                    && matches.value_source(id) == Some(ValueSource::CommandLine)
+            })
+    };
+    let mut args = Vec::new();
+    for arg in cli.get_arguments().filter(|arg| arg.is_global_set()) {
+        let id = arg.get_id().as_str();
+        let (Some(long), Some(var)) = (arg.get_long(), option_var(arg)) else {
+            continue;
+        };
+        let Some(value) = lookup(&var).filter(|value| !value.is_empty()) else {
+            continue;
+        };
+        // Conflicts are declared on either of the options
+        let conflicts = |other: &Arg| {
+            let conflict = |a, b: &Arg| cli.get_arg_conflicts_with(a).contains(&b);
+            conflict(arg, other) || conflict(other, arg)
+        };
+        if NO_VAR_OPTIONS.contains(&id)
+            || given(id)
+            || cli
+                .get_arguments()
+                .any(|other| given(other.get_id().as_str()) && conflicts(other))
+        {
+            continue;
+        }
+        let off = ["0", "false", "no", "off"];
+        let is_off = off.iter().any(|off| value.eq_ignore_ascii_case(off));
+        let values: Vec<&str> = match arg.get_action() {
+            ArgAction::SetTrue => {
+                if !is_off {
+                    args.push(OsString::from(format!("--{long}")));
+                }
+                continue;
+            }
+            // Counted as often as the number says, as ENW_VERBOSE=2 does -vv
+            ArgAction::Count => {
+                let count = value
+                    .parse::<u8>()
+                    .map_or(usize::from(!is_off), usize::from);
+                args.extend(std::iter::repeat_n(
+                    OsString::from(format!("--{long}")),
+                    count,
+                ));
+                continue;
+            }
+            ArgAction::Append if is_list_of_files(arg) => {
+                value.split(':').filter(|v| !v.is_empty()).collect()
+            }
+            ArgAction::Append => value.split_whitespace().collect(),
+            _ => vec![value.as_str()],
+        };
+        for value in values {
+            let mut option = OsString::from(format!("--{long}="));
+            option.push(string_to_os(value));
+            args.push(option);
+        }
+    }
+    args
+}
+
+/// Replace each `-S STRING` among the options with the arguments STRING splits into, so that
+/// several arguments can be given on a shebang line, e.g. `#!/usr/bin/enw -S -f app.env python3`
+pub(crate) fn expand_split_string(
+    mut args: Vec<OsString>,
+    lookup: Lookup,
+) -> Result<Vec<OsString>, Error> {
+    let cli = build_cli(false);
+    let takes_value = |arg: &str| {
+        cli.get_arguments()
+            .filter(|a| a.get_action().takes_values() && !a.is_positional())
+            .any(|a| match arg.strip_prefix("--") {
+                Some(long) => a.get_long() == Some(long),
+                None => arg.ends_with(|c| a.get_short() == Some(c)),
+            })
+    };
+    let mut i = 1;
+    while let Some(arg) = args.get(i).map(|arg| os_to_string(arg)) {
+        let split = match arg.as_str() {
+            "-S" | "--split-string" => {
+                let value = args.get(i + 1).ok_or(format!("{arg} requires a STRING"))?;
+                Some((2, os_to_string(value)))
+            }
+            _ => arg
+                .strip_prefix("--split-string=")
+                .or_else(|| arg.strip_prefix("-S").filter(|v| !v.is_empty()))
+                .map(|value| (1, value.to_owned())),
+        };
+        if let Some((len, value)) = split {
+            let split_args = split_string(&value, lookup)?;
+            args.splice(i..i + len, split_args.iter().map(|arg| string_to_os(arg)));
+        } else if arg == "--" || arg == "-" || !arg.starts_with('-') {
+            break;
+        } else if !arg.contains('=') && takes_value(&arg) {
+            i += 2;
+        } else {
+            i += 1;
+        }
+    }
+    Ok(args)
+}
+
+/// Split `s` into arguments the way `env -S` does: on whitespace outside of quotes, with `\`
+/// escapes, `${NAME}` references, and `#` starting a comment at the beginning of an argument
+pub(crate) fn split_string(s: &str, lookup: Lookup) -> Result<Vec<String>, Error> {
+    let mut args = Vec::new();
+    let mut arg = String::new();
+    let mut in_arg = false;
+    let mut quote = None;
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), _) if c == q => quote = None,
+            (Some('\''), '\\') => match chars.peek() {
+                Some(&escaped @ ('\\' | '\'')) => {
+                    chars.next();
+                    arg.push(escaped);
+                }
+                _ => arg.push(c),
+            },
+            (Some('\''), _) => arg.push(c),
+            (_, '\\') => {
+                let escaped = chars.next().ok_or("no character after \\ in -S string")?;
+                match escaped {
+                    '_' if quote.is_some() => arg.push(' '),
+                    '_' => {
+                        if in_arg {
+                            args.push(std::mem::take(&mut arg));
+                            in_arg = false;
+                        }
+                        continue;
+                    }
+                    'c' if quote.is_none() => break,
+                    'n' => arg.push('\n'),
+                    't' => arg.push('\t'),
+                    'r' => arg.push('\r'),
+                    'f' => arg.push('\x0c'),
+                    'v' => arg.push('\x0b'),
+                    '\\' | '\'' | '"' | '#' | '$' => arg.push(escaped),
+                    _ => return Err(format!("invalid sequence \\{escaped} in -S string").into()),
+                }
+            }
+            (_, '$') => {
+                if chars.next() != Some('{') {
+                    return Err("only ${NAME} references are supported in -S string".into());
+                }
+                let name: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                arg.push_str(&lookup(&name).unwrap_or_default());
+            }
+            (Some(_), _) => arg.push(c),
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '#') if !in_arg => break,
+            (None, c) if c.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut arg));
+                    in_arg = false;
+                }
+                continue;
+            }
+            (None, _) => arg.push(c),
+        }
+        in_arg = true;
+    }
+    if quote.is_some() {
+        return Err("unmatched quote in -S string".into());
+    }
+    if in_arg {
+        args.push(arg);
+    }
+    Ok(args)
+}
+
+/// Print a completion script for `shell` to stdout
+pub(crate) fn print_completions(shell: clap_complete::Shell) {
+    clap_complete::generate(shell, &mut build_cli(true), "enw", &mut std::io::stdout());
+}
+
+/// The names of all subcommands, for `--version-json`
+pub(crate) fn subcommand_names() -> Vec<String> {
+    build_cli(true)
+        .get_subcommands()
+        .map(|subcommand| subcommand.get_name().to_owned())
+        .collect()
+}
+
+fn build_cli(with_subcommands: bool) -> Command {
+    let mut cmd = Command::new("enw")
+        .about(ABOUT)
+        .version(env!("CARGO_PKG_VERSION"))
+        .override_usage(USAGE)
+        .after_long_help(OPTION_VARS_HELP)
+        .arg(
+            flag("version_json")
+                .long("version-json")
+                .help("print the version and capabilities of enw as JSON"),
+        )
+        .args(common_args().into_iter().map(|arg| arg.global(true)))
+        .arg(rest_arg("REST").hide(true));
+    if with_subcommands {
+        for (name, about) in SUBCOMMANDS {
+            let mut subcommand = Command::new(name).about(about);
+            subcommand = match name {
+                "run" => subcommand
+                    .visible_alias("exec")
+                    .arg(
+                        rest_arg("[NAME=VALUE]... COMMAND [ARGS]")
+                            .required_unless_present("shell_cmd"),
+                    ),
+                "print" => subcommand.arg(rest_arg("[NAME=VALUE]... [KEY]")),
+                "check" => subcommand
+                    .arg(
+                        flag("collisions")
+                            .long("collisions")
+                            .help("report variables that several sources define with different values"),
+                    )
+                    .arg(rest_arg("NAME=VALUE")),
+                "get" => subcommand
+                    .arg(Arg::new("get_key").value_name("NAME").required(true))
+                    .arg(rest_arg("NAME=VALUE")),
+                "which" => subcommand
+                    .arg(
+                        Arg::new("which_command")
+                            .value_name("COMMAND")
+                            .required(true),
+                    )
+                    .arg(rest_arg("NAME=VALUE")),
+                "diff" => subcommand
+                    .arg(
+                        Arg::new("diff_pid")
+                            .long("pid")
+                            .value_name("PID")
+                            .help("compare with the environment process PID was started with, to tell whether it needs a restart")
+                            .value_parser(value_parser!(u32).range(1..)),
+                    )
+                    .arg(rest_arg("NAME=VALUE")),
+                "export" => subcommand
+                    .arg(
+                        Arg::new("export_shell")
+                            .value_name("SHELL")
+                            .value_parser(SHELL_NAMES)
+                            .required(true),
+                    )
+                    .arg(
+                        flag("export_diff")
+                            .long("diff")
+                            .help("only export variables that differ from the current environment"),
+                    )
+                    .arg(rest_arg("NAME=VALUE")),
+                "status" => subcommand
+                    .arg(
+                        flag("porcelain")
+                            .long("porcelain")
+                            .help("print KEY VALUE lines that stay stable, e.g. for a prompt"),
+                    )
+                    .arg(rest_arg("NAME=VALUE")),
+                "shell" => subcommand
+                    .arg(
+                        Arg::new("prompt_marker")
+                            .long("prompt")
+                            .value_name("MARKER")
+                            .help("put MARKER in front of the prompt of the shell [default: \"(enw) \"]"),
+                    )
+                    .arg(rest_arg("NAME=VALUE")),
+                "up" => subcommand
+                    .arg(
+                        Arg::new("procfile")
+                            .long("procfile")
+                            .value_name("FILE")
+                            .help("read the processes from FILE [default: Procfile]")
+                            .value_parser(value_parser!(PathBuf))
+                            .value_hint(ValueHint::FilePath),
+                    )
+                    .arg(
+                        Arg::new("shutdown_timeout")
+                            .long("shutdown-timeout")
+                            .value_name("DURATION")
+                            .help("kill processes still running DURATION after their stop signal [default: 5s]")
+                            .value_parser(parse_duration),
+                    )
+                    .arg(rest_arg("NAME=VALUE")),
+                _ => subcommand.arg(rest_arg("NAME=VALUE")),
+            };
+            cmd = cmd.subcommand(subcommand);
+        }
+        cmd = cmd.subcommand(
+            Command::new("lint")
+                .about("check env files for common mistakes, .env by default")
+                .arg(
+                    Arg::new("lint_files")
+                        .value_name("FILE")
+                        .value_parser(value_parser!(PathBuf))
+                        .value_hint(ValueHint::FilePath)
+                        .num_args(1..),
+                )
+                .arg(
+                    flag("fix")
+                        .long("fix")
+                        .help("rewrite the files to fix what can be fixed"),
+                ),
+        );
+        cmd = cmd.subcommand(
+            Command::new("graph")
+                .about("print which variables the variables of env files reference, .env by default, and report cycles and undefined references")
+                .arg(
+                    Arg::new("graph_files")
+                        .value_name("FILE")
+                        .value_parser(value_parser!(PathBuf))
+                        .value_hint(ValueHint::FilePath)
+                        .num_args(1..),
+                )
+                .arg(
+                    flag("dot")
+                        .long("dot")
+                        .help("print the graph in the DOT language of graphviz"),
+                ),
+        );
+        cmd = cmd.subcommand(
+            Command::new("set")
+                .about("set variables in the env file given with -f, .env by default")
+                .arg(
+                    Arg::new("set_vars")
+                        .value_name("NAME=VALUE")
+                        .value_parser(value_parser!(OsString))
+                        .num_args(1..)
+                        .required(true),
+                ),
+        );
+        cmd = cmd.subcommand(
+            Command::new("encrypt")
+                .about("encrypt FILE, .env by default, into FILE.gpg with gpg")
+                .arg(
+                    Arg::new("encrypt_file")
+                        .value_name("FILE")
+                        .value_parser(value_parser!(PathBuf))
+                        .value_hint(ValueHint::FilePath),
+                )
+                .arg(
+                    Arg::new("recipients")
+                        .long("recipient")
+                        .value_name("KEY")
+                        .action(ArgAction::Append)
+                        .help("encrypt to the key of KEY rather than with a passphrase, may be repeated"),
+                )
+                .arg(
+                    flag("force")
+                        .long("force")
+                        .help("overwrite FILE.gpg if it exists"),
+                )
+                .arg(
+                    flag("remove")
+                        .long("remove")
+                        .help("remove FILE once FILE.gpg has been decrypted back to it"),
+                ),
+        );
+        cmd = cmd.subcommand(
+            Command::new("decrypt")
+                .about(
+                    "decrypt FILE.gpg, .env.gpg by default, into FILE, readable only by the user",
+                )
+                .arg(
+                    Arg::new("decrypt_file")
+                        .value_name("FILE")
+                        .value_parser(value_parser!(PathBuf))
+                        .value_hint(ValueHint::FilePath),
+                ),
+        );
+        cmd = cmd.subcommand(
+            Command::new("edit")
+                .about("edit the encrypted FILE, .env.gpg by default, with $VISUAL or $EDITOR and encrypt it again when changed")
+                .arg(
+                    Arg::new("edit_file")
+                        .value_name("FILE")
+                        .value_parser(value_parser!(PathBuf))
+                        .value_hint(ValueHint::FilePath),
+                ),
+        );
+        for (name, about) in [
+            (
+                "allow",
+                "allow the .env and .enwrc files of DIR, by default the current directory, to be loaded automatically, as long as they stay unchanged",
+            ),
+            ("deny", "revoke `enw allow` for DIR, by default the current directory"),
+        ] {
+            cmd = cmd.subcommand(
+                Command::new(name).about(about).arg(
+                    Arg::new("trust_dir")
+                        .value_name("DIR")
+                        .value_parser(value_parser!(PathBuf))
+                        .value_hint(ValueHint::DirPath),
+                ),
+            );
+        }
+        cmd = cmd.subcommand(
+            Command::new("hook")
+                .about("print a hook for SHELL to load the environment of each directory at the prompt")
+                .arg(
+                    Arg::new("hook_shell")
+                        .value_name("SHELL")
+                        .value_parser(["bash", "zsh", "fish"])
+                        .required(true),
+                ),
+        );
+        cmd = cmd.subcommand(
+            Command::new("stop").about("stop the processes started with --daemon --pid-file FILE"),
+        );
+        cmd = cmd.subcommand(
+            Command::new("cache")
+                .about("manage the cache of parsed env files kept with --cache")
+                .subcommand_required(true)
+                .subcommand(Command::new("clear").about("remove all cached env files")),
+        );
+        cmd = cmd.subcommand(
+            Command::new("completions")
+                .about("print a shell completion script")
+                .arg(
+                    Arg::new("completion_shell")
+                        .value_name("SHELL")
+                        .value_parser(value_parser!(clap_complete::Shell))
+                        .required(true),
+                ),
+        );
+    }
+    cmd
+}
+
+/// The trailing `[NAME=VALUE]... [COMMAND [ARGS]...]` arguments
+fn rest_arg(value_name: &'static str) -> Arg {
+    Arg::new("rest")
+        .value_name(value_name)
+        .value_parser(value_parser!(OsString))
+        .value_hint(ValueHint::CommandWithArguments)
+        .num_args(1..)
+        .trailing_var_arg(true)
+}
+
+fn flag(id: &'static str) -> Arg {
+    Arg::new(id).action(ArgAction::SetTrue)
+}
+
+fn repeated(id: &'static str) -> Arg {
+    Arg::new(id).action(ArgAction::Append).num_args(1)
+}
+
+fn common_args() -> Vec<Arg> {
+    vec![
+        // Expanded by `expand_split_string` before the arguments are parsed
+        Arg::new("split_string")
+            .short('S')
+            .long("split-string")
+            .value_name("STRING")
+            .help("split STRING into separate arguments, for use on shebang lines"),
+        repeated("env_file")
+            .short('f')
+            .long("file")
+            .value_name("FILE")
+            .help(".env file, or - to read from stdin")
+            .value_parser(value_parser!(PathBuf))
+            .value_hint(ValueHint::AnyPath),
+        repeated("optional_file")
+            .long("optional-file")
+            .value_name("FILE")
+            .help(".env file loaded if it exists, and skipped without a warning otherwise")
+            .value_parser(value_parser!(PathBuf))
+            .value_hint(ValueHint::FilePath),
+        flag("lenient")
+            .long("lenient")
+            .help("warn about missing env files given with -f rather than failing"),
+        repeated("json_file")
+            .long("from-json")
+            .value_name("FILE")
+            .help("load the variables of a JSON object, or - to read it from stdin")
+            .value_parser(value_parser!(PathBuf))
+            .value_hint(ValueHint::FilePath),
+        Arg::new("from_pid")
+            .long("from-pid")
+            .value_name("PID")
+            .help("load the environment process PID was started with, below the env files, e.g. to match a running daemon with -i")
+            .value_parser(value_parser!(u32).range(1..)),
+        Arg::new("json_separator")
+            .long("json-separator")
+            .value_name("SEP")
+            .help("join the keys of nested JSON objects and arrays with SEP [default: _]")
+            .requires("json_file"),
+        repeated("prefix")
+            .long("prefix")
+            .value_name("PREFIX")
+            .help("strip PREFIX from the keys of the following .env files"),
+        repeated("add_prefix")
+            .long("add-prefix")
+            .value_name("PREFIX")
+            .help("add PREFIX to the keys of the following .env files"),
+        repeated("append")
+            .long("append")
+            .value_name("NAME=VALUE")
+            .help("add VALUE to the end of the list in NAME, e.g. PATH, as in NAME+=VALUE in .env files"),
+        repeated("prepend")
+            .long("prepend")
+            .value_name("NAME=VALUE")
+            .help("add VALUE to the start of the list in NAME, as in NAME^=VALUE in .env files"),
+        Arg::new("path_sep")
+            .long("path-sep")
+            .value_name("SEP")
+            .help("separator of the lists added to by --append, --prepend, += and ^= [default: :]"),
+        repeated("map")
+            .long("map")
+            .value_name("OLD=NEW")
+            .help("rename the variable OLD from .env files to NEW"),
+        repeated("only")
+            .long("only")
+            .value_name("PATTERN")
+            .help("only pass on variables matching PATTERN, may contain * wildcards"),
+        repeated("exclude")
+            .long("exclude")
+            .value_name("PATTERN")
+            .help("don't pass on variables matching PATTERN, may contain * wildcards"),
+        flag("ignore_env")
+            .short('i')
+            .long("ignore-env")
+            .visible_alias("pristine")
+            .help("start with an empty environment"),
+        repeated("var")
+            .long("var")
+            .value_name("NAME=VALUE")
+            .help("set NAME to VALUE, as a NAME=VALUE pair after the options does"),
+        repeated("unset")
+            .short('u')
+            .long("unset")
+            .value_name("NAME")
+            .help("remove NAME from the environment, even if inherited or set by an env file"),
+        repeated("keep")
+            .long("keep")
+            .value_name("PATTERN")
+            .help("with -i, keep the variables matching PATTERN, may contain * wildcards")
+            .requires("ignore_env"),
+        flag("no_implicit_env_file")
+            .short('n')
+            .long("no-env-file")
+            .help("don't implicitly load the .env file from current dir"),
+        flag("no_local")
+            .long("no-local")
+            .help("don't load the .env.local file on top of the implicit .env file"),
+        flag("no_global")
+            .long("no-global")
+            .help("don't load the global env files, /etc/enw/global.env and ~/.config/enw/global.env"),
+        flag("export")
+            .long("export")
+            .help("print the environment as shell export statements"),
+        Arg::new("shell")
+            .long("shell")
+            .value_name("SHELL")
+            .help("shell syntax used by --export")
+            .value_parser(SHELL_NAMES)
+            .requires("export"),
+        flag("mask")
+            .long("mask")
+            .help("redact values of secret-looking keys when printing"),
+        repeated("mask_pattern")
+            .long("mask-pattern")
+            .value_name("PATTERN")
+            .help(
+                "key pattern to redact, may contain * wildcards \
+                 [default: *TOKEN* *SECRET* *PASSWORD* *KEY*]",
+            )
+            .requires("mask"),
+        flag("mask_all")
+            .long("mask-all")
+            .help("print only the names of the variables")
+            .conflicts_with_all(["mask", "export"]),
+        flag("allow_command_subst")
+            .long("allow-command-subst")
+            .help("execute $(...) command substitutions in env file values"),
+        flag("expand")
+            .short('x')
+            .long("expand")
+            .help("expand $VAR and ${VAR} references in env file values"),
+        flag("resolve_symlinks")
+            .long("resolve-symlinks")
+            .help("resolve the includes of symlinked .env files from the directory of the target rather than of the link"),
+        flag("no_follow_symlinks")
+            .long("no-follow-symlinks")
+            .help("refuse to load .env files that are symlinks")
+            .conflicts_with("resolve_symlinks"),
+        flag("interpret_escapes")
+            .long("interpret-escapes")
+            .help("interpret \\n, \\t, \\r and \\uXXXX escapes in double-quoted values"),
+        flag("null")
+            .short('0')
+            .long("null")
+            .help("end each output line with NUL, not newline")
+            .conflicts_with("export"),
+        Arg::new("kv_sep")
+            .long("kv-sep")
+            .value_name("SEP")
+            .help("separate each printed name from its value with SEP, which may contain \\t [default: =]")
+            .value_parser(parse_separator)
+            .conflicts_with_all(["export", "format", "mask_all"]),
+        Arg::new("entry_sep")
+            .long("entry-sep")
+            .value_name("SEP")
+            .help("end each printed variable with SEP, which may contain \\t, \\n or \\0 [default: \\n]")
+            .value_parser(parse_separator)
+            .conflicts_with_all(["export", "format", "mask_all", "null"]),
+        Arg::new("quote_values")
+            .long("quote-values")
+            .value_name("WHEN")
+            .help("double quote printed values: auto quotes values with special characters [default: auto]")
+            .value_parser(["auto", "always", "never"])
+            .conflicts_with_all(["export", "format", "mask_all"]),
+        Arg::new("save_env")
+            .long("save-env")
+            .value_name("FILE")
+            .help("write the resulting environment to FILE in .env format")
+            .value_parser(value_parser!(PathBuf))
+            .value_hint(ValueHint::FilePath),
+        flag("resolve_op")
+            .long("resolve-op")
+            .help("resolve op://vault/item/field values with the 1Password CLI"),
+        flag("cache")
+            .long("cache")
+            .help("cache parsed env files, for as long as they are unchanged"),
+        Arg::new("remote")
+            .long("remote")
+            .value_name("NAME")
+            .help("fetch the environment from the secrets service [remote.NAME] of the .enwrc"),
+        Arg::new("schema")
+            .long("schema")
+            .value_name("FILE")
+            .help("fail unless the resolved environment matches the JSON schema in FILE")
+            .value_parser(value_parser!(PathBuf))
+            .value_hint(ValueHint::FilePath),
+        Arg::new("nice")
+            .long("nice")
+            .value_name("N")
+            .help("add N to the niceness of COMMAND, as with nice(1)")
+            .value_parser(value_parser!(i32))
+            .allow_negative_numbers(true),
+        Arg::new("umask")
+            .long("umask")
+            .value_name("MODE")
+            .help("run COMMAND with the octal file mode creation mask MODE"),
+        repeated("rlimit")
+            .long("rlimit")
+            .value_name("RESOURCE=SOFT[:HARD]")
+            .help("set a resource limit of COMMAND, e.g. nofile=1024:4096, as with prlimit(1)"),
+        Arg::new("max_value_size")
+            .long("max-value-size")
+            .value_name("BYTES")
+            .help("fail before running COMMAND if a value is larger than BYTES")
+            .value_parser(value_parser!(usize)),
+        Arg::new("max_env_size")
+            .long("max-env-size")
+            .value_name("BYTES")
+            .help("fail before running COMMAND if its environment and arguments take more than BYTES [default: ARG_MAX]")
+            .value_parser(value_parser!(usize)),
+        Arg::new("max_vars")
+            .long("max-vars")
+            .value_name("N")
+            .help("fail before running COMMAND if its environment has more than N variables")
+            .value_parser(value_parser!(usize)),
+        Arg::new("retries")
+            .long("retries")
+            .value_name("N")
+            .help("run COMMAND as a child and run it again up to N times while it fails")
+            .value_parser(value_parser!(u32)),
+        Arg::new("retry_delay")
+            .long("retry-delay")
+            .value_name("DURATION")
+            .help("wait DURATION, such as 500ms or 2s, before the first retry [default: 1s]")
+            .value_parser(parse_duration)
+            .requires("retries"),
+        Arg::new("retry_backoff")
+            .long("retry-backoff")
+            .value_name("FACTOR")
+            .help("wait FACTOR times longer before each further retry [default: 2]")
+            .value_parser(value_parser!(f64))
+            .requires("retries"),
+        flag("reload_on_retry")
+            .long("reload-on-retry")
+            .help("read the env files again before each retry")
+            .requires("retries"),
+        repeated("wait_for")
+            .long("wait-for")
+            .value_name("URL")
+            .help("wait for tcp://HOST:PORT to accept connections or http://HOST/PATH to answer before running COMMAND")
+            .value_parser(WaitTarget::parse),
+        Arg::new("wait_timeout")
+            .long("wait-timeout")
+            .value_name("DURATION")
+            .help("fail if --wait-for services don't respond within DURATION [default: 30s]")
+            .value_parser(parse_duration)
+            .requires("wait_for"),
+        Arg::new("log_prefix")
+            .long("log-prefix")
+            .value_name("PREFIX")
+            .help("run COMMAND as a child and start each line of its output with PREFIX"),
+        Arg::new("audit_log")
+            .long("audit-log")
+            .value_name("FILE")
+            .help("append a record of the command and of the names of the variables from secrets backends passed to it to FILE, or to syslog if FILE is `syslog`")
+            .value_parser(value_parser!(PathBuf))
+            .value_hint(ValueHint::FilePath),
+        Arg::new("log_file")
+            .long("log-file")
+            .value_name("FILE")
+            .help("run COMMAND as a child and append its output to FILE with timestamps")
+            .value_parser(value_parser!(PathBuf))
+            .value_hint(ValueHint::FilePath),
+        flag("daemon")
+            .long("daemon")
+            .help("run COMMAND in the background, detached from the terminal, with its output going to --log-file if given")
+            .requires("pid_file"),
+        Arg::new("pid_file")
+            .long("pid-file")
+            .value_name("FILE")
+            .help("write the pid of the process started with --daemon to FILE, or read it with `enw stop`")
+            .value_parser(value_parser!(PathBuf))
+            .value_hint(ValueHint::FilePath),
+        repeated("truncate")
+            .long("truncate")
+            .value_name("PATTERN")
+            .help("cut down values of variables matching PATTERN that are too large rather than failing"),
+        Arg::new("user")
+            .long("user")
+            .value_name("USER")
+            .help("run COMMAND as USER, a name or number; requires -i or --preserve-env"),
+        Arg::new("group")
+            .long("group")
+            .value_name("GROUP")
+            .help("run COMMAND with the group GROUP, by default the primary group of USER"),
+        flag("preserve_env")
+            .long("preserve-env")
+            .help("pass on the inherited environment to COMMAND run with --user or --group"),
+        flag("private_tmp")
+            .long("private-tmp")
+            .help("give COMMAND an empty /tmp of its own (Linux, sandbox feature)"),
+        flag("no_network")
+            .long("no-network")
+            .help("run COMMAND without network access, in a network namespace of its own (Linux, sandbox feature)"),
+        Arg::new("cgroup")
+            .long("cgroup")
+            .value_name("CGROUP")
+            .help("run COMMAND in an existing cgroup, a path or a name under /sys/fs/cgroup (Linux, sandbox feature)")
+            .value_parser(value_parser!(PathBuf))
+            .value_hint(ValueHint::DirPath),
+        flag("allow_dangerous")
+            .long("allow-dangerous")
+            .help("let the implicitly loaded env files set variables such as LD_PRELOAD"),
+        flag("strict_perms")
+            .long("strict-perms")
+            .help("fail if an env file defining secrets can be read by other users, rather than warn"),
+        flag("no_perms_check")
+            .long("no-perms-check")
+            .help("don't check whether env files defining secrets can be read by other users")
+            .conflicts_with("strict_perms"),
+        Arg::new("on_duplicate")
+            .long("on-duplicate")
+            .value_name("POLICY")
+            .help("what to do with variables defined more than once in the env files [default: last-wins]")
+            .value_parser(["warn", "error", "first-wins", "last-wins"]),
+        Arg::new("normalize_keys")
+            .long("normalize-keys")
+            .value_name("CASE")
+            .help("convert the names of all variables, inherited ones included, to upper or lower case [default: none]")
+            .value_parser(["upper", "lower", "none"]),
+        flag("here")
+            .long("here")
+            .help("load the implicit .env file from the directory of the script run by COMMAND")
+            .conflicts_with("no_implicit_env_file"),
+        Arg::new("timings")
+            .long("timings")
+            .value_name("FORMAT")
+            .help("report how long reading, decrypting, fetching and parsing each source took on stderr")
+            .value_parser(["text", "json"])
+            .num_args(0..=1)
+            .require_equals(true)
+            .default_missing_value("text"),
+        flag("sorted_env")
+            .long("sorted-env")
+            .help("sort the environment of COMMAND, and the file of --save-env, by name rather than by where the variables come from; COMMAND run with --retry or --capture always gets it sorted"),
+        flag("sort")
+            .long("sort")
+            .help("print variables sorted by name rather than in the order they were defined"),
+        Arg::new("format")
+            .long("format")
+            .value_name("FORMAT")
+            .help("output format when printing the environment")
+            .value_parser(FORMAT_NAMES)
+            .conflicts_with_all(["export", "mask_all"]),
+        Arg::new("chunk_size")
+            .long("chunk-size")
+            .value_name("BYTES")
+            .help("split --format docker-args into lines whose arguments each fit in BYTES, for xargs -L 1 [default: ARG_MAX less the current environment]")
+            .value_parser(value_parser!(usize))
+            .num_args(0..=1)
+            .require_equals(true)
+            .default_missing_value("0")
+            .conflicts_with("null"),
+        Arg::new("name")
+            .long("name")
+            .value_name("NAME")
+            .help("resource name for the k8s-secret and k8s-configmap formats"),
+        Arg::new("profile")
+            .long("profile")
+            .value_name("NAME")
+            .help("select the # enw:if profile=NAME blocks of the .env files"),
+        Arg::new("dialect")
+            .long("dialect")
+            .value_name("DIALECT")
+            .help("syntax of the .env files, unless a file sets its own with # enw:dialect DIALECT")
+            .value_parser(DIALECT_NAMES),
+        flag("raw")
+            .long("raw")
+            .help("same as --dialect raw: values are taken verbatim after the first '='")
+            .conflicts_with("dialect"),
+        flag("no_inline_vars")
+            .long("no-inline-vars")
+            .help("take the first argument after the options as COMMAND even if it contains '=', rather than as a NAME=VALUE pair"),
+        flag("posix")
+            .long("posix")
+            .help("behave exactly like POSIX env, without any .env handling")
+            .conflicts_with_all([
+                "env_file",
+                "optional_file",
+                "lenient",
+                "json_file",
+                "json_separator",
+                "from_pid",
+                "export",
+                "mask",
+                "mask_all",
+                "allow_command_subst",
+                "expand",
+                "interpret_escapes",
+                "resolve_symlinks",
+                "no_follow_symlinks",
+                "save_env",
+                "schema",
+                "cache",
+                "resolve_op",
+                "remote",
+                "prefix",
+                "add_prefix",
+                "map",
+                "append",
+                "prepend",
+                "path_sep",
+                "only",
+                "exclude",
+                "format",
+                "chunk_size",
+                "kv_sep",
+                "entry_sep",
+                "quote_values",
+                "profile",
+                "dialect",
+                "raw",
+                "sort",
+                "sorted_env",
+                "timings",
+                "here",
+                "on_duplicate",
+                "normalize_keys",
+                "allow_dangerous",
+                "no_global",
+                "no_local",
+                "strict_perms",
+                "no_perms_check",
+                "keep",
+                "unset",
+                "nice",
+                "umask",
+                "rlimit",
+                "max_value_size",
+                "max_env_size",
+                "max_vars",
+                "truncate",
+                "retries",
+                "retry_delay",
+                "retry_backoff",
+                "reload_on_retry",
+                "log_prefix",
+                "wait_for",
+                "wait_timeout",
+                "log_file",
+                "audit_log",
+                "daemon",
+                "pid_file",
+                "user",
+                "group",
+                "preserve_env",
+                "private_tmp",
+                "no_network",
+                "cgroup",
+                "warn",
+                "no_warn",
+                "warnings_as_errors",
+                "shell_cmd",
+                "no_inline_vars",
+                "var",
+            ]),
+        Arg::new("shell_cmd")
+            .short('c')
+            .long("shell-cmd")
+            .value_name("STRING")
+            .help("run STRING with $SHELL -c, or sh -c, so that pipes and globs work"),
+        flag("quiet")
+            .short('q')
+            .long("quiet")
+            .help("don't print any warnings"),
+        repeated("warn")
+            .long("warn")
+            .value_name("KIND")
+            .help("only report warnings of KIND, such as dup-key or file-not-found")
+            .value_parser(WARNING_KINDS),
+        repeated("no_warn")
+            .long("no-warn")
+            .value_name("KIND")
+            .help("don't report warnings of KIND")
+            .value_parser(WARNING_KINDS),
+        flag("warnings_as_errors")
+            .long("warnings-as-errors")
+            .help("fail if any warning is reported, e.g. to keep CI strict"),
+        Arg::new("verbose")
+            .short('v')
+            .long("verbose")
+            .action(ArgAction::Count)
+            .help("report on stderr which files are loaded and where variables come from; repeat for more detail"),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn test_split_string() {
+        let lookup = |key: &str| (key == "HOME").then(|| "/home/xyzzy".to_owned());
+        let split = |s| split_string(s, &lookup).unwrap();
+        assert_eq!(
+            split("-f /etc/app.env  python3"),
+            ["-f", "/etc/app.env", "python3"]
+        );
+        assert_eq!(split(r#"a'b c'"d e" '' x\_y"#), ["ab cd e", "", "x", "y"]);
+        assert_eq!(split(r#"'\\ \' \n' "\_\t\"""#), [r"\ ' \n", " \t\""]);
+        assert_eq!(
+            split("${HOME}/bin \"${UNSET}\" # comment"),
+            ["/home/xyzzy/bin", ""]
+        );
+        assert_eq!(split(r"a \c b"), ["a"]);
+        assert_eq!(split("a#b"), ["a#b"]);
+        assert!(split_string("'unmatched", &lookup).is_err());
+        assert!(split_string(r"\q", &lookup).is_err());
+        assert!(split_string("$HOME", &lookup).is_err());
+    }
+
+    #[test]
+    fn test_expand_split_string() {
+        let expand = |args: &[&str]| {
+            let args = args.iter().map(OsString::from).collect();
+            expand_split_string(args, &|_| None).unwrap()
+        };
+        assert_eq!(
+            expand(&["enw", "-S", "-i -f a.env python3", "script.py"]),
+            ["enw", "-i", "-f", "a.env", "python3", "script.py"]
+        );
+        assert_eq!(
+            expand(&["enw", "-n", "-f", "-S", "--split-string=a b", "-Sc"]),
+            ["enw", "-n", "-f", "-S", "a", "b", "-Sc"]
+        );
+        assert_eq!(
+            expand(&["enw", "cmd", "-S", "a b"]),
+            ["enw", "cmd", "-S", "a b"]
+        );
+    }
+
+    #[test]
+    fn test_var_args() {
+        let vars = [
+            ("ENW_QUIET", "1"),
+            ("ENW_SORT", "off"),
+            ("ENW_FILES", "a.env::b.env"),
+            ("ENW_ONLY", " A  B "),
+            ("ENW_PROFILE", "staging"),
+            ("ENW_FORMAT", "docker-args"),
+            ("ENW_LOG_FILE", "app.log"),
+            ("ENW_SHELL", "1"),
+            ("ENW_ALLOW_DANGEROUS", "1"),
+            ("ENW_PRESERVE_ENV", "1"),
+            ("ENW_USER", "root"),
+            ("ENW_VERBOSE", "2"),
+            ("ENW_NO_PERMS_CHECK", "1"),
+            ("ENW_SAVE_ENV", "saved.env"),
+        ];
+        let lookup = |key: &str| {
+            vars.iter()
+                .find(|(k, _)| *k == key)
+                .map(|(_, v)| v.to_string())
+        };
+        let var_args = |args: &[&str]| {
+            let cli = build_cli(true);
+            let matches = cli.clone().try_get_matches_from(args).unwrap();
+            var_args(&cli, &matches, &lookup)
+        };
+        assert_eq!(
+            var_args(&["enw", "print", "--format", "env"]),
+            [
+                "--file=a.env",
+                "--file=b.env",
+                "--only=A",
+                "--only=B",
+                "--profile=staging",
+                "--quiet",
+                "--verbose",
+                "--verbose",
+            ]
+        );
+        assert_eq!(
+            var_args(&["enw", "-f", "c.env", "--profile=dev", "--only", "C"]),
+            ["--format=docker-args", "--quiet", "--verbose", "--verbose"]
+        );
+        // Only what --posix allows
+        assert_eq!(
+            var_args(&["enw", "--posix"]),
+            ["--quiet", "--verbose", "--verbose"]
+        );
+    }
+}
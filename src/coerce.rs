@@ -0,0 +1,114 @@
+//! Type annotations in env files, validating and normalizing values
+//!
+//! ```text
+//! # enw:type PORT=int DEBUG=bool
+//! PORT= 8080
+//! DEBUG=True
+//! RATIO:float=0.5
+//! ```
+//!
+//! passes on `PORT=8080`, `DEBUG=true` and `RATIO=0.5`, while a value not of its type is an
+//! error.
+
+use std::collections::HashMap;
+
+use crate::Error;
+
+pub(crate) const TYPE_DIRECTIVE: &str = "# enw:type ";
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum ValueType {
+    /// A whole number, normalized to its decimal digits without sign or leading zeros
+    Int,
+    /// A finite number, trimmed
+    Float,
+    /// `true` or `false`, also given as `yes`, `on` and `1` or `no`, `off` and `0`
+    Bool,
+}
+
+/// The types of the variables annotated so far
+pub(crate) type Types = HashMap<String, ValueType>;
+
+impl ValueType {
+    pub(crate) fn parse(name: &str) -> Option<Self> {
+        match name {
+            "int" | "integer" => Some(ValueType::Int),
+            "float" | "number" => Some(ValueType::Float),
+            "bool" | "boolean" => Some(ValueType::Bool),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn normalize(self, value: &str) -> Result<String, String> {
+        let trimmed = value.trim();
+        let normalized = match self {
+            ValueType::Int => trimmed.parse::<i64>().ok().map(|n| n.to_string()),
+            ValueType::Float => trimmed
+                .parse::<f64>()
+                .ok()
+                .filter(|n| n.is_finite())
+                .map(|_| trimmed.to_owned()),
+            ValueType::Bool => match trimmed.to_ascii_lowercase().as_str() {
+                "true" | "yes" | "on" | "1" => Some("true".to_owned()),
+                "false" | "no" | "off" | "0" => Some("false".to_owned()),
+                _ => None,
+            },
+        };
+        normalized.ok_or_else(|| {
+            let expected = match self {
+                ValueType::Int => "an integer",
+                ValueType::Float => "a number",
+                ValueType::Bool => "a boolean",
+            };
+            format!("expected {expected}, got {value:?}")
+        })
+    }
+}
+
+/// Add the `KEY=TYPE` annotations of a `# enw:type` directive to `types`
+pub(crate) fn parse_type_directive(annotations: &str, types: &mut Types) -> Result<(), Error> {
+    for annotation in annotations.split_whitespace() {
+        let (key, name) = annotation
+            .split_once('=')
+            .ok_or_else(|| format!("expected KEY=TYPE in type directive: {annotation}"))?;
+        let value_type = ValueType::parse(name).ok_or_else(|| format!("unknown type: {name}"))?;
+        types.insert(key.to_owned(), value_type);
+    }
+    Ok(())
+}
+
+/// Split an inline annotation such as `PORT:int` off a key
+pub(crate) fn split_annotated_key(key: &str) -> (&str, Option<ValueType>) {
+    key.rsplit_once(':')
+        .and_then(|(key, name)| Some((key, Some(ValueType::parse(name)?))))
+        .unwrap_or((key, None))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(ValueType::Int.normalize(" +0080 "), Ok("80".to_owned()));
+        assert!(ValueType::Int.normalize("80a").is_err());
+        assert_eq!(ValueType::Float.normalize(" 1.50"), Ok("1.50".to_owned()));
+        assert!(ValueType::Float.normalize("NaN").is_err());
+        assert_eq!(ValueType::Bool.normalize("True"), Ok("true".to_owned()));
+        assert_eq!(ValueType::Bool.normalize("off"), Ok("false".to_owned()));
+        assert_eq!(
+            ValueType::Bool.normalize("maybe"),
+            Err("expected a boolean, got \"maybe\"".to_owned())
+        );
+
+        let mut types = Types::new();
+        parse_type_directive("PORT=int  DEBUG=bool", &mut types).unwrap();
+        assert_eq!(types["DEBUG"], ValueType::Bool);
+        assert!(parse_type_directive("PORT=date", &mut types).is_err());
+        assert_eq!(
+            split_annotated_key("PORT:int"),
+            ("PORT", Some(ValueType::Int))
+        );
+        assert_eq!(split_annotated_key("a:b"), ("a:b", None));
+    }
+}
@@ -0,0 +1,252 @@
+//! Conditional blocks in env files
+//!
+//! ```text
+//! # enw:if os=linux,freebsd
+//! BROWSER=xdg-open
+//! # enw:elif os=macos
+//! BROWSER=open
+//! # enw:else
+//! BROWSER=start
+//! # enw:endif
+//!
+//! # enw:if profile=dev !$CI
+//! LOG_LEVEL=debug
+//! # enw:endif
+//! ```
+//!
+//! A condition is a list of terms that all have to hold: `os=NAME`, `arch=NAME` and
+//! `profile=NAME`, with alternatives separated by commas, or `$VAR` for a variable that is set
+//! and not empty. `!` negates a term. Blocks nest, and the lines of the branches not taken are
+//! skipped, directives included.
+
+use std::env::consts;
+
+use crate::{parse::Lookup, Error};
+
+const IF_DIRECTIVE: &str = "# enw:if";
+const ELIF_DIRECTIVE: &str = "# enw:elif";
+const ELSE_DIRECTIVE: &str = "# enw:else";
+const ENDIF_DIRECTIVE: &str = "# enw:endif";
+
+/// An open `if` block
+#[derive(Debug)]
+struct Block {
+    /// The line the block starts on, for reporting it unclosed
+    line: usize,
+    /// Whether the lines of the block can be taken at all, i.e. the enclosing ones are
+    enclosing_active: bool,
+    /// Whether a branch of the block has been taken
+    taken: bool,
+    /// Whether the current branch is the one taken
+    active: bool,
+    has_else: bool,
+}
+
+/// The nesting of the conditional blocks around the current line of a file
+#[derive(Debug, Default)]
+pub(crate) struct Conditions {
+    blocks: Vec<Block>,
+}
+
+impl Conditions {
+    /// Whether the current line is in the branches taken
+    pub(crate) fn is_active(&self) -> bool {
+        self.blocks.last().is_none_or(|block| block.active)
+    }
+
+    /// Handle `line` if it is a conditional directive, returning whether it was one. The
+    /// conditions of branches that can't be taken aren't evaluated.
+    pub(crate) fn directive(
+        &mut self,
+        line: &str,
+        line_number: usize,
+        profile: Option<&str>,
+        lookup: Lookup,
+    ) -> Result<bool, Error> {
+        let line = line.trim();
+        let evaluate = |condition: &str| evaluate(condition, profile, lookup);
+        if let Some(condition) = argument(line, IF_DIRECTIVE) {
+            let enclosing_active = self.is_active();
+            let active = enclosing_active && evaluate(condition)?;
+            self.blocks.push(Block {
+                line: line_number,
+                enclosing_active,
+                taken: active,
+                active,
+                has_else: false,
+            });
+        } else if let Some(condition) = argument(line, ELIF_DIRECTIVE) {
+            let block = self.open_block("elif")?;
+            if block.has_else {
+                return Err("enw:elif after enw:else".into());
+            }
+            block.active = block.enclosing_active && !block.taken && evaluate(condition)?;
+            block.taken |= block.active;
+        } else if argument(line, ELSE_DIRECTIVE).is_some() {
+            let block = self.open_block("else")?;
+            if block.has_else {
+                return Err("enw:else after enw:else".into());
+            }
+            block.has_else = true;
+            block.active = block.enclosing_active && !block.taken;
+            block.taken = true;
+        } else if argument(line, ENDIF_DIRECTIVE).is_some() {
+            self.open_block("endif")?;
+            self.blocks.pop();
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    fn open_block(&mut self, directive: &str) -> Result<&mut Block, Error> {
+        self.blocks
+            .last_mut()
+            .ok_or_else(|| format!("enw:{directive} without enw:if").into())
+    }
+
+    /// The line starting a block left open, an error at the end of a file
+    pub(crate) fn unclosed(&self) -> Option<usize> {
+        self.blocks.last().map(|block| block.line)
+    }
+}
+
+/// What follows `directive` on `line`, if the line is that directive
+fn argument<'a>(line: &'a str, directive: &str) -> Option<&'a str> {
+    let rest = line.strip_prefix(directive)?;
+    (rest.is_empty() || rest.starts_with(char::is_whitespace)).then_some(rest)
+}
+
+/// Whether all terms of `condition` hold
+pub(crate) fn evaluate(
+    condition: &str,
+    profile: Option<&str>,
+    lookup: Lookup,
+) -> Result<bool, Error> {
+    let mut terms = condition.split_whitespace().peekable();
+    if terms.peek().is_none() {
+        return Err("enw:if without a condition".into());
+    }
+    for term in terms {
+        let (negated, term) = match term.strip_prefix('!') {
+            Some(term) => (true, term),
+            None => (false, term),
+        };
+        let holds = if let Some(var) = term.strip_prefix('$') {
+            lookup(var).is_some_and(|value| !value.is_empty())
+        } else {
+            let (name, alternatives) = term
+                .split_once('=')
+                .ok_or_else(|| format!("expected os=, arch=, profile= or $VAR, got {term}"))?;
+            let actual = match name {
+                "os" => Some(consts::OS),
+                "arch" => Some(consts::ARCH),
+                "profile" => profile,
+                _ => {
+                    return Err(
+                        format!("unknown condition {name}, expected os, arch or profile").into(),
+                    )
+                }
+            };
+            alternatives
+                .split(',')
+                .any(|alternative| Some(alternative) == actual)
+        };
+        if holds == negated {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_vars(_: &str) -> Option<String> {
+        None
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let lookup = |key: &str| match key {
+            "CI" => Some("true".to_owned()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        };
+        let eval = |condition: &str| evaluate(condition, Some("dev"), &lookup).unwrap();
+        assert!(eval(&format!("os={}", consts::OS)));
+        assert!(eval(&format!(
+            "os=plan9,{} arch={}",
+            consts::OS,
+            consts::ARCH
+        )));
+        assert!(!eval("os=plan9"));
+        assert!(eval("!os=plan9"));
+        assert!(eval("profile=dev $CI"));
+        assert!(!eval("profile=prod"));
+        assert!(!eval("$EMPTY"));
+        assert!(eval("!$UNSET !$EMPTY"));
+        assert!(!evaluate("profile=dev", None, &no_vars).unwrap());
+        assert!(evaluate("", None, &no_vars).is_err());
+        assert!(evaluate("color=red", None, &no_vars).is_err());
+        assert!(evaluate("CI", None, &no_vars).is_err());
+    }
+
+    #[test]
+    fn test_nesting() {
+        let lines = [
+            "A=1",
+            "# enw:if os=plan9",
+            "B=1",
+            "# enw:if !os=plan9",
+            "C=1",
+            "# enw:endif",
+            "# enw:elif profile=dev",
+            "D=1",
+            "# enw:if profile=dev",
+            "E=1",
+            "# enw:else",
+            "F=1",
+            "# enw:endif",
+            "# enw:else",
+            "G=1",
+            "# enw:endif",
+        ];
+        let mut conditions = Conditions::default();
+        let mut taken = Vec::new();
+        for (index, line) in lines.into_iter().enumerate() {
+            if !conditions
+                .directive(line, index + 1, Some("dev"), &no_vars)
+                .unwrap()
+                && conditions.is_active()
+            {
+                taken.push(line);
+            }
+        }
+        assert_eq!(conditions.unclosed(), None);
+        assert_eq!(taken, ["A=1", "D=1", "E=1"]);
+
+        let mut conditions = Conditions::default();
+        assert!(conditions
+            .directive("# enw:endif", 1, None, &no_vars)
+            .is_err());
+        assert!(!conditions
+            .directive("# enw:iffy", 1, None, &no_vars)
+            .unwrap());
+        assert!(conditions.directive("# enw:if", 1, None, &no_vars).is_err());
+        conditions
+            .directive("# enw:if $X", 2, None, &no_vars)
+            .unwrap();
+        conditions
+            .directive("# enw:else", 3, None, &no_vars)
+            .unwrap();
+        assert!(conditions
+            .directive("# enw:else", 4, None, &no_vars)
+            .is_err());
+        assert!(conditions
+            .directive("# enw:elif $X", 5, None, &no_vars)
+            .is_err());
+        assert_eq!(conditions.unclosed(), Some(2));
+    }
+}
@@ -0,0 +1,169 @@
+//! The `.enwrc` configuration file
+//!
+//! ```text
+//! # Env files loaded for particular commands, relative to the .enwrc
+//! [commands]
+//! psql = .env.db
+//! cargo = .env .env.build
+//!
+//! # Variables the implicitly loaded env files may not set, or only with a warning
+//! [dangerous]
+//! refuse = LD_* DYLD_* BASH_ENV
+//! warn = PATH PYTHONPATH
+//!
+//! # A secrets service to fetch the environment from with `--remote prod`
+//! [remote.prod]
+//! url = https://secrets.example.com/api
+//! project = backend
+//! environment = production
+//! token-env = SECRETS_TOKEN
+//! ```
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{DangerPolicy, Error};
+
+pub(crate) const CONFIG_FILE_NAME: &str = ".enwrc";
+
+#[derive(Debug, Default)]
+pub(crate) struct Config {
+    /// Env files to load for each command name
+    commands: HashMap<String, Vec<PathBuf>>,
+    /// Patterns replacing the default refused and warned about dangerous variables
+    refuse: Option<Vec<String>>,
+    warn: Option<Vec<String>>,
+    /// The settings of each remote, interpreted by [`crate::remote`]
+    remotes: HashMap<String, HashMap<String, String>>,
+}
+
+impl Config {
+    /// Parse a config file, with relative paths resolved against `dir`
+    pub(crate) fn parse(text: &str, dir: &Path) -> Result<Self, Error> {
+        let mut config = Config::default();
+        let mut section = None;
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: &str| format!("{CONFIG_FILE_NAME}:{}: {message}", index + 1);
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                match name.trim() {
+                    "commands" | "dangerous" => section = Some(name.trim().to_owned()),
+                    name if name.strip_prefix("remote.").is_some_and(|r| !r.is_empty()) => {
+                        section = Some(name.to_owned())
+                    }
+                    name => return Err(error(&format!("unknown section [{name}]")).into()),
+                }
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .map(|(key, value)| (key.trim(), value.trim()))
+                .filter(|(key, _)| !key.is_empty())
+                .ok_or_else(|| error("expected NAME = VALUE"))?;
+            match section.as_deref() {
+                Some("commands") => {
+                    let files = value.split_whitespace().map(|file| dir.join(file));
+                    config.commands.insert(key.to_owned(), files.collect());
+                }
+                Some("dangerous") => {
+                    let patterns = Some(value.split_whitespace().map(str::to_owned).collect());
+                    match key {
+                        "refuse" => config.refuse = patterns,
+                        "warn" => config.warn = patterns,
+                        key => return Err(error(&format!("unknown setting {key}")).into()),
+                    }
+                }
+                Some(section) if section.starts_with("remote.") => {
+                    let remote = section.trim_start_matches("remote.").to_owned();
+                    let settings = config.remotes.entry(remote).or_default();
+                    settings.insert(key.to_owned(), value.to_owned());
+                }
+                _ => return Err(error("setting outside of a section").into()),
+            }
+        }
+        Ok(config)
+    }
+
+    /// The config file in `dir`, or an empty config if there is none
+    pub(crate) fn load(dir: &Path) -> Result<Self, Error> {
+        let path = dir.join(CONFIG_FILE_NAME);
+        match fs::read_to_string(&path) {
+            Ok(text) => Config::parse(&text, dir),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(err) => Err(format!("{}: {err}", path.to_string_lossy()).into()),
+        }
+    }
+
+    /// The env files bound to `command`, looked up by its file name so that `/usr/bin/psql`
+    /// and `psql` are the same
+    pub(crate) fn files_for(&self, command: &str) -> &[PathBuf] {
+        Path::new(command)
+            .file_name()
+            .and_then(|name| self.commands.get(name.to_str()?))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// The settings of the `[remote.NAME]` section
+    pub(crate) fn remote(&self, name: &str) -> Option<&HashMap<String, String>> {
+        self.remotes.get(name)
+    }
+
+    /// The default [`DangerPolicy`], with the patterns given in the `[dangerous]` section
+    pub(crate) fn danger_policy(&self) -> DangerPolicy {
+        let mut policy = DangerPolicy::default();
+        if let Some(refuse) = &self.refuse {
+            policy = policy.with_refused(refuse.clone());
+        }
+        if let Some(warn) = &self.warn {
+            policy = policy.with_warned(warn.clone());
+        }
+        policy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let text = "# comment\n[commands]\npsql = .env.db\ncargo = .env  .env.build\n";
+        let config = Config::parse(text, Path::new("/project")).unwrap();
+        assert_eq!(
+            config.files_for("/usr/bin/psql"),
+            [PathBuf::from("/project/.env.db")]
+        );
+        assert_eq!(
+            config.files_for("cargo"),
+            [
+                PathBuf::from("/project/.env"),
+                PathBuf::from("/project/.env.build")
+            ]
+        );
+        assert!(config.files_for("ls").is_empty());
+        assert!(Config::parse("psql = .env.db", Path::new("/")).is_err());
+        assert!(Config::parse("[other]", Path::new("/")).is_err());
+        assert!(Config::parse("[commands]\npsql", Path::new("/")).is_err());
+        assert!(Config::parse("[dangerous]\nblock = PATH", Path::new("/")).is_err());
+        let config = Config::parse("[remote.prod]\nurl = https://x\n", Path::new("/")).unwrap();
+        assert_eq!(config.remote("prod").unwrap()["url"], "https://x");
+        assert!(config.remote("staging").is_none());
+        assert!(Config::parse("[remote.]", Path::new("/")).is_err());
+    }
+
+    #[test]
+    fn test_danger_policy() {
+        let config = Config::parse("[dangerous]\nwarn = PATH *_PATH\n", Path::new("/")).unwrap();
+        assert_eq!(
+            config.danger_policy(),
+            DangerPolicy::default().with_warned(vec!["PATH".to_owned(), "*_PATH".to_owned()])
+        );
+        assert_eq!(Config::default().danger_policy(), DangerPolicy::default());
+    }
+}
@@ -0,0 +1,187 @@
+//! Running COMMAND in the background with `--daemon`, and stopping it with `enw stop`
+//!
+//! enw forks twice so that COMMAND is neither a session leader nor a child of the shell, and
+//! can't get a controlling terminal again. The process left runs in a process group of its own,
+//! whose id is written to the pid file, so that `enw stop` also reaches the children of
+//! COMMAND, such as the one supervised with `--log-file`.
+
+use std::path::Path;
+#[cfg(unix)]
+use std::{
+    fs::{self, OpenOptions},
+    io,
+    os::fd::AsRawFd,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::Error;
+#[cfg(unix)]
+use crate::{edit, report_error, EXIT_ERROR};
+
+/// How long `enw stop` waits for the processes to exit after signalling them
+#[cfg(unix)]
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Detach from the terminal into the background, writing the id of the process that goes on to
+/// `pid_file`. Only returns in that process: the invoking one exits once the pid file has been
+/// written, successfully unless that failed.
+#[cfg(unix)]
+pub(crate) fn daemonize(pid_file: &Path) -> Result<(), Error> {
+    if let Some(pid) = running_pid(pid_file)? {
+        return Err(Error::Other(format!(
+            "already running with pid {pid}, according to {}",
+            pid_file.to_string_lossy()
+        )));
+    }
+    // Opened before forking so that failing is reported
+    let null = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")
+        .map_err(|err| Error::io("/dev/null", err))?;
+    match fork()? {
+        0 => (),
+        child => {
+            let mut status = 0;
+            if unsafe { libc::waitpid(child, &mut status, 0) } == -1 {
+                return Err(io::Error::last_os_error().into());
+            }
+            let succeeded = libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0;
+            std::process::exit(if succeeded { 0 } else { EXIT_ERROR });
+        }
+    }
+    // The intermediate process only starts a new session and writes the pid file
+    let intermediate = || -> Result<(), Error> {
+        if unsafe { libc::setsid() } == -1 {
+            return Err(io::Error::last_os_error().into());
+        }
+        match fork()? {
+            0 => Ok(()),
+            child => {
+                unsafe { libc::setpgid(child, child) };
+                if let Err(err) = edit::write_atomically(pid_file, format!("{child}\n").as_bytes())
+                {
+                    unsafe { libc::kill(child, libc::SIGKILL) };
+                    return Err(Error::io(pid_file, err));
+                }
+                unsafe { libc::_exit(0) }
+            }
+        }
+    };
+    if let Err(err) = intermediate() {
+        report_error(&err);
+        unsafe { libc::_exit(EXIT_ERROR) }
+    }
+    unsafe { libc::setpgid(0, 0) };
+    for fd in [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+        if unsafe { libc::dup2(null.as_raw_fd(), fd) } == -1 {
+            return Err(io::Error::last_os_error().into());
+        }
+    }
+    Ok(())
+}
+
+/// Stop the processes started with `--daemon` that wrote `pid_file`, and remove it
+#[cfg(unix)]
+pub(crate) fn stop(pid_file: &Path) -> Result<(), Error> {
+    let Some(pid) = read_pid(pid_file)? else {
+        return Err(Error::Other(format!(
+            "{} does not exist, nothing to stop",
+            pid_file.to_string_lossy()
+        )));
+    };
+    if !is_running(pid) {
+        fs::remove_file(pid_file).map_err(|err| Error::io(pid_file, err))?;
+        return Err(Error::Other(format!(
+            "no process with pid {pid} is running, removed {}",
+            pid_file.to_string_lossy()
+        )));
+    }
+    // The whole process group, unless the process has left it
+    if unsafe { libc::kill(-pid, libc::SIGTERM) } == -1 {
+        unsafe { libc::kill(pid, libc::SIGTERM) };
+    }
+    let started = Instant::now();
+    while is_running(pid) || is_running(-pid) {
+        if started.elapsed() > STOP_TIMEOUT {
+            return Err(Error::Other(format!(
+                "pid {pid} did not stop within {} seconds",
+                STOP_TIMEOUT.as_secs()
+            )));
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    fs::remove_file(pid_file).map_err(|err| Error::io(pid_file, err))?;
+    Ok(())
+}
+
+/// The pid in `pid_file` if that process is still running
+#[cfg(unix)]
+fn running_pid(pid_file: &Path) -> Result<Option<libc::pid_t>, Error> {
+    Ok(read_pid(pid_file)?.filter(|&pid| is_running(pid)))
+}
+
+#[cfg(unix)]
+fn read_pid(pid_file: &Path) -> Result<Option<libc::pid_t>, Error> {
+    let text = match fs::read_to_string(pid_file) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        text => text.map_err(|err| Error::io(pid_file, err))?,
+    };
+    let pid = text
+        .trim()
+        .parse()
+        .ok()
+        .filter(|&pid: &libc::pid_t| pid > 0)
+        .ok_or_else(|| Error::Other(format!("{}: invalid pid", pid_file.to_string_lossy())))?;
+    Ok(Some(pid))
+}
+
+/// Whether the process `pid` exists, or the process group `-pid`
+#[cfg(unix)]
+fn is_running(pid: libc::pid_t) -> bool {
+    let signalled = unsafe { libc::kill(pid, 0) } == 0;
+    signalled || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(unix)]
+fn fork() -> io::Result<libc::pid_t> {
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        pid => Ok(pid),
+    }
+}
+
+/// Without Unix, there is no detaching from the terminal
+#[cfg(not(unix))]
+pub(crate) fn daemonize(_: &Path) -> Result<(), Error> {
+    Err(Error::Usage(
+        "--daemon is only supported on Unix".to_owned(),
+    ))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn stop(_: &Path) -> Result<(), Error> {
+    Err(Error::Usage(
+        "enw stop is only supported on Unix".to_owned(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_pid() {
+        let path = std::env::temp_dir().join(format!("enw-pid-{}", std::process::id()));
+        assert_eq!(running_pid(&path).unwrap(), None);
+        fs::write(&path, format!("{}\n", std::process::id())).unwrap();
+        assert_eq!(
+            running_pid(&path).unwrap(),
+            Some(std::process::id() as libc::pid_t)
+        );
+        fs::write(&path, "-1\n").unwrap();
+        assert!(read_pid(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,147 @@
+//! In-place editing of env files
+
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::Path,
+    process,
+};
+
+use crate::{
+    lint::{split_assignment, split_comment},
+    parse::{is_continued, ParseOptions},
+    serialize::format_dotenv,
+    Error,
+};
+
+/// Set each variable in the document, replacing the value of its last definition or appending
+/// it. Comments, ordering and the quoting style of the replaced values are kept, as are `\r\n`
+/// line endings and a byte order mark.
+pub(crate) fn set_vars(text: &str, vars: &[(String, String)]) -> Result<String, Error> {
+    let (bom, text) = match text.strip_prefix('\u{feff}') {
+        Some(rest) => ("\u{feff}", rest),
+        None => ("", text),
+    };
+    let line_ending = if text.contains("\r\n") { "\r\n" } else { "\n" };
+    let mut lines: Vec<String> = text.lines().map(str::to_owned).collect();
+    for (key, value) in vars {
+        let found = lines
+            .iter()
+            .rposition(|line| split_assignment(line).is_some_and(|(k, _)| k == key));
+        match found {
+            Some(index) => {
+                // The lines the old value is continued on are replaced as well
+                let mut end = index;
+                while end + 1 < lines.len()
+                    && is_continued(&lines[index..=end].join("\n"), &ParseOptions::default())
+                {
+                    end += 1;
+                }
+                lines.drain(index + 1..=end);
+                let line = &lines[index];
+                let (_, old_value) = split_assignment(line).unwrap_or_default();
+                let (_, comment) = split_comment(old_value);
+                let indent = &line[..line.len() - line.trim_start().len()];
+                let mut new_line = format!("{indent}{}", format_like(key, value, old_value)?);
+                if !comment.is_empty() {
+                    new_line.push(' ');
+                    new_line.push_str(comment);
+                }
+                lines[index] = new_line;
+            }
+            None => lines.push(format_dotenv(key, value)?),
+        }
+    }
+    let mut doc = bom.to_owned();
+    doc.push_str(&lines.join(line_ending));
+    doc.push_str(line_ending);
+    Ok(doc)
+}
+
+/// Format the variable as a dotenv line, quoted the same way as `old_value` where possible
+fn format_like(key: &str, value: &str, old_value: &str) -> Result<String, Error> {
+    // Fails on values that can't be represented on one line
+    let line = format_dotenv(key, value)?;
+    if old_value.starts_with('"') && !value.contains('$') {
+        let escaped = value.replace('\\', r"\\").replace('"', "\\\"");
+        Ok(format!("{key}=\"{escaped}\""))
+    } else if old_value.starts_with('\'') {
+        let escaped = value.replace('\\', r"\\").replace('\'', r"\'");
+        Ok(format!("{key}='{escaped}'"))
+    } else {
+        Ok(line)
+    }
+}
+
+/// Replace the file at `path` by writing to a temporary file next to it and renaming that over
+/// it, so that readers see either the old or the new contents. The permissions of an existing
+/// file are kept.
+pub(crate) fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let file_name = path.file_name().ok_or(io::ErrorKind::InvalidInput)?;
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(format!(".enw-{}.tmp", process::id()));
+    let temp_path = path.with_file_name(temp_name);
+    let result = (|| {
+        let mut file = File::create(&temp_path)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+        if let Ok(metadata) = fs::metadata(path) {
+            fs::set_permissions(&temp_path, metadata.permissions())?;
+        }
+        fs::rename(&temp_path, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn test_set_vars() {
+        let doc = "# comment\nA=1\n  B=\"old\" # keep\nC='x'\nA=2\n";
+        let vars = vec![
+            owned("A", "new"),
+            owned("B", "say \"hi\""),
+            owned("C", "it's"),
+            owned("D", "a b"),
+        ];
+        assert_eq!(
+            set_vars(doc, &vars).unwrap(),
+            "# comment\nA=1\n  B=\"say \\\"hi\\\"\" # keep\nC='it\\'s'\nA=new\nD='a b'\n"
+        );
+        assert_eq!(set_vars("", &[owned("A", "1")]).unwrap(), "A=1\n");
+        assert!(set_vars("", &[owned("A", "a\nb")]).is_err());
+        assert_eq!(
+            set_vars(
+                "\u{feff}A=1\r\nB=2\r\n",
+                &[owned("B", "3"), owned("C", "4")]
+            )
+            .unwrap(),
+            "\u{feff}A=1\r\nB=3\r\nC=4\r\n"
+        );
+        assert_eq!(
+            set_vars("A=one \\\n  two\nB=2\n", &[owned("A", "1")]).unwrap(),
+            "A=1\nB=2\n"
+        );
+    }
+
+    #[test]
+    fn test_write_atomically() {
+        let path = std::env::temp_dir().join(format!("enw-edit-{}.env", process::id()));
+        write_atomically(&path, b"A=1\n").unwrap();
+        write_atomically(&path, b"A=2\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "A=2\n");
+        fs::remove_file(&path).unwrap();
+    }
+
+    fn owned(k: &str, v: &str) -> (String, String) {
+        (k.into(), v.into())
+    }
+}
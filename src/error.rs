@@ -0,0 +1,164 @@
+//! The error type of enw
+
+use std::{fmt, io, path::PathBuf};
+
+use crate::{EXIT_CANNOT_INVOKE, EXIT_ERROR, EXIT_NOT_FOUND, EXIT_NOT_SET};
+
+/// An error of enw, or of starting COMMAND
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Reading or writing a file, or standard input or output, failed
+    Io {
+        path: Option<PathBuf>,
+        source: io::Error,
+    },
+    /// A line of an env file could not be parsed
+    Parse {
+        /// The path of the file, or `<stdin>`
+        file: String,
+        /// 1-based number of the line the entry starts on
+        line: usize,
+        message: String,
+    },
+    /// The options given don't go together, e.g. a Kubernetes format without `--name`
+    Usage(String),
+    /// An env file would be loaded automatically from a directory not allowed with `enw allow`
+    NotAllowed(PathBuf),
+    /// An env file loaded automatically has changed since its directory was allowed
+    Modified(PathBuf),
+    /// `enw run` was given no COMMAND
+    MissingCommand,
+    /// COMMAND could not be found
+    CommandNotFound { command: String, source: io::Error },
+    /// COMMAND was found but could not be executed
+    CommandNotExecutable { command: String, source: io::Error },
+    /// The variables looked up are not set in the resolved environment
+    NotSet(Vec<String>),
+    /// Anything else, such as problems found by `enw check`
+    Other(String),
+}
+
+impl Error {
+    /// The exit status for the error: 127 if COMMAND was not found, 126 if it could not be
+    /// executed, 1 if a variable looked up is not set, and 125 for errors of enw itself, as
+    /// with env(1)
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::NotSet(_) => EXIT_NOT_SET,
+            Error::CommandNotFound { .. } => EXIT_NOT_FOUND,
+            Error::CommandNotExecutable { .. } => EXIT_CANNOT_INVOKE,
+            _ => EXIT_ERROR,
+        }
+    }
+
+    pub(crate) fn io(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        Error::Io {
+            path: Some(path.into()),
+            source,
+        }
+    }
+
+    /// The error of executing `command` failing with `source`
+    pub(crate) fn exec(command: String, source: io::Error) -> Self {
+        if source.kind() == io::ErrorKind::NotFound {
+            Error::CommandNotFound { command, source }
+        } else {
+            Error::CommandNotExecutable { command, source }
+        }
+    }
+
+    /// Attribute the error to the source `name`, unless it already names its origin
+    pub(crate) fn in_source(self, name: &str) -> Self {
+        match self {
+            Error::Io { path: None, source } => Error::io(name, source),
+            Error::Other(message) => Error::Other(format!("{name}: {message}")),
+            err => err,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io {
+                path: Some(path),
+                source,
+            } => write!(f, "{}: {source}", path.to_string_lossy()),
+            Error::Io { path: None, source } => write!(f, "{source}"),
+            Error::Parse {
+                file,
+                line,
+                message,
+            } => write!(f, "{file}:{line}: {message}"),
+            Error::Usage(message) | Error::Other(message) => f.write_str(message),
+            Error::NotAllowed(file) => write!(
+                f,
+                "{} is not allowed to be loaded automatically, run `enw allow` in its directory \
+                 to allow it, or use -n to skip it",
+                file.to_string_lossy()
+            ),
+            Error::Modified(file) => write!(
+                f,
+                "{} has changed since it was allowed, review it and run `enw allow` in its \
+                 directory to allow it again, or use -n to skip it",
+                file.to_string_lossy()
+            ),
+            Error::MissingCommand => f.write_str("run: missing COMMAND"),
+            Error::NotSet(keys) => match keys.as_slice() {
+                [key] => write!(f, "{key} is not set"),
+                keys => write!(f, "{} are not set", keys.join(", ")),
+            },
+            Error::CommandNotFound { command, source }
+            | Error::CommandNotExecutable { command, source } => write!(f, "{command}: {source}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. }
+            | Error::CommandNotFound { source, .. }
+            | Error::CommandNotExecutable { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(source: io::Error) -> Self {
+        Error::Io { path: None, source }
+    }
+}
+
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Error::Other(message)
+    }
+}
+
+impl From<&str> for Error {
+    fn from(message: &str) -> Self {
+        Error::Other(message.to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_code() {
+        let not_found = io::Error::from(io::ErrorKind::NotFound);
+        let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+        assert_eq!(Error::exec("x".into(), not_found).exit_code(), 127);
+        assert_eq!(Error::exec("x".into(), denied).exit_code(), 126);
+        assert_eq!(Error::MissingCommand.exit_code(), 125);
+        assert_eq!(Error::NotSet(vec!["A".into()]).exit_code(), 1);
+        assert_eq!(
+            Error::from("message").in_source("a.env").to_string(),
+            "a.env: message"
+        );
+    }
+}
@@ -0,0 +1,70 @@
+//! Env files with machine-wide defaults, such as `EDITOR` or proxy settings
+//!
+//! `/etc/enw/global.env` and then `$XDG_CONFIG_HOME/enw/global.env`, by default in
+//! `~/.config`, are loaded below all other env files unless `--no-global` is given.
+
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+
+use crate::log::log;
+
+pub(crate) const GLOBAL_ENV_FILE_NAME: &str = "global.env";
+const SYSTEM_CONFIG_DIR: &str = "/etc/enw";
+
+/// `$XDG_CONFIG_HOME/enw`, by default in `~/.config`
+fn user_config_dir() -> Option<PathBuf> {
+    let config_home = match env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => Path::new(&env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_home.join("enw"))
+}
+
+/// The global env files that exist, the system-wide one first as it has the lowest precedence
+pub(crate) fn global_env_files() -> Vec<PathBuf> {
+    existing_files([Some(PathBuf::from(SYSTEM_CONFIG_DIR)), user_config_dir()])
+}
+
+fn existing_files(dirs: impl IntoIterator<Item = Option<PathBuf>>) -> Vec<PathBuf> {
+    dirs.into_iter()
+        .flatten()
+        .map(|dir| dir.join(GLOBAL_ENV_FILE_NAME))
+        .filter(|path| {
+            let exists = path.is_file();
+            if !exists {
+                log!(Debug, "no global env file {}", path.to_string_lossy());
+            }
+            exists
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_existing_files() {
+        let dir = env::temp_dir().join(format!("enw-global-{}", std::process::id()));
+        let (system, user) = (dir.join("system"), dir.join("user"));
+        fs::create_dir_all(&system).unwrap();
+        fs::create_dir_all(&user).unwrap();
+        fs::write(user.join(GLOBAL_ENV_FILE_NAME), "EDITOR=vi\n").unwrap();
+        let user_only = existing_files([Some(system.clone()), Some(user.clone()), None]);
+        fs::write(system.join(GLOBAL_ENV_FILE_NAME), "EDITOR=ed\n").unwrap();
+        let both = existing_files([Some(system.clone()), Some(user.clone())]);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(user_only, [user.join(GLOBAL_ENV_FILE_NAME)]);
+        assert_eq!(
+            both,
+            [
+                system.join(GLOBAL_ENV_FILE_NAME),
+                user.join(GLOBAL_ENV_FILE_NAME)
+            ]
+        );
+    }
+}
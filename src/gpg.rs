@@ -0,0 +1,216 @@
+//! Env files encrypted with GnuPG, such as `.env.gpg`
+//!
+//! Files ending in `.gpg` are decrypted with `gpg --decrypt` before being parsed. The plaintext
+//! is only kept in memory: it is neither written to disk nor cached with `--cache`. An implicit
+//! `.env.gpg` is loaded when there is no `.env` file.
+
+use std::{
+    env,
+    ffi::OsString,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::{self, Command, Output, Stdio},
+    thread,
+};
+
+use crate::{
+    edit::write_atomically,
+    log::log,
+    os::{bytes_to_string, private_dir_builder, private_open_options},
+    Error,
+};
+
+/// The extension of encrypted env files
+pub(crate) const ENCRYPTED_EXTENSION: &str = "gpg";
+const GPG_PROGRAM: &str = "gpg";
+/// The prefix of the machine-readable lines that gpg prints with `--status-fd`
+const STATUS_PREFIX: &str = "[GNUPG:] ";
+
+/// Whether `path` is an encrypted env file
+pub(crate) fn is_encrypted(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext == ENCRYPTED_EXTENSION)
+}
+
+/// The encrypted counterpart of `path`, e.g. `.env.gpg` for `.env`
+pub(crate) fn encrypted_path(path: &Path) -> PathBuf {
+    let mut encrypted = OsString::from(path);
+    encrypted.push(".");
+    encrypted.push(ENCRYPTED_EXTENSION);
+    PathBuf::from(encrypted)
+}
+
+/// The decrypted contents of `path`. The passphrase, if needed, is asked for by gpg itself.
+pub(crate) fn decrypt(path: &Path) -> Result<Vec<u8>, Error> {
+    decrypt_with_recipients(path).map(|(plaintext, _)| plaintext)
+}
+
+/// The decrypted contents of `path`, and the ids of the keys it was encrypted to, which are
+/// none if it was encrypted with a passphrase
+pub(crate) fn decrypt_with_recipients(path: &Path) -> Result<(Vec<u8>, Vec<String>), Error> {
+    log!(Info, "decrypting {}", path.to_string_lossy());
+    let mut command = Command::new(GPG_PROGRAM);
+    command
+        .args(["--quiet", "--status-fd", "2", "--decrypt", "--"])
+        .arg(path);
+    let output = run(command, None, path, "decryption")?;
+    Ok((output.stdout, recipients(&bytes_to_string(&output.stderr))))
+}
+
+/// Encrypt `plaintext` into `path`, to the keys of `recipients`, or with a passphrase asked for
+/// by gpg if there are none
+pub(crate) fn encrypt(plaintext: &[u8], recipients: &[String], path: &Path) -> Result<(), Error> {
+    log!(Info, "encrypting {}", path.to_string_lossy());
+    let mut command = Command::new(GPG_PROGRAM);
+    command.arg("--quiet");
+    match recipients.is_empty() {
+        true => command.arg("--symmetric"),
+        false => command
+            .arg("--encrypt")
+            .args(recipients.iter().flat_map(|id| ["--recipient", id])),
+    };
+    let output = run(command, Some(plaintext), path, "encryption")?;
+    write_atomically(path, &output.stdout).map_err(|err| Error::io(path, err))
+}
+
+/// Run gpg with `input` on its stdin, or else the stdin of enw, and collect its output
+fn run(
+    mut command: Command,
+    input: Option<&[u8]>,
+    path: &Path,
+    operation: &str,
+) -> Result<Output, Error> {
+    let mut child = command
+        .stdin(match input {
+            Some(_) => Stdio::piped(),
+            None => Stdio::inherit(),
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::NotFound => Error::Other(format!(
+                "{}: `{GPG_PROGRAM}` is needed for its {operation}",
+                path.to_string_lossy()
+            )),
+            _ => Error::Other(format!("{GPG_PROGRAM}: {err}")),
+        })?;
+    let output = thread::scope(|scope| {
+        // Written while the output is read, so that neither pipe fills up
+        if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+            scope.spawn(move || stdin.write_all(input));
+        }
+        child.wait_with_output()
+    })
+    .map_err(|err| Error::Other(format!("{GPG_PROGRAM}: {err}")))?;
+    if !output.status.success() {
+        let stderr = bytes_to_string(&output.stderr);
+        let messages: Vec<&str> = stderr
+            .lines()
+            .filter(|line| !line.starts_with(STATUS_PREFIX))
+            .collect();
+        return Err(Error::Other(format!(
+            "{}: {operation} failed: {}",
+            path.to_string_lossy(),
+            messages.join("\n").trim()
+        )));
+    }
+    Ok(output)
+}
+
+/// The key ids of the `ENC_TO` lines of the status output of gpg
+fn recipients(status: &str) -> Vec<String> {
+    status
+        .lines()
+        .filter_map(|line| line.strip_prefix(STATUS_PREFIX)?.strip_prefix("ENC_TO "))
+        .filter_map(|rest| rest.split_whitespace().next())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Decrypt `path` into a private temporary file, open it with `editor` and encrypt it back to
+/// the same recipients if it was changed. Returns whether it was.
+pub(crate) fn edit(path: &Path, editor: &str) -> Result<bool, Error> {
+    let (plaintext, recipients) = decrypt_with_recipients(path)?;
+    let dir = env::temp_dir().join(format!("enw-edit-{}", process::id()));
+    let name = decrypted_path(path);
+    let temp_path = dir.join(name.file_name().unwrap_or(name.as_os_str()));
+    let result = (|| {
+        private_dir_builder()
+            .create(&dir)
+            .map_err(|err| Error::io(&dir, err))?;
+        write_private(&temp_path, &plaintext)?;
+        // The editor may come with arguments of its own, as in `code --wait`
+        let status = Command::new("sh")
+            .args(["-c", &format!("{editor} \"$1\""), "sh"])
+            .arg(&temp_path)
+            .status()
+            .map_err(|err| Error::Other(format!("{editor}: {err}")))?;
+        if !status.success() {
+            return Err(Error::Other(format!(
+                "{editor} failed, {} is left unchanged",
+                path.to_string_lossy()
+            )));
+        }
+        let edited = fs::read(&temp_path).map_err(|err| Error::io(&temp_path, err))?;
+        if edited == plaintext {
+            return Ok(false);
+        }
+        encrypt(&edited, &recipients, path)?;
+        Ok(true)
+    })();
+    let _ = fs::remove_file(&temp_path);
+    let _ = fs::remove_dir(&dir);
+    result
+}
+
+/// The plaintext counterpart of `path`, e.g. `.env` for `.env.gpg`
+pub(crate) fn decrypted_path(path: &Path) -> PathBuf {
+    match is_encrypted(path) {
+        true => path.with_extension(""),
+        false => path.to_owned(),
+    }
+}
+
+/// Write `contents` to a new file at `path` that only the user can read
+pub(crate) fn write_private(path: &Path, contents: &[u8]) -> Result<(), Error> {
+    private_open_options()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(contents))
+        .map_err(|err| Error::io(path, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypted_path() {
+        assert!(is_encrypted(Path::new("dir/.env.gpg")));
+        assert!(is_encrypted(Path::new("prod.env.gpg")));
+        assert!(!is_encrypted(Path::new(".env")));
+        assert!(!is_encrypted(Path::new("gpg")));
+        assert_eq!(
+            encrypted_path(Path::new("dir/.env")),
+            Path::new("dir/.env.gpg")
+        );
+        assert_eq!(
+            decrypted_path(Path::new("dir/.env.gpg")),
+            Path::new("dir/.env")
+        );
+        assert_eq!(decrypted_path(Path::new(".env")), Path::new(".env"));
+    }
+
+    #[test]
+    fn test_recipients() {
+        let status = "[GNUPG:] ENC_TO 9722513F9A2C7B7A 1 0\n\
+                      gpg: encrypted with rsa3072 key\n\
+                      [GNUPG:] ENC_TO 0123456789ABCDEF 1 0\n\
+                      [GNUPG:] DECRYPTION_OKAY\n";
+        assert_eq!(recipients(status), ["9722513F9A2C7B7A", "0123456789ABCDEF"]);
+        assert!(recipients("[GNUPG:] NEED_PASSPHRASE_SYM 9 3 2\n").is_empty());
+    }
+}
@@ -0,0 +1,264 @@
+//! `enw graph`, the references between the variables of env files
+//!
+//! References are found in the text of the values, whether or not expansion is enabled, so that
+//! files can be untangled before they are loaded. A reference inside `${A:-$B}` counts both `A`
+//! and `B`, as either may be used, while only `B` has to be defined.
+
+use std::{cell::Cell, collections::HashSet, fs, io, path::Path};
+
+use indexmap::IndexMap;
+
+use crate::{
+    lint::{split_assignment, split_comment},
+    parse::Lookup,
+    source::doc_lines,
+    Error,
+};
+
+/// The variables referenced by each variable, in the order they are defined
+#[derive(Debug, Default, Eq, PartialEq)]
+pub(crate) struct Graph {
+    edges: IndexMap<String, Vec<Reference>>,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) struct Reference {
+    name: String,
+    /// Referenced with a default, alternative or error message, as `${NAME:-default}`, so that
+    /// it may be left undefined
+    guarded: bool,
+}
+
+impl Graph {
+    /// The graph of the files at `paths`, where later definitions replace earlier ones
+    pub(crate) fn load(paths: &[&Path]) -> Result<Self, Error> {
+        let mut graph = Graph::default();
+        for path in paths {
+            let text = fs::read(path).map_err(|err| Error::io(*path, err))?;
+            graph.add_doc(io::Cursor::new(text))?;
+        }
+        Ok(graph)
+    }
+
+    fn add_doc(&mut self, reader: impl io::BufRead) -> Result<(), Error> {
+        let dialect = Cell::new(Default::default());
+        for entry in doc_lines(reader, &dialect) {
+            let (_, line) = entry?;
+            let Some((key, value)) = split_assignment(&line) else {
+                continue;
+            };
+            let key = key.strip_prefix("export ").unwrap_or(key).trim();
+            let (value, _) = split_comment(value);
+            self.edges.insert(key.to_owned(), references(value));
+        }
+        Ok(())
+    }
+
+    /// The referenced variables that aren't defined, neither by the files nor by `lookup`, with
+    /// the first variable referencing each
+    pub(crate) fn undefined(&self, lookup: Lookup) -> Vec<(&str, &str)> {
+        let mut undefined: Vec<(&str, &str)> = Vec::new();
+        for (key, refs) in &self.edges {
+            for Reference { name, guarded } in refs {
+                if !guarded
+                    && !self.edges.contains_key(name)
+                    && lookup(name).is_none()
+                    && !undefined.iter().any(|(undefined, _)| undefined == name)
+                {
+                    undefined.push((name, key));
+                }
+            }
+        }
+        undefined
+    }
+
+    /// The cycles of references, each as the variables in it starting with the one defined
+    /// first, and the same again at the end
+    pub(crate) fn cycles(&self) -> Vec<Vec<&str>> {
+        let mut cycles = Vec::new();
+        let mut done: HashSet<&str> = HashSet::new();
+        for key in self.edges.keys() {
+            let mut path = Vec::new();
+            self.find_cycles(key, &mut path, &mut done, &mut cycles);
+        }
+        cycles
+    }
+
+    fn find_cycles<'a>(
+        &'a self,
+        key: &'a str,
+        path: &mut Vec<&'a str>,
+        done: &mut HashSet<&'a str>,
+        cycles: &mut Vec<Vec<&'a str>>,
+    ) {
+        if let Some(start) = path.iter().position(|&k| k == key) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(key);
+            cycles.push(cycle);
+            return;
+        }
+        if done.contains(key) {
+            return;
+        }
+        path.push(key);
+        for reference in self.edges.get(key).into_iter().flatten() {
+            self.find_cycles(&reference.name, path, done, cycles);
+        }
+        path.pop();
+        done.insert(key);
+    }
+
+    /// `KEY -> REF, REF` lines, and a line of its own for each variable without references
+    pub(crate) fn format_text(&self) -> String {
+        let mut text = String::new();
+        for (key, refs) in &self.edges {
+            let names: Vec<&str> = refs.iter().map(|r| r.name.as_str()).collect();
+            match names.is_empty() {
+                true => text.push_str(&format!("{key}\n")),
+                false => text.push_str(&format!("{key} -> {}\n", names.join(", "))),
+            }
+        }
+        text
+    }
+
+    /// The graph in the DOT language of graphviz, with undefined variables dashed
+    pub(crate) fn format_dot(&self, lookup: Lookup) -> String {
+        let mut dot = "digraph env {\n".to_owned();
+        for key in self.edges.keys() {
+            dot.push_str(&format!("  {};\n", dot_id(key)));
+        }
+        for (name, _) in self.undefined(lookup) {
+            dot.push_str(&format!("  {} [style=dashed];\n", dot_id(name)));
+        }
+        for (key, refs) in &self.edges {
+            for reference in refs {
+                let (from, to) = (dot_id(key), dot_id(&reference.name));
+                dot.push_str(&format!("  {from} -> {to};\n"));
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+fn dot_id(name: &str) -> String {
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// The variables referenced with `$NAME` and `${NAME...}` in a raw value, in order and without
+/// repetitions. Nothing is referenced in single quotes or after a backslash.
+pub(crate) fn references(value: &str) -> Vec<Reference> {
+    let mut refs = Vec::new();
+    collect_references(value, &mut refs);
+    refs
+}
+
+fn collect_references(value: &str, refs: &mut Vec<Reference>) {
+    let push = |refs: &mut Vec<Reference>, name: &str, guarded: bool| {
+        match refs.iter_mut().find(|r| r.name == name) {
+            // Guarded only if it always is
+            Some(reference) => reference.guarded &= guarded,
+            None if !name.is_empty() => refs.push(Reference {
+                name: name.to_owned(),
+                guarded,
+            }),
+            None => (),
+        }
+    };
+    let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut in_double_quotes = false;
+    let mut chars = value.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\'' if !in_double_quotes => {
+                for (_, c) in chars.by_ref() {
+                    if c == '\'' {
+                        break;
+                    }
+                }
+            }
+            '"' => in_double_quotes = !in_double_quotes,
+            '\\' => {
+                chars.next();
+            }
+            '$' => match chars.peek() {
+                Some((_, '{')) => {
+                    let start = i + 2;
+                    let mut depth = 0;
+                    let mut end = value.len();
+                    for (j, c) in chars.by_ref() {
+                        match c {
+                            '{' => depth += 1,
+                            '}' if depth == 1 => {
+                                end = j;
+                                break;
+                            }
+                            '}' => depth -= 1,
+                            _ => (),
+                        }
+                    }
+                    let expr = &value[start.min(end)..end];
+                    let name_len = expr.find(|c| !is_name_char(c)).unwrap_or(expr.len());
+                    push(refs, &expr[..name_len], name_len < expr.len());
+                    // The word after the operator, as in `${A:-$B}`
+                    collect_references(&expr[name_len..], refs);
+                }
+                Some(&(_, c)) if c.is_ascii_alphabetic() || c == '_' => {
+                    let start = i + 1;
+                    let mut end = value.len();
+                    while let Some(&(j, c)) = chars.peek() {
+                        if !is_name_char(c) {
+                            end = j;
+                            break;
+                        }
+                        chars.next();
+                    }
+                    push(refs, &value[start..end], false);
+                }
+                _ => (),
+            },
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(doc: &str) -> Graph {
+        let mut graph = Graph::default();
+        graph.add_doc(io::Cursor::new(doc)).unwrap();
+        graph
+    }
+
+    #[test]
+    fn test_references() {
+        let names = |value: &str| -> Vec<String> {
+            references(value).into_iter().map(|r| r.name).collect()
+        };
+        assert_eq!(names("$A ${B} x$A"), ["A", "B"]);
+        assert_eq!(names(r#""${A:-$B}" '$C' \$D"#), ["A", "B"]);
+        assert_eq!(names("${A:+${B:-$C}}/$ $D"), ["A", "B", "C", "D"]);
+        assert!(names("plain $1 ${").is_empty());
+        let refs = references("${A:-x} ${B:-x} $B");
+        assert!(refs[0].guarded);
+        assert!(!refs[1].guarded);
+    }
+
+    #[test]
+    fn test_graph() {
+        let graph =
+            graph("A=$B\nexport B=${C}/x # $D\nC=\"$A\"\nE=$HOME $MISSING ${OPT:-1}\nF=1\n");
+        assert_eq!(
+            graph.format_text(),
+            "A -> B\nB -> C\nC -> A\nE -> HOME, MISSING, OPT\nF\n"
+        );
+        assert_eq!(graph.cycles(), [["A", "B", "C", "A"]]);
+        let lookup = |key: &str| (key == "HOME").then(|| "/home/me".to_owned());
+        assert_eq!(graph.undefined(&lookup), [("MISSING", "E")]);
+        let dot = graph.format_dot(&lookup);
+        assert!(dot.contains("  \"MISSING\" [style=dashed];\n"), "{}", dot);
+        assert!(dot.contains("  \"E\" -> \"HOME\";\n"), "{}", dot);
+    }
+}
@@ -0,0 +1,69 @@
+//! Shell hooks loading the environment of the current directory at each prompt, as direnv does
+//!
+//! The hooks evaluate `enw export SHELL --diff`, which prints export statements for the
+//! variables differing from the environment of the shell.
+
+use crate::output::posix_single_quote;
+
+const BASH_HOOK: &str = r#"_enw_hook() {
+  local previous_exit_status=$?
+  trap -- '' SIGINT
+  eval "$(ENW -q export bash --diff)"
+  trap - SIGINT
+  return $previous_exit_status
+}
+if [[ ";${PROMPT_COMMAND[*]:-};" != *";_enw_hook;"* ]]; then
+  PROMPT_COMMAND="_enw_hook${PROMPT_COMMAND:+;$PROMPT_COMMAND}"
+fi
+"#;
+
+const ZSH_HOOK: &str = r#"_enw_hook() {
+  trap -- '' SIGINT
+  eval "$(ENW -q export zsh --diff)"
+  trap - SIGINT
+}
+typeset -ag precmd_functions
+if (( ! ${precmd_functions[(I)_enw_hook]} )); then
+  precmd_functions=(_enw_hook $precmd_functions)
+fi
+typeset -ag chpwd_functions
+if (( ! ${chpwd_functions[(I)_enw_hook]} )); then
+  chpwd_functions=(_enw_hook $chpwd_functions)
+fi
+"#;
+
+const FISH_HOOK: &str = r#"function __enw_hook --on-event fish_prompt
+    ENW -q export fish --diff | source
+end
+"#;
+
+/// The hook for `shell`, one of `bash`, `zsh` and `fish`, running the enw at `enw`
+pub(crate) fn hook_script(shell: &str, enw: &str) -> String {
+    let template = match shell {
+        "zsh" => ZSH_HOOK,
+        "fish" => FISH_HOOK,
+        _ => BASH_HOOK,
+    };
+    // Fish single quotes only differ from POSIX ones for backslashes and quotes, which the path
+    // of an executable hardly holds
+    let enw = if shell == "fish" {
+        format!("'{}'", enw.replace('\\', r"\\").replace('\'', r"\'"))
+    } else {
+        posix_single_quote(enw)
+    };
+    template.replace("ENW", &enw)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hook_script() {
+        let bash = hook_script("bash", "/opt/it's/enw");
+        assert!(bash.contains(r#"eval "$('/opt/it'\''s/enw' -q export bash --diff)""#));
+        assert!(hook_script("zsh", "enw").contains("precmd_functions=(_enw_hook"));
+        let fish = hook_script("fish", "/opt/it's/enw");
+        assert!(fish.contains(r"'/opt/it\'s/enw' -q export fish --diff | source"));
+    }
+}
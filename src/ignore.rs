@@ -0,0 +1,66 @@
+//! `.enwignore` files, keeping env files from being loaded automatically
+
+use std::{fs, io, path::Path};
+
+use crate::{glob_match, Error};
+
+pub(crate) const IGNORE_FILE_NAME: &str = ".enwignore";
+
+/// Patterns from an ignore file, in the syntax of `.gitignore`: one glob per line, `#` starts a
+/// comment, and a leading `!` re-includes files excluded by an earlier pattern
+#[derive(Debug, Default)]
+pub(crate) struct IgnoreRules {
+    /// Each pattern, and whether it is negated
+    patterns: Vec<(String, bool)>,
+}
+
+impl IgnoreRules {
+    pub(crate) fn parse(text: &str) -> Self {
+        let patterns = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| match line.strip_prefix('!') {
+                Some(pattern) => (pattern, true),
+                None => (line, false),
+            })
+            .map(|(pattern, negated)| (pattern.trim_start_matches('/').to_owned(), negated))
+            .collect();
+        IgnoreRules { patterns }
+    }
+
+    /// The rules of the ignore file in `dir`, or no rules if there is none
+    pub(crate) fn load(dir: &Path) -> Result<Self, Error> {
+        let path = dir.join(IGNORE_FILE_NAME);
+        match fs::read_to_string(&path) {
+            Ok(text) => Ok(IgnoreRules::parse(&text)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(IgnoreRules::default()),
+            Err(err) => Err(format!("{}: {err}", path.to_string_lossy()).into()),
+        }
+    }
+
+    /// Whether the file `name`, relative to the directory of the ignore file, is ignored. The
+    /// last matching pattern decides.
+    pub(crate) fn is_ignored(&self, name: &str) -> bool {
+        self.patterns
+            .iter()
+            .rev()
+            .find(|(pattern, _)| glob_match(pattern, name))
+            .is_some_and(|(_, negated)| !negated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_ignored() {
+        let rules = IgnoreRules::parse("# comment\n.env.*\n!.env.development\n/.env.secret\n");
+        assert!(rules.is_ignored(".env.production"));
+        assert!(rules.is_ignored(".env.secret"));
+        assert!(!rules.is_ignored(".env.development"));
+        assert!(!rules.is_ignored(".env"));
+        assert!(!IgnoreRules::default().is_ignored(".env"));
+    }
+}
@@ -0,0 +1,224 @@
+//! Limits on the environment passed to COMMAND
+//!
+//! They are checked before COMMAND is executed, as exceeding the limits of the kernel otherwise
+//! only surfaces as an opaque E2BIG from exec.
+
+use std::mem;
+
+use crate::{glob_match, log::log, Error};
+
+/// The longest `KEY=VALUE` string, including the terminating NUL, that Linux passes to a new
+/// program, 32 pages
+#[cfg(target_os = "linux")]
+const MAX_ARG_STRLEN: usize = 32 * 4096;
+#[cfg(not(target_os = "linux"))]
+const MAX_ARG_STRLEN: usize = usize::MAX;
+
+/// How many of the largest variables are named when the environment is too large
+const LARGEST_SHOWN: usize = 3;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct Limits {
+    /// Largest value in bytes
+    max_value_size: Option<usize>,
+    /// Largest size of the environment and arguments in bytes, by default `ARG_MAX`
+    max_env_size: Option<usize>,
+    max_vars: Option<usize>,
+    /// Patterns of variables cut down to the largest value size rather than failing
+    truncate: Vec<String>,
+}
+
+impl Limits {
+    pub(crate) fn new(
+        max_value_size: Option<usize>,
+        max_env_size: Option<usize>,
+        max_vars: Option<usize>,
+        truncate: Vec<String>,
+    ) -> Self {
+        Limits {
+            max_value_size,
+            max_env_size,
+            max_vars,
+            truncate,
+        }
+    }
+
+    /// Check the environment and the command line of COMMAND, truncating the values of
+    /// variables selected by `--truncate` that are too large
+    pub(crate) fn check(&self, env: &mut [(String, String)], args: &[String]) -> Result<(), Error> {
+        if let Some(max_vars) = self.max_vars {
+            if env.len() > max_vars {
+                return Err(Error::Other(format!(
+                    "{} variables are more than the limit of {max_vars} set by --max-vars",
+                    env.len()
+                )));
+            }
+        }
+        for (key, value) in env.iter_mut() {
+            let (limit, origin) = self.value_limit(key);
+            if value.len() <= limit {
+                continue;
+            }
+            if !self.truncate.iter().any(|pattern| glob_match(pattern, key)) {
+                return Err(Error::Other(format!(
+                    "{key} is {} bytes, more than the limit of {limit} bytes {origin}, \
+                     use --truncate {key} to cut it down",
+                    value.len()
+                )));
+            }
+            let mut end = limit;
+            while !value.is_char_boundary(end) {
+                end -= 1;
+            }
+            log!(Info, "{key}: truncated from {} to {end} bytes", value.len());
+            value.truncate(end);
+        }
+        let (limit, origin) = match self.max_env_size {
+            Some(limit) => (limit, "set by --max-env-size"),
+            None => (arg_max(), "of the system (ARG_MAX)"),
+        };
+        let args_size: usize = args.iter().map(|arg| entry_size(arg.len())).sum();
+        let env_size: usize = env
+            .iter()
+            .map(|(key, value)| entry_size(key.len() + 1 + value.len()))
+            .sum();
+        if args_size + env_size > limit {
+            let mut largest: Vec<_> = env.iter().collect();
+            largest.sort_by_key(|(_, value)| std::cmp::Reverse(value.len()));
+            let largest: Vec<_> = largest
+                .iter()
+                .take(LARGEST_SHOWN)
+                .map(|(key, value)| format!("{key} ({} bytes)", value.len()))
+                .collect();
+            return Err(Error::Other(format!(
+                "the environment of {} variables and the arguments take {} bytes, more than the \
+                 limit of {limit} bytes {origin}; the largest variables are {}",
+                env.len(),
+                args_size + env_size,
+                largest.join(", ")
+            )));
+        }
+        Ok(())
+    }
+
+    /// The largest value size of `key`, with where the limit comes from
+    fn value_limit(&self, key: &str) -> (usize, &'static str) {
+        // The `=` and the terminating NUL count towards the limit of the kernel
+        let kernel_limit = MAX_ARG_STRLEN.saturating_sub(key.len() + 2);
+        match self.max_value_size {
+            Some(limit) if limit <= kernel_limit => (limit, "set by --max-value-size"),
+            _ => (kernel_limit, "for a single variable of the system"),
+        }
+    }
+}
+
+/// Split generated arguments into chunks that each fit in `chunk_size` bytes of arguments, as
+/// xargs(1) counts them, keeping the arguments of a group such as `-e KEY=VALUE` together.
+/// A `chunk_size` of 0 takes what is left of `ARG_MAX` by the current environment.
+pub(crate) fn chunk_args(
+    groups: Vec<Vec<String>>,
+    chunk_size: usize,
+) -> Result<Vec<Vec<String>>, Error> {
+    let limit = match chunk_size {
+        0 => default_chunk_size(),
+        size => size,
+    };
+    let mut chunks: Vec<Vec<String>> = Vec::new();
+    let mut size = 0;
+    for group in groups {
+        let group_size: usize = group.iter().map(|arg| entry_size(arg.len())).sum();
+        if group_size > limit {
+            return Err(Error::Other(format!(
+                "'{}' takes {group_size} bytes, more than the chunk size of {limit} bytes",
+                group.join(" ")
+            )));
+        }
+        match chunks.last_mut() {
+            Some(chunk) if size + group_size <= limit => chunk.extend(group),
+            _ => {
+                chunks.push(group);
+                size = 0;
+            }
+        }
+        size += group_size;
+    }
+    Ok(chunks)
+}
+
+/// What is left of `ARG_MAX` by the current environment, less the headroom POSIX asks xargs(1)
+/// to keep for the command and its own arguments
+fn default_chunk_size() -> usize {
+    const HEADROOM: usize = 2048;
+    let env_size: usize = std::env::vars_os()
+        .map(|(key, value)| entry_size(key.len() + 1 + value.len()))
+        .sum();
+    arg_max()
+        .min(MAX_ARG_STRLEN)
+        .saturating_sub(env_size + HEADROOM)
+}
+
+/// The space a string of `len` bytes takes on the stack of a new program: the string, its NUL
+/// and the pointer to it
+fn entry_size(len: usize) -> usize {
+    len + 1 + mem::size_of::<*const u8>()
+}
+
+/// The limit of the system on the size of the arguments and environment of a new program
+#[cfg(unix)]
+fn arg_max() -> usize {
+    // SAFETY: sysconf has no preconditions
+    let arg_max = unsafe { libc::sysconf(libc::_SC_ARG_MAX) };
+    usize::try_from(arg_max).unwrap_or(usize::MAX)
+}
+
+#[cfg(not(unix))]
+fn arg_max() -> usize {
+    usize::MAX
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_limits() {
+        let args = ["cmd".to_owned()];
+        let mut vars = env(&[("A", "1"), ("LOG", "ååå"), ("B", "2")]);
+        assert!(Limits::default().check(&mut vars, &args).is_ok());
+        assert!(Limits::new(None, None, Some(2), Vec::new())
+            .check(&mut vars, &args)
+            .is_err());
+
+        let limits = Limits::new(Some(4), None, None, Vec::new());
+        let err = limits.check(&mut vars, &args).unwrap_err();
+        assert!(err.to_string().starts_with("LOG is 6 bytes"), "{}", err);
+        let limits = Limits::new(Some(5), None, None, vec!["LOG*".to_owned()]);
+        limits.check(&mut vars, &args).unwrap();
+        assert_eq!(vars[1].1, "åå");
+
+        let limits = Limits::new(None, Some(48), None, Vec::new());
+        let err = limits.check(&mut vars, &args).unwrap_err();
+        assert!(err.to_string().contains("LOG (4 bytes), A"), "{}", err);
+    }
+
+    #[test]
+    fn test_chunk_args() {
+        let group = |key: &str| vec!["-e".to_owned(), format!("{key}=1")];
+        let groups = vec![group("A"), group("B"), group("C")];
+        let size = entry_size(2) + entry_size(3);
+        let chunks = chunk_args(groups.clone(), 2 * size).unwrap();
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0], ["-e", "A=1", "-e", "B=1"]);
+        assert_eq!(chunks[1], ["-e", "C=1"]);
+        assert_eq!(chunk_args(groups.clone(), usize::MAX).unwrap().len(), 1);
+        assert_eq!(chunk_args(groups.clone(), 0).unwrap().len(), 1);
+        let err = chunk_args(groups, size - 1).unwrap_err();
+        assert!(err.to_string().starts_with("'-e A=1' takes"), "{}", err);
+    }
+}
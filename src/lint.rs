@@ -0,0 +1,325 @@
+//! Checks for common mistakes in env files
+
+use std::{collections::HashMap, fs, path::Path};
+
+use crate::{
+    edit::write_atomically,
+    parse::{parse_value, ParseOptions},
+    report::{paint, Stream, Style},
+    serialize::format_dotenv,
+    Error,
+};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Rule {
+    DuplicateKey,
+    LowercaseKey,
+    UnquotedSpaces,
+    TrailingWhitespace,
+    UnorderedKey,
+    InvalidEscape,
+}
+
+impl Rule {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Rule::DuplicateKey => "duplicate-key",
+            Rule::LowercaseKey => "lowercase-key",
+            Rule::UnquotedSpaces => "unquoted-spaces",
+            Rule::TrailingWhitespace => "trailing-whitespace",
+            Rule::UnorderedKey => "unordered-key",
+            Rule::InvalidEscape => "invalid-escape",
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) struct Problem {
+    /// 1-based line number
+    pub(crate) line: usize,
+    pub(crate) rule: Rule,
+    pub(crate) message: String,
+}
+
+/// Lint each file, rewriting it first with `fix`, and print the problems found
+pub(crate) fn lint_files(paths: &[&Path], fix: bool) -> Result<(), Error> {
+    let mut problems = 0;
+    for path in paths {
+        let mut text =
+            fs::read_to_string(path).map_err(|err| format!("{}: {err}", path.display()))?;
+        if fix {
+            let fixed = fix_doc(&text);
+            if fixed != text {
+                write_atomically(path, fixed.as_bytes())
+                    .map_err(|err| format!("{}: {err}", path.display()))?;
+                text = fixed;
+            }
+        }
+        for problem in lint_doc(&text) {
+            let location = format!("{}:{}:", path.display(), problem.line);
+            println!(
+                "{} {}: {}",
+                paint(Stream::Stdout, Style::Dim, &location),
+                paint(Stream::Stdout, Style::Warning, problem.rule.name()),
+                problem.message
+            );
+            problems += 1;
+        }
+    }
+    match problems {
+        0 => Ok(()),
+        1 => Err("1 problem found".into()),
+        _ => Err(format!("{problems} problems found").into()),
+    }
+}
+
+pub(crate) fn lint_doc(text: &str) -> Vec<Problem> {
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    let mut problems = Vec::new();
+    let mut seen = HashMap::new();
+    let mut previous_key: Option<&str> = None;
+    for (index, line) in text.lines().enumerate() {
+        let mut report = |rule, message| {
+            problems.push(Problem {
+                line: index + 1,
+                rule,
+                message,
+            })
+        };
+        if line.ends_with([' ', '\t']) {
+            report(Rule::TrailingWhitespace, "trailing whitespace".to_owned());
+        }
+        let Some((key, value)) = split_assignment(line) else {
+            previous_key = None;
+            continue;
+        };
+        if key.chars().any(char::is_lowercase) {
+            report(Rule::LowercaseKey, format!("{key} should be uppercase"));
+        }
+        if let Some(first_line) = seen.insert(key, index + 1) {
+            report(
+                Rule::DuplicateKey,
+                format!("{key} is already defined on line {first_line}"),
+            );
+        }
+        if previous_key.is_some_and(|previous| previous > key) {
+            report(
+                Rule::UnorderedKey,
+                format!(
+                    "{key} should go before {}",
+                    previous_key.unwrap_or_default()
+                ),
+            );
+        }
+        previous_key = Some(key);
+        if let Some(c) = find_invalid_escape(value) {
+            report(Rule::InvalidEscape, format!("invalid escape \\{c}"));
+        }
+        if has_unquoted_spaces(value) {
+            report(
+                Rule::UnquotedSpaces,
+                format!("value of {key} should be quoted"),
+            );
+        }
+    }
+    problems
+}
+
+/// Rewrite the document with every fixable problem corrected. Of duplicate keys the last
+/// definition, which is the one in effect, is kept.
+pub(crate) fn fix_doc(text: &str) -> String {
+    let mut lines: Vec<String> = text.lines().map(fix_line).collect();
+    let mut last_definition = HashMap::new();
+    for (index, line) in lines.iter().enumerate() {
+        if let Some((key, _)) = split_assignment(line) {
+            last_definition.insert(key.to_owned(), index);
+        }
+    }
+    let mut index = 0;
+    lines.retain(|line| {
+        index += 1;
+        split_assignment(line).is_none_or(|(key, _)| last_definition[key] == index - 1)
+    });
+    // Sort each run of consecutive assignments, leaving blank lines and comments in place
+    let mut start = 0;
+    while start < lines.len() {
+        let len = lines[start..]
+            .iter()
+            .take_while(|line| split_assignment(line).is_some())
+            .count();
+        lines[start..start + len].sort_by(|a, b| {
+            let key = |line| split_assignment(line).map(|(key, _)| key);
+            key(a).cmp(&key(b))
+        });
+        start += len.max(1);
+    }
+    let mut fixed = lines.join("\n");
+    if text.ends_with('\n') {
+        fixed.push('\n');
+    }
+    fixed
+}
+
+fn fix_line(line: &str) -> String {
+    let line = line.trim_end();
+    let Some((key, value)) = split_assignment(line) else {
+        return line.to_owned();
+    };
+    let indent = &line[..line.len() - line.trim_start().len()];
+    let key = key.to_uppercase();
+    let mut value = escape_invalid_escapes(value);
+    if has_unquoted_spaces(&value) && !value.contains('$') {
+        let (unquoted, comment) = split_comment(&value);
+        let fixed = parse_value(unquoted, &ParseOptions::default(), &|_| None)
+            .ok()
+            .and_then(|parsed| format_dotenv(&key, &parsed).ok());
+        if let Some(fixed) = fixed {
+            let fixed_value = &fixed[key.len() + 1..];
+            value = match comment {
+                "" => fixed_value.to_owned(),
+                _ => format!("{fixed_value} {comment}"),
+            };
+        }
+    }
+    format!("{indent}{key}={value}")
+}
+
+/// The key and raw value of a `KEY=value` line, or `None` for blank lines and comments
+pub(crate) fn split_assignment(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim_start();
+    if line.starts_with('#') {
+        return None;
+    }
+    line.split_once('=')
+        .map(|(key, value)| (key.trim(), value.trim()))
+}
+
+/// Split an unquoted `#` comment off a raw value
+pub(crate) fn split_comment(value: &str) -> (&str, &str) {
+    let mut quote = None;
+    let mut chars = value.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            (Some(q), _) if c == q => quote = None,
+            (Some('"') | None, '\\') => {
+                chars.next();
+            }
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => return (&value[..i], &value[i..]),
+            _ => (),
+        }
+    }
+    (value, "")
+}
+
+/// Whether the value, not counting any comment, has whitespace outside quotes
+fn has_unquoted_spaces(value: &str) -> bool {
+    let (value, _) = split_comment(value);
+    let mut quote = None;
+    let mut chars = value.trim_end().chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), _) if c == q => quote = None,
+            (Some('"') | None, '\\') => {
+                chars.next();
+            }
+            (None, '"' | '\'') => quote = Some(c),
+            (None, ' ' | '\t') => return true,
+            _ => (),
+        }
+    }
+    false
+}
+
+/// The first character after a backslash that the parser doesn't treat as an escape
+fn find_invalid_escape(value: &str) -> Option<char> {
+    let (value, _) = split_comment(value);
+    let mut quote = None;
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), _) if c == q => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (Some('"'), '\\') => match chars.next() {
+                Some('"' | '\\' | '$' | 'n' | 't' | 'r' | 'u') | None => (),
+                Some(c) => return Some(c),
+            },
+            (None, '\\') => match chars.next() {
+                Some('"' | '\'' | ' ' | '$' | '\\') | None => (),
+                Some(c) => return Some(c),
+            },
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Double the backslash of invalid escapes in double quotes, which keeps the value the same
+fn escape_invalid_escapes(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut quote = None;
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        out.push(c);
+        match (quote, c) {
+            (Some(q), _) if c == q => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => {
+                out.extend(chars.by_ref());
+            }
+            (Some('"') | None, '\\') => match chars.next() {
+                Some(next @ ('"' | '\\' | '$' | 'n' | 't' | 'r' | 'u')) => out.push(next),
+                Some(next) if quote.is_none() => out.push(next),
+                Some(next) => {
+                    out.push('\\');
+                    out.push(next);
+                }
+                None => (),
+            },
+            _ => (),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn test_lint_doc() {
+        let doc = "B=1\nA=2 \nlower=x\nB=3\n\n# comment\nC=a b # c\nD=\"\\q\"\nE='a b'\n";
+        let problems: Vec<_> = lint_doc(doc)
+            .into_iter()
+            .map(|problem| (problem.line, problem.rule))
+            .collect();
+        assert_eq!(
+            problems,
+            vec![
+                (2, Rule::TrailingWhitespace),
+                (2, Rule::UnorderedKey),
+                (3, Rule::LowercaseKey),
+                (4, Rule::DuplicateKey),
+                (4, Rule::UnorderedKey),
+                (7, Rule::UnquotedSpaces),
+                (8, Rule::InvalidEscape),
+            ]
+        );
+        assert_eq!(lint_doc("A=1\nB=\"x y\"\n# B=2\nC=\"\\n\"\n"), vec![]);
+    }
+
+    #[test]
+    fn test_fix_doc() {
+        let doc = "B=1\nA=2 \nlower=x\nB=3\n\n# comment\nC=a b # c\nD=\"\\q\"\nE='a b'\n";
+        let fixed = fix_doc(doc);
+        assert_eq!(
+            fixed,
+            "A=2\nB=3\nLOWER=x\n\n# comment\nC='a b' # c\nD=\"\\\\q\"\nE='a b'\n"
+        );
+        assert_eq!(lint_doc(&fixed), vec![]);
+        let value = |line| parse_value(line, &ParseOptions::default(), &|_| None).unwrap();
+        assert_eq!(value("\"\\\\q\""), value("\"\\q\""));
+    }
+}
@@ -0,0 +1,103 @@
+//! Diagnostics on stderr about how the environment is resolved, enabled with `-v` or `ENW_LOG`
+
+use std::{
+    fmt,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use crate::report::{paint, Stream, Style};
+
+pub(crate) const LOG_ENV_VAR: &str = "ENW_LOG";
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Off as u8);
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub(crate) enum Level {
+    Off,
+    /// Which files are found, skipped and loaded, and how many variables each defines
+    Info,
+    /// Also where each variable comes from and which definitions it overrides
+    Debug,
+}
+
+impl Level {
+    /// The level for a number of `-v` flags
+    pub(crate) fn from_count(count: u8) -> Self {
+        match count {
+            0 => Level::Off,
+            1 => Level::Info,
+            _ => Level::Debug,
+        }
+    }
+
+    /// Parse the value of `ENW_LOG`
+    pub(crate) fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "" | "off" | "0" => Some(Level::Off),
+            "info" | "1" => Some(Level::Info),
+            "debug" | "trace" | "2" => Some(Level::Debug),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Level::Off => "off",
+            Level::Info => "info",
+            Level::Debug => "debug",
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+pub(crate) fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub(crate) fn enabled(level: Level) -> bool {
+    level != Level::Off && level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+/// Print a message to stderr if `level` is enabled, e.g. `log!(Info, "loading {}", path)`
+macro_rules! log {
+    ($level:ident, $($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::$level) {
+            $crate::log::print($crate::log::Level::$level, format_args!($($arg)*));
+        }
+    };
+}
+
+/// Print a message of `level`, dimmed on a terminal apart from the message of `Info`
+pub(crate) fn print(level: Level, message: fmt::Arguments) {
+    match level {
+        Level::Info => eprintln!(
+            "{}: {message}",
+            paint(Stream::Stderr, Style::Dim, level.as_str())
+        ),
+        _ => eprintln!(
+            "{}",
+            paint(Stream::Stderr, Style::Dim, &format!("{level}: {message}"))
+        ),
+    }
+}
+
+pub(crate) use log;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_level() {
+        assert_eq!(Level::parse("DEBUG"), Some(Level::Debug));
+        assert_eq!(Level::parse(""), Some(Level::Off));
+        assert_eq!(Level::parse("loud"), None);
+        assert_eq!(Level::from_count(3), Level::Debug);
+        assert!(!enabled(Level::Off));
+    }
+}
@@ -1,5 +1,8 @@
-use enw::BoxError;
+use std::process;
 
-fn main() -> Result<(), BoxError> {
-    enw::run(std::env::args())
+fn main() {
+    if let Err(err) = enw::run(std::env::args_os()) {
+        enw::report_error(&err);
+        process::exit(err.exit_code());
+    }
 }
@@ -0,0 +1,147 @@
+//! Lossless conversion between the bytes of OS strings and `String`, and the few other things
+//! that differ between Unix and other targets
+//!
+//! On Unix, arguments, environment variables and files can hold any bytes, while enw works on
+//! `String`s. Bytes that are not valid UTF-8 are carried through as characters of a private use
+//! range, U+10FE80 to U+10FEFF, and turned back into the original bytes on the way out.
+
+#[cfg(unix)]
+use std::os::unix::{
+    ffi::OsStringExt,
+    fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt},
+    process::ExitStatusExt,
+};
+use std::{
+    borrow::Cow,
+    ffi::{OsStr, OsString},
+    fs::{DirBuilder, Metadata, OpenOptions},
+    process::ExitStatus,
+};
+
+/// The character standing in for the invalid byte `0x80 + n` is `RAW_BYTE_BASE + n`
+const RAW_BYTE_BASE: u32 = 0x10FE80;
+
+pub(crate) fn bytes_to_string(bytes: &[u8]) -> Cow<'_, str> {
+    if let Ok(s) = std::str::from_utf8(bytes) {
+        return Cow::Borrowed(s);
+    }
+    let mut s = String::with_capacity(bytes.len() + 8);
+    for chunk in bytes.utf8_chunks() {
+        s.push_str(chunk.valid());
+        s.extend(chunk.invalid().iter().map(|&byte| raw_byte_char(byte)));
+    }
+    Cow::Owned(s)
+}
+
+pub(crate) fn os_to_string(os: &OsStr) -> String {
+    bytes_to_string(os.as_encoded_bytes()).into_owned()
+}
+
+pub(crate) fn string_to_bytes(s: &str) -> Cow<'_, [u8]> {
+    if !s.chars().any(|c| raw_byte(c).is_some()) {
+        return Cow::Borrowed(s.as_bytes());
+    }
+    let mut bytes = Vec::with_capacity(s.len());
+    for c in s.chars() {
+        match raw_byte(c) {
+            Some(byte) => bytes.push(byte),
+            None => bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+    Cow::Owned(bytes)
+}
+
+#[cfg(unix)]
+pub(crate) fn string_to_os(s: &str) -> OsString {
+    OsString::from_vec(string_to_bytes(s).into_owned())
+}
+
+/// Elsewhere, OS strings are only made of text, and the bytes that are not are replaced
+#[cfg(not(unix))]
+pub(crate) fn string_to_os(s: &str) -> OsString {
+    OsString::from(String::from_utf8_lossy(&string_to_bytes(s)).into_owned())
+}
+
+/// The signal that terminated a process
+#[cfg(unix)]
+pub(crate) fn exit_signal(status: &ExitStatus) -> Option<i32> {
+    status.signal()
+}
+
+/// Only processes on Unix are terminated by signals
+#[cfg(not(unix))]
+pub(crate) fn exit_signal(_: &ExitStatus) -> Option<i32> {
+    None
+}
+
+/// The permission bits of a file
+#[cfg(unix)]
+pub(crate) fn permission_bits(metadata: &Metadata) -> Option<u32> {
+    Some(metadata.permissions().mode() & 0o7777)
+}
+
+/// Only files on Unix have permission bits
+#[cfg(not(unix))]
+pub(crate) fn permission_bits(_: &Metadata) -> Option<u32> {
+    None
+}
+
+/// The options to create a file that only the user can read and write
+#[cfg(unix)]
+pub(crate) fn private_open_options() -> OpenOptions {
+    let mut options = OpenOptions::new();
+    options.mode(0o600);
+    options
+}
+
+#[cfg(not(unix))]
+pub(crate) fn private_open_options() -> OpenOptions {
+    OpenOptions::new()
+}
+
+/// The builder of directories that only the user can access
+#[cfg(unix)]
+pub(crate) fn private_dir_builder() -> DirBuilder {
+    let mut builder = DirBuilder::new();
+    builder.mode(0o700);
+    builder
+}
+
+#[cfg(not(unix))]
+pub(crate) fn private_dir_builder() -> DirBuilder {
+    DirBuilder::new()
+}
+
+fn raw_byte_char(byte: u8) -> char {
+    // Invalid UTF-8 bytes are always 0x80 or above
+    char::from_u32(RAW_BYTE_BASE + u32::from(byte - 0x80)).unwrap_or(char::REPLACEMENT_CHARACTER)
+}
+
+fn raw_byte(c: char) -> Option<u8> {
+    let offset = u32::from(c).checked_sub(RAW_BYTE_BASE)?;
+    u8::try_from(offset)
+        .ok()
+        .filter(|&n| n < 0x80)
+        .map(|n| n + 0x80)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        for bytes in [
+            &b"plain"[..],
+            "unicode \u{3000}".as_bytes(),
+            b"latin-1 \xe9t\xe9",
+            b"\xff\xfe\x80",
+            b"truncated \xe3\x80",
+        ] {
+            let s = bytes_to_string(bytes);
+            assert_eq!(string_to_bytes(&s), bytes, "{:?}", s);
+        }
+        assert!(matches!(bytes_to_string(b"valid"), Cow::Borrowed(_)));
+        assert_eq!(bytes_to_string(b"a\xffb").chars().count(), 3);
+    }
+}
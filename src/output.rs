@@ -0,0 +1,529 @@
+//! Printing of the resolved environment
+
+use std::{
+    env,
+    ffi::OsString,
+    io::{self, Write},
+};
+
+use crate::{
+    glob_match,
+    os::{string_to_bytes, string_to_os},
+    UNSET,
+};
+
+pub(crate) const DEFAULT_MASK_PATTERNS: [&str; 4] = ["*TOKEN*", "*SECRET*", "*PASSWORD*", "*KEY*"];
+pub(crate) const MASKED_VALUE: &str = "********";
+
+/// The shells of `--shell` and `enw export`
+pub(crate) const SHELL_NAMES: [&str; 5] = ["sh", "bash", "zsh", "fish", "powershell"];
+/// The formats of `--format`
+pub(crate) const FORMAT_NAMES: [&str; 5] = [
+    "env",
+    "k8s-secret",
+    "k8s-configmap",
+    "docker-args",
+    "systemd",
+];
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Shell {
+    Posix,
+    Fish,
+    Pwsh,
+}
+
+impl Shell {
+    /// The syntax of a shell named by `--shell` or `enw export`, POSIX by default
+    pub(crate) fn from_name(name: Option<&str>) -> Self {
+        match name {
+            Some("fish") => Shell::Fish,
+            Some("powershell") => Shell::Pwsh,
+            _ => Shell::Posix,
+        }
+    }
+}
+
+/// Output format used when printing the environment
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub(crate) enum Format {
+    #[default]
+    Env,
+    K8sSecret,
+    K8sConfigMap,
+    DockerArgs,
+    Systemd,
+}
+
+#[derive(Debug, Default)]
+pub(crate) enum Mask {
+    #[default]
+    Nothing,
+    Matching(Vec<String>),
+    All,
+}
+
+/// When printed values are double quoted
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub(crate) enum Quoting {
+    /// Values other than the simplest, with special characters escaped
+    #[default]
+    Auto,
+    Always,
+    /// Values are printed verbatim
+    Never,
+}
+
+impl Quoting {
+    pub(crate) fn from_name(name: &str) -> Self {
+        match name {
+            "always" => Quoting::Always,
+            "never" => Quoting::Never,
+            _ => Quoting::Auto,
+        }
+    }
+}
+
+/// The separators and quoting of printed variables, `KEY="value"` lines by default
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Layout {
+    pub(crate) kv_sep: String,
+    pub(crate) entry_sep: String,
+    pub(crate) quoting: Quoting,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout {
+            kv_sep: "=".to_owned(),
+            entry_sep: "\n".to_owned(),
+            quoting: Quoting::Auto,
+        }
+    }
+}
+
+impl Layout {
+    /// The layout of `--null`: NUL-terminated entries with verbatim values
+    pub(crate) fn null() -> Self {
+        Layout {
+            entry_sep: "\0".to_owned(),
+            quoting: Quoting::Never,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn format_entry(&self, key: &str, value: &str) -> String {
+        let value = match self.quoting {
+            Quoting::Auto
+                if value
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-')) =>
+            {
+                value.to_owned()
+            }
+            Quoting::Auto | Quoting::Always => format!("\"{}\"", value.escape_default()),
+            Quoting::Never => value.to_owned(),
+        };
+        format!("{key}{}{value}{}", self.kv_sep, self.entry_sep)
+    }
+}
+
+/// Parse a separator given on the command line, where `\t`, `\n`, `\0` and `\\` stand for a
+/// tab, a newline, a NUL and a backslash
+pub(crate) fn parse_separator(s: &str) -> Result<String, String> {
+    let mut separator = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            separator.push(c);
+            continue;
+        }
+        separator.push(match chars.next() {
+            Some('t') => '\t',
+            Some('n') => '\n',
+            Some('0') => '\0',
+            Some('\\') => '\\',
+            Some(c) => {
+                return Err(format!(
+                    "unknown escape \\{c}, expected \\t, \\n, \\0 or \\\\"
+                ))
+            }
+            None => return Err("trailing backslash".to_owned()),
+        });
+    }
+    Ok(separator)
+}
+
+pub(crate) fn format_export(shell: Shell, key: &str, value: &str) -> String {
+    match shell {
+        Shell::Posix => format!("export {}={}", key, posix_single_quote(value)),
+        Shell::Fish => format!(
+            "set -gx {} '{}'",
+            key,
+            value.replace('\\', r"\\").replace('\'', r"\'")
+        ),
+        Shell::Pwsh => format!("$env:{} = '{}'", key, value.replace('\'', "''")),
+    }
+}
+
+pub(crate) fn format_unset(shell: Shell, key: &str) -> String {
+    match shell {
+        Shell::Posix => format!("unset {key}"),
+        Shell::Fish => format!("set -e {key}"),
+        Shell::Pwsh => format!("Remove-Item -ErrorAction SilentlyContinue Env:{key}"),
+    }
+}
+
+/// Format a variable as an entry of a systemd `EnvironmentFile=`. Values other than the simplest
+/// are double quoted, which also keeps newlines.
+pub(crate) fn format_systemd(key: &str, value: &str) -> String {
+    if !value.is_empty()
+        && value.chars().all(|c| {
+            c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '/' | ':' | '@' | ',' | '+')
+        })
+    {
+        format!("{key}={value}")
+    } else {
+        let mut escaped = String::with_capacity(value.len() + 2);
+        for c in value.chars() {
+            if matches!(c, '"' | '\\' | '`' | '$') {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
+        format!("{key}=\"{escaped}\"")
+    }
+}
+
+/// Render a Kubernetes Secret or ConfigMap manifest holding the variables
+pub(crate) fn format_k8s_manifest(
+    format: Format,
+    name: &str,
+    env_vars: &[(String, String)],
+) -> String {
+    let mut manifest = String::from("apiVersion: v1\n");
+    if format == Format::K8sSecret {
+        manifest.push_str("kind: Secret\n");
+    } else {
+        manifest.push_str("kind: ConfigMap\n");
+    }
+    manifest.push_str(&format!("metadata:\n  name: {}\n", yaml_quote(name)));
+    if format == Format::K8sSecret {
+        manifest.push_str("type: Opaque\n");
+    }
+    if env_vars.is_empty() {
+        manifest.push_str("data: {}\n");
+        return manifest;
+    }
+    manifest.push_str("data:\n");
+    for (key, value) in env_vars {
+        let value = if format == Format::K8sSecret {
+            base64_encode(&string_to_bytes(value))
+        } else {
+            yaml_quote(value)
+        };
+        manifest.push_str(&format!("  {}: {}\n", yaml_quote(key), value));
+    }
+    manifest
+}
+
+/// Quote a string as a double-quoted YAML scalar
+pub(crate) fn yaml_quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str(r"\\"),
+            '\n' => quoted.push_str(r"\n"),
+            '\t' => quoted.push_str(r"\t"),
+            '\r' => quoted.push_str(r"\r"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Standard base64 with padding
+pub(crate) fn base64_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | u32::from(b) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Print the environment the way env(1) does: the inherited environment in its original order,
+/// with overridden variables updated in place and new ones appended
+pub(crate) fn print_posix_environment(
+    ignore_env: bool,
+    vars: Vec<(String, String)>,
+    terminator: u8,
+) -> io::Result<()> {
+    let mut environment: Vec<(OsString, OsString)> = if ignore_env {
+        Vec::new()
+    } else {
+        env::vars_os().collect()
+    };
+    for (key, value) in vars {
+        let (key, value) = (string_to_os(&key), string_to_os(&value));
+        match environment.iter_mut().find(|(k, _)| *k == key) {
+            Some(entry) => entry.1 = value,
+            None => environment.push((key, value)),
+        }
+    }
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for (key, value) in environment {
+        out.write_all(key.as_encoded_bytes())?;
+        out.write_all(b"=")?;
+        out.write_all(value.as_encoded_bytes())?;
+        out.write_all(&[terminator])?;
+    }
+    out.flush()
+}
+
+/// Quote `s` for a POSIX shell, unless it only contains characters that are safe unquoted
+pub(crate) fn shell_quote(s: &str) -> String {
+    if !s.is_empty()
+        && s.chars().all(|c| {
+            c.is_ascii_alphanumeric()
+                || matches!(c, '_' | '-' | '.' | '/' | ':' | '=' | '@' | ',' | '+' | '%')
+        })
+    {
+        s.to_owned()
+    } else {
+        posix_single_quote(s)
+    }
+}
+
+pub(crate) fn posix_single_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+pub(crate) fn mask_values(mask: &Mask, env_vars: Vec<(String, String)>) -> Vec<(String, String)> {
+    match mask {
+        Mask::Nothing => env_vars,
+        Mask::All => env_vars
+            .into_iter()
+            .map(|(key, _)| (key, MASKED_VALUE.to_owned()))
+            .collect(),
+        Mask::Matching(patterns) => env_vars
+            .into_iter()
+            .map(|(key, value)| {
+                let upper_key = key.to_uppercase();
+                if patterns
+                    .iter()
+                    .any(|pattern| glob_match(&pattern.to_uppercase(), &upper_key))
+                {
+                    (key, MASKED_VALUE.to_owned())
+                } else {
+                    (key, value)
+                }
+            })
+            .collect(),
+    }
+}
+
+/// Lines of `-KEY=old` and `+KEY=new` for the variables whose value differs between the
+/// `current` environment and the `resolved` one. With `ignore_env`, every current variable
+/// missing from `resolved` is shown as removed.
+pub(crate) fn format_diff(
+    current: &[(String, String)],
+    resolved: &[(String, String)],
+    ignore_env: bool,
+) -> String {
+    let mut diff = String::new();
+    for (key, value) in resolved {
+        let old = current.iter().find(|(current_key, _)| current_key == key);
+        if value == UNSET {
+            if let Some((_, old)) = old {
+                diff.push_str(&format!("-{key}={}\n", old.escape_default()));
+            }
+            continue;
+        }
+        match old {
+            Some((_, old)) if old == value => continue,
+            Some((_, old)) => diff.push_str(&format!("-{key}={}\n", old.escape_default())),
+            None => (),
+        }
+        diff.push_str(&format!("+{key}={}\n", value.escape_default()));
+    }
+    if ignore_env {
+        for (key, old) in current {
+            if !resolved.iter().any(|(resolved_key, _)| resolved_key == key) {
+                diff.push_str(&format!("-{key}={}\n", old.escape_default()));
+            }
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::parse::{parse_doc_line, Dialect, ParseOptions};
+
+    #[test]
+    fn test_format_export() {
+        let value = r"it's a \ test";
+        assert_eq!(
+            format_export(Shell::Posix, "KEY", value),
+            r"export KEY='it'\''s a \ test'"
+        );
+        assert_eq!(
+            format_export(Shell::Fish, "KEY", value),
+            r"set -gx KEY 'it\'s a \\ test'"
+        );
+        assert_eq!(
+            format_export(Shell::Pwsh, "KEY", value),
+            r"$env:KEY = 'it''s a \ test'"
+        );
+    }
+
+    #[test]
+    fn test_layout() {
+        let layout = Layout::default();
+        assert_eq!(layout.format_entry("A", "simple-1"), "A=simple-1\n");
+        assert_eq!(
+            layout.format_entry("A", "a \"b\"\n"),
+            "A=\"a \\\"b\\\"\\n\"\n"
+        );
+        let layout = Layout {
+            kv_sep: ": ".to_owned(),
+            quoting: Quoting::Always,
+            ..Layout::null()
+        };
+        assert_eq!(layout.format_entry("A", "1"), "A: \"1\"\0");
+        assert_eq!(parse_separator(r"\t|\\"), Ok("\t|\\".to_owned()));
+        assert!(parse_separator(r"\x").is_err());
+        assert!(parse_separator("\\").is_err());
+    }
+
+    #[test]
+    fn test_format_systemd_roundtrip() {
+        let options = ParseOptions {
+            dialect: Dialect::Systemd,
+            ..Default::default()
+        };
+        for value in [
+            "",
+            "simple/path:8080",
+            "with space ",
+            r#"quotes ' and " and \ and `cmd` and $HOME"#,
+            "; # not comments",
+            "line 1\nline 2",
+        ] {
+            let line = format_systemd("KEY", value);
+            let parsed = parse_doc_line(&line, &options, &no_vars).unwrap().unwrap();
+            assert_eq!(parsed, ("KEY", value.into()), "{}", line);
+        }
+        assert_eq!(format_systemd("KEY", "a b"), "KEY=\"a b\"");
+    }
+
+    #[test]
+    fn test_base64_encode() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(base64_encode(b"foob"), "Zm9vYg==");
+        assert_eq!(base64_encode(b"\xff\xfe\xfd"), "//79");
+    }
+
+    #[test]
+    fn test_format_k8s_manifest() {
+        let env_vars = vec![owned("A", "foo"), owned("B", "say \"hi\"\n")];
+        assert_eq!(
+            format_k8s_manifest(Format::K8sSecret, "mysecret", &env_vars),
+            "apiVersion: v1\nkind: Secret\nmetadata:\n  name: \"mysecret\"\ntype: Opaque\n\
+             data:\n  \"A\": Zm9v\n  \"B\": c2F5ICJoaSIK\n"
+        );
+        assert_eq!(
+            format_k8s_manifest(Format::K8sConfigMap, "config", &env_vars),
+            "apiVersion: v1\nkind: ConfigMap\nmetadata:\n  name: \"config\"\n\
+             data:\n  \"A\": \"foo\"\n  \"B\": \"say \\\"hi\\\"\\n\"\n"
+        );
+        assert_eq!(
+            format_k8s_manifest(Format::K8sConfigMap, "empty", &[]),
+            "apiVersion: v1\nkind: ConfigMap\nmetadata:\n  name: \"empty\"\ndata: {}\n"
+        );
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("KEY=value"), "KEY=value");
+        assert_eq!(shell_quote("KEY=a b"), "'KEY=a b'");
+        assert_eq!(shell_quote("KEY=it's"), r"'KEY=it'\''s'");
+        assert_eq!(shell_quote("KEY=$HOME"), "'KEY=$HOME'");
+        assert_eq!(shell_quote(""), "''");
+    }
+
+    #[test]
+    fn test_mask_values() {
+        let env_vars = vec![
+            owned("API_TOKEN", "abc"),
+            owned("db_password", "x"),
+            owned("HOME", "/"),
+        ];
+        let mask = Mask::Matching(
+            DEFAULT_MASK_PATTERNS
+                .iter()
+                .map(|&p| p.to_owned())
+                .collect(),
+        );
+        assert_eq!(
+            mask_values(&mask, env_vars),
+            vec![
+                owned("API_TOKEN", MASKED_VALUE),
+                owned("db_password", MASKED_VALUE),
+                owned("HOME", "/")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_diff() {
+        let current = vec![
+            owned("HOME", "/root"),
+            owned("PORT", "80"),
+            owned("USER", "me"),
+        ];
+        let resolved = vec![
+            owned("PORT", "8080"),
+            owned("USER", "me"),
+            owned("NEW", "a\nb"),
+        ];
+        assert_eq!(
+            format_diff(&current, &resolved, false),
+            "-PORT=80\n+PORT=8080\n+NEW=a\\nb\n"
+        );
+        assert_eq!(
+            format_diff(&current, &resolved, true),
+            "-PORT=80\n+PORT=8080\n+NEW=a\\nb\n-HOME=/root\n"
+        );
+    }
+
+    fn no_vars(_: &str) -> Option<String> {
+        None
+    }
+
+    fn owned(k: &str, v: &str) -> (String, String) {
+        (k.into(), v.into())
+    }
+}
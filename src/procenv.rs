@@ -0,0 +1,72 @@
+//! The environment of other running processes, read from `/proc/PID/environ` on Linux
+//!
+//! The file holds the environment the process was started with. Changes the process made to its
+//! own environment later are not reflected, which is what tells whether it needs a restart to
+//! pick up new configuration.
+
+use std::{fs, io, path::PathBuf};
+
+use crate::{os::bytes_to_string, Error};
+
+/// The environment process `pid` was started with, in its original order
+pub(crate) fn read_environ(pid: u32) -> Result<Vec<(String, String)>, Error> {
+    if !cfg!(target_os = "linux") {
+        return Err(Error::Usage(
+            "reading the environment of another process is only supported on Linux".to_owned(),
+        ));
+    }
+    let path = PathBuf::from(format!("/proc/{pid}/environ"));
+    let bytes = fs::read(&path).map_err(|err| match err.kind() {
+        io::ErrorKind::NotFound => Error::Other(format!("no process with pid {pid} is running")),
+        io::ErrorKind::PermissionDenied => Error::Other(format!(
+            "{}: permission denied, the process may belong to another user",
+            path.to_string_lossy()
+        )),
+        _ => Error::io(&path, err),
+    })?;
+    Ok(parse_environ(&bytes))
+}
+
+/// The `KEY=VALUE` entries of NUL-separated `bytes`. Entries without `=` are skipped, as
+/// env(1) skips them.
+fn parse_environ(bytes: &[u8]) -> Vec<(String, String)> {
+    bytes
+        .split(|&byte| byte == b'\0')
+        .filter_map(|entry| {
+            let sep = entry.iter().position(|&byte| byte == b'=')?;
+            Some((
+                bytes_to_string(&entry[..sep]).into_owned(),
+                bytes_to_string(&entry[sep + 1..]).into_owned(),
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_environ() {
+        let vars = parse_environ(b"A=1\0EMPTY=\0B=x=y\0junk\0");
+        assert_eq!(
+            vars,
+            [
+                ("A".to_owned(), "1".to_owned()),
+                ("EMPTY".to_owned(), String::new()),
+                ("B".to_owned(), "x=y".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_read_environ() {
+        let vars = read_environ(std::process::id()).unwrap();
+        assert!(vars.iter().any(|(key, _)| key == "PATH"));
+        assert!(read_environ(u32::MAX)
+            .unwrap_err()
+            .to_string()
+            .contains("no process"));
+    }
+}
@@ -0,0 +1,407 @@
+//! Process attributes set up before COMMAND is executed
+
+use std::io;
+#[cfg(unix)]
+use std::{
+    ffi::{CStr, CString},
+    mem, ptr,
+};
+
+use crate::{sandbox::Sandbox, Error};
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+type Resource = libc::__rlimit_resource_t;
+#[cfg(all(unix, not(all(target_os = "linux", target_env = "gnu"))))]
+type Resource = libc::c_int;
+
+/// Resource names accepted by `--rlimit`, as in prlimit(1)
+#[cfg(unix)]
+const RESOURCES: [(&str, Resource); 10] = [
+    ("as", libc::RLIMIT_AS),
+    ("core", libc::RLIMIT_CORE),
+    ("cpu", libc::RLIMIT_CPU),
+    ("data", libc::RLIMIT_DATA),
+    ("fsize", libc::RLIMIT_FSIZE),
+    ("memlock", libc::RLIMIT_MEMLOCK),
+    ("nofile", libc::RLIMIT_NOFILE),
+    ("nproc", libc::RLIMIT_NPROC),
+    ("rss", libc::RLIMIT_RSS),
+    ("stack", libc::RLIMIT_STACK),
+];
+
+#[cfg(unix)]
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Rlimit {
+    name: String,
+    resource: Resource,
+    soft: libc::rlim_t,
+    hard: libc::rlim_t,
+}
+
+/// The user and group to switch to
+#[cfg(unix)]
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Credentials {
+    uid: Option<libc::uid_t>,
+    gid: libc::gid_t,
+    /// Name of the user, whose supplementary groups are set up
+    user_name: Option<CString>,
+}
+
+#[cfg(unix)]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct ProcessSetup {
+    /// Added to the niceness, as with nice(1)
+    nice: Option<i32>,
+    umask: Option<libc::mode_t>,
+    rlimits: Vec<Rlimit>,
+    credentials: Option<Credentials>,
+    sandbox: Sandbox,
+}
+
+#[cfg(unix)]
+impl ProcessSetup {
+    pub(crate) fn new(
+        nice: Option<i32>,
+        umask: Option<&str>,
+        rlimits: &[String],
+    ) -> Result<Self, Error> {
+        let umask = umask
+            .map(|mode| {
+                libc::mode_t::from_str_radix(mode, 8)
+                    .ok()
+                    .filter(|&mode| mode <= 0o777)
+                    .ok_or_else(|| Error::Usage(format!("invalid --umask, expected octal: {mode}")))
+            })
+            .transpose()?;
+        let rlimits = rlimits
+            .iter()
+            .map(|rlimit| parse_rlimit(rlimit))
+            .collect::<Result<_, _>>()?;
+        Ok(ProcessSetup {
+            nice,
+            umask,
+            rlimits,
+            credentials: None,
+            sandbox: Sandbox::default(),
+        })
+    }
+
+    /// Switch to `user` and `group`, given by name or number. The group defaults to the primary
+    /// group of the user.
+    pub(crate) fn with_credentials(
+        mut self,
+        user: Option<&str>,
+        group: Option<&str>,
+    ) -> Result<Self, Error> {
+        if user.is_none() && group.is_none() {
+            return Ok(self);
+        }
+        let passwd = user
+            .map(|user| {
+                let passwd = lookup_user(user).map_err(|err| format!("--user {user}: {err}"))?;
+                passwd.ok_or_else(|| Error::Usage(format!("--user {user}: no such user")))
+            })
+            .transpose()?;
+        let gid = match (group, &passwd) {
+            (Some(group), _) => lookup_group(group)
+                .map_err(|err| format!("--group {group}: {err}"))?
+                .ok_or_else(|| Error::Usage(format!("--group {group}: no such group")))?,
+            (None, Some(passwd)) => passwd.gid,
+            (None, None) => unreachable!("a user or a group is given"),
+        };
+        self.credentials = Some(Credentials {
+            uid: passwd.as_ref().map(|passwd| passwd.uid),
+            gid,
+            user_name: passwd.map(|passwd| passwd.name),
+        });
+        Ok(self)
+    }
+
+    /// Isolate COMMAND with `sandbox`
+    pub(crate) fn with_sandbox(mut self, sandbox: Sandbox) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+
+    pub(crate) fn switches_user(&self) -> bool {
+        self.credentials.is_some()
+    }
+
+    /// Apply the attributes to the current process. Suitable for `pre_exec`, as it only makes
+    /// system calls unless one of them fails.
+    pub(crate) fn apply(&self) -> io::Result<()> {
+        let context =
+            |option: &str, err: io::Error| io::Error::new(err.kind(), format!("{option}: {err}"));
+        // Namespaces and cgroups first, as they may need privileges dropped below
+        self.sandbox.apply()?;
+        if let Some(increment) = self.nice {
+            // SAFETY: plain system calls on the current process
+            let result = unsafe {
+                let current = libc::getpriority(libc::PRIO_PROCESS, 0);
+                libc::setpriority(libc::PRIO_PROCESS, 0, (current + increment).clamp(-20, 19))
+            };
+            if result == -1 {
+                return Err(context("--nice", io::Error::last_os_error()));
+            }
+        }
+        if let Some(mask) = self.umask {
+            // SAFETY: umask can't fail
+            unsafe { libc::umask(mask) };
+        }
+        for rlimit in &self.rlimits {
+            let limit = libc::rlimit {
+                rlim_cur: rlimit.soft,
+                rlim_max: rlimit.hard,
+            };
+            // SAFETY: `limit` is a valid rlimit for the duration of the call
+            if unsafe { libc::setrlimit(rlimit.resource, &limit) } == -1 {
+                let option = format!("--rlimit {}", rlimit.name);
+                return Err(context(&option, io::Error::last_os_error()));
+            }
+        }
+        // Privileges are dropped last, as the settings above may need them
+        if let Some(credentials) = &self.credentials {
+            let gid = credentials.gid;
+            // SAFETY: plain system calls, with a valid C string and a one element array
+            let result = unsafe {
+                match &credentials.user_name {
+                    Some(name) => libc::initgroups(name.as_ptr(), gid as _),
+                    None => libc::setgroups(1, &gid),
+                }
+            };
+            if result == -1 {
+                return Err(context("supplementary groups", io::Error::last_os_error()));
+            }
+            // SAFETY: plain system call
+            if unsafe { libc::setgid(gid) } == -1 {
+                return Err(context("--group", io::Error::last_os_error()));
+            }
+            if let Some(uid) = credentials.uid {
+                // SAFETY: plain system call
+                if unsafe { libc::setuid(uid) } == -1 {
+                    return Err(context("--user", io::Error::last_os_error()));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+struct Passwd {
+    uid: libc::uid_t,
+    gid: libc::gid_t,
+    name: CString,
+}
+
+/// Size of the buffer for the strings of passwd and group entries
+#[cfg(unix)]
+const ENTRY_BUFFER_SIZE: usize = 16 * 1024;
+
+/// The passwd entry of a user name or number, if there is one
+#[cfg(unix)]
+fn lookup_user(user: &str) -> io::Result<Option<Passwd>> {
+    // SAFETY: passwd is a plain C struct, for which all zeroes is valid
+    let mut passwd: libc::passwd = unsafe { mem::zeroed() };
+    let mut buffer = vec![0; ENTRY_BUFFER_SIZE];
+    let mut result = ptr::null_mut();
+    // SAFETY: the pointers are valid for the duration of the calls, with the buffer length
+    let status = match user.parse::<libc::uid_t>() {
+        Ok(uid) => unsafe {
+            libc::getpwuid_r(
+                uid,
+                &mut passwd,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            )
+        },
+        Err(_) => {
+            let name = CString::new(user)?;
+            unsafe {
+                libc::getpwnam_r(
+                    name.as_ptr(),
+                    &mut passwd,
+                    buffer.as_mut_ptr(),
+                    buffer.len(),
+                    &mut result,
+                )
+            }
+        }
+    };
+    if status != 0 {
+        return Err(io::Error::from_raw_os_error(status));
+    }
+    if result.is_null() {
+        return Ok(None);
+    }
+    Ok(Some(Passwd {
+        uid: passwd.pw_uid,
+        gid: passwd.pw_gid,
+        // SAFETY: a found entry has a valid name, pointing into the buffer
+        name: unsafe { CStr::from_ptr(passwd.pw_name) }.to_owned(),
+    }))
+}
+
+/// The number of a group name, or the number itself, which needn't have a group entry
+#[cfg(unix)]
+fn lookup_group(group: &str) -> io::Result<Option<libc::gid_t>> {
+    if let Ok(gid) = group.parse() {
+        return Ok(Some(gid));
+    }
+    let name = CString::new(group)?;
+    // SAFETY: group is a plain C struct, for which all zeroes is valid
+    let mut entry: libc::group = unsafe { mem::zeroed() };
+    let mut buffer = vec![0; ENTRY_BUFFER_SIZE];
+    let mut result = ptr::null_mut();
+    // SAFETY: the pointers are valid for the duration of the call, with the buffer length
+    let status = unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if status != 0 {
+        return Err(io::Error::from_raw_os_error(status));
+    }
+    Ok((!result.is_null()).then_some(entry.gr_gid))
+}
+
+/// Parse `RESOURCE=SOFT[:HARD]`, where a single limit sets both and `unlimited` lifts it
+#[cfg(unix)]
+fn parse_rlimit(s: &str) -> Result<Rlimit, Error> {
+    let invalid = || {
+        Error::Usage(format!(
+            "invalid --rlimit, expected RESOURCE=SOFT[:HARD]: {s}"
+        ))
+    };
+    let (name, limits) = s.split_once('=').ok_or_else(invalid)?;
+    let name = name.trim().to_ascii_lowercase();
+    let name = name.strip_prefix("rlimit_").unwrap_or(&name);
+    let &(name, resource) = RESOURCES
+        .iter()
+        .find(|(resource_name, _)| *resource_name == name)
+        .ok_or_else(|| Error::Usage(format!("unknown --rlimit resource: {name}")))?;
+    let limit = |value: &str| match value.trim() {
+        "unlimited" | "infinity" => Ok(libc::RLIM_INFINITY),
+        value => value.parse().map_err(|_| invalid()),
+    };
+    let (soft, hard) = match limits.split_once(':') {
+        Some((soft, hard)) => (limit(soft)?, limit(hard)?),
+        None => (limit(limits)?, limit(limits)?),
+    };
+    if soft > hard {
+        return Err(Error::Usage(format!(
+            "invalid --rlimit, soft limit above hard limit: {s}"
+        )));
+    }
+    Ok(Rlimit {
+        name: name.to_owned(),
+        resource,
+        soft,
+        hard,
+    })
+}
+
+/// Without Unix, there are no such attributes, and asking for any is refused
+#[cfg(not(unix))]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct ProcessSetup {
+    sandbox: Sandbox,
+}
+
+#[cfg(not(unix))]
+impl ProcessSetup {
+    pub(crate) fn new(
+        nice: Option<i32>,
+        umask: Option<&str>,
+        rlimits: &[String],
+    ) -> Result<Self, Error> {
+        if nice.is_some() || umask.is_some() || !rlimits.is_empty() {
+            return Err(Error::Usage(
+                "--nice, --umask and --rlimit are only supported on Unix".to_owned(),
+            ));
+        }
+        Ok(ProcessSetup::default())
+    }
+
+    pub(crate) fn with_credentials(
+        self,
+        user: Option<&str>,
+        group: Option<&str>,
+    ) -> Result<Self, Error> {
+        if user.is_some() || group.is_some() {
+            return Err(Error::Usage(
+                "--user and --group are only supported on Unix".to_owned(),
+            ));
+        }
+        Ok(self)
+    }
+
+    pub(crate) fn with_sandbox(mut self, sandbox: Sandbox) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+
+    pub(crate) fn switches_user(&self) -> bool {
+        false
+    }
+
+    pub(crate) fn apply(&self) -> io::Result<()> {
+        self.sandbox.apply()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_process_setup() {
+        let rlimits = ["nofile=1024:4096".to_owned(), "RLIMIT_CORE=0".to_owned()];
+        let setup = ProcessSetup::new(Some(5), Some("027"), &rlimits).unwrap();
+        assert_eq!(setup.umask, Some(0o027));
+        assert_eq!((setup.rlimits[0].soft, setup.rlimits[0].hard), (1024, 4096));
+        assert_eq!((setup.rlimits[1].soft, setup.rlimits[1].hard), (0, 0));
+        assert_eq!(
+            parse_rlimit("stack=8388608:unlimited").unwrap().hard,
+            libc::RLIM_INFINITY
+        );
+        assert_eq!(
+            ProcessSetup::new(None, None, &[]).unwrap(),
+            ProcessSetup::default()
+        );
+        assert!(ProcessSetup::new(None, Some("999"), &[]).is_err());
+        for invalid in ["nofile", "bogus=1", "nofile=x", "nofile=10:5"] {
+            assert!(parse_rlimit(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_credentials() {
+        let root = ProcessSetup::default()
+            .with_credentials(Some("root"), None)
+            .unwrap()
+            .credentials
+            .unwrap();
+        assert_eq!((root.uid, root.gid), (Some(0), 0));
+        assert_eq!(root.user_name.as_deref(), Some(c"root"));
+        let by_number = ProcessSetup::default()
+            .with_credentials(Some("0"), Some("12345"))
+            .unwrap()
+            .credentials
+            .unwrap();
+        assert_eq!((by_number.uid, by_number.gid), (Some(0), 12345));
+        assert!(ProcessSetup::default()
+            .with_credentials(Some("no-such-user-xyzzy"), None)
+            .is_err());
+        assert!(!ProcessSetup::default()
+            .with_credentials(None, None)
+            .unwrap()
+            .switches_user());
+    }
+}
@@ -0,0 +1,358 @@
+//! Environments fetched from a secrets service, with `--remote NAME`
+//!
+//! A remote is configured by a `[remote.NAME]` section of the `.enwrc`:
+//!
+//! ```text
+//! [remote.prod]
+//! url = https://secrets.example.com/api
+//! project = backend
+//! environment = production
+//! # The variable holding the access token, ENW_REMOTE_TOKEN by default
+//! token-env = SECRETS_TOKEN
+//! # How long the fetched environment is cached with --cache, in seconds
+//! cache-ttl = 300
+//! ```
+//!
+//! The environment is read with `GET URL/projects/PROJECT/environments/ENVIRONMENT/secrets`,
+//! which returns a JSON object of the variables. Nested values are flattened as with
+//! `--from-json`. Fetching needs enw to be built with the `remote` feature.
+
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use crate::{
+    cache,
+    log::log,
+    parse::Lookup,
+    source::{EnvSource, Prefetched},
+    timings::{self, Step},
+    Error,
+};
+
+/// The variable holding the access token unless the remote sets `token-env`
+pub const DEFAULT_TOKEN_VAR: &str = "ENW_REMOTE_TOKEN";
+
+/// How long a fetched environment is cached unless the remote sets `cache-ttl`
+const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(300);
+
+/// A secrets manager holding the environment of a project
+pub trait SecretsBackend: Send + Sync {
+    /// Short description used in diagnostics and to key the cache, e.g. the URL fetched
+    fn name(&self) -> String;
+
+    /// Fetch the variables of the environment
+    fn fetch(&self) -> Result<Vec<(String, String)>, Error>;
+}
+
+/// A secrets service reached over HTTP with a bearer token
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HttpBackend {
+    url: String,
+    project: String,
+    environment: String,
+    token_var: String,
+    cache_ttl: Duration,
+}
+
+impl HttpBackend {
+    pub fn new(
+        url: impl Into<String>,
+        project: impl Into<String>,
+        environment: impl Into<String>,
+    ) -> Self {
+        HttpBackend {
+            url: url.into(),
+            project: project.into(),
+            environment: environment.into(),
+            token_var: DEFAULT_TOKEN_VAR.to_owned(),
+            cache_ttl: DEFAULT_CACHE_TTL,
+        }
+    }
+
+    /// Read the access token from the variable `token_var`
+    pub fn with_token_var(mut self, token_var: impl Into<String>) -> Self {
+        self.token_var = token_var.into();
+        self
+    }
+
+    /// The backend configured by the settings of a `[remote.NAME]` section
+    pub(crate) fn from_settings(
+        name: &str,
+        settings: &HashMap<String, String>,
+    ) -> Result<Self, Error> {
+        let setting = |key: &str| {
+            settings
+                .get(key)
+                .ok_or_else(|| Error::Other(format!("remote {name}: {key} is not set")))
+        };
+        if let Some(key) = settings.keys().find(|key| {
+            !matches!(
+                key.as_str(),
+                "url" | "project" | "environment" | "token-env" | "cache-ttl"
+            )
+        }) {
+            return Err(Error::Other(format!(
+                "remote {name}: unknown setting {key}"
+            )));
+        }
+        let mut backend = HttpBackend::new(
+            setting("url")?.trim_end_matches('/'),
+            setting("project")?,
+            setting("environment")?,
+        );
+        if let Some(token_var) = settings.get("token-env") {
+            backend = backend.with_token_var(token_var);
+        }
+        if let Some(ttl) = settings.get("cache-ttl") {
+            let seconds = ttl.parse().map_err(|_| {
+                Error::Other(format!(
+                    "remote {name}: cache-ttl: expected seconds, got {ttl}"
+                ))
+            })?;
+            backend.cache_ttl = Duration::from_secs(seconds);
+        }
+        Ok(backend)
+    }
+
+    /// How long the fetched environment is cached with `--cache`
+    pub(crate) fn cache_ttl(&self) -> Duration {
+        self.cache_ttl
+    }
+
+    fn secrets_url(&self) -> String {
+        format!(
+            "{}/projects/{}/environments/{}/secrets",
+            self.url, self.project, self.environment
+        )
+    }
+}
+
+impl SecretsBackend for HttpBackend {
+    fn name(&self) -> String {
+        self.secrets_url()
+    }
+
+    #[cfg(feature = "remote")]
+    fn fetch(&self) -> Result<Vec<(String, String)>, Error> {
+        use serde_json::Value;
+
+        let token = std::env::var(&self.token_var)
+            .map_err(|_| Error::Other(format!("{} is not set", self.token_var)))?;
+        let response = ureq::get(&self.secrets_url())
+            .set("Authorization", &format!("Bearer {token}"))
+            .set("Accept", "application/json")
+            .call()
+            .map_err(|err| match err {
+                ureq::Error::Status(status, response) => Error::Other(format!(
+                    "the secrets service answered {status} {}",
+                    response.status_text()
+                )),
+                err => Error::Other(err.to_string()),
+            })?;
+        let body = response.into_string()?;
+        let value: Value = serde_json::from_str(&body)
+            .map_err(|err| Error::Other(format!("invalid JSON: {err}")))?;
+        if !value.is_object() {
+            return Err("expected a JSON object".into());
+        }
+        let mut vars = Vec::new();
+        crate::source::flatten_json(String::new(), value, "_", &mut vars)?;
+        Ok(vars)
+    }
+
+    #[cfg(not(feature = "remote"))]
+    fn fetch(&self) -> Result<Vec<(String, String)>, Error> {
+        Err("enw was built without support for remotes, rebuild it with --features remote".into())
+    }
+}
+
+/// The environment of a [`SecretsBackend`], optionally cached
+pub struct RemoteSource {
+    backend: Box<dyn SecretsBackend>,
+    cache_ttl: Option<Duration>,
+    /// The environment fetched by `prefetch`, taken by `load`
+    prefetched: Prefetched<Vec<(String, String)>>,
+}
+
+impl RemoteSource {
+    pub fn new(backend: impl SecretsBackend + 'static) -> Self {
+        RemoteSource {
+            backend: Box::new(backend),
+            cache_ttl: None,
+            prefetched: Mutex::new(None),
+        }
+    }
+
+    /// Reuse an environment fetched at most `ttl` ago, see [`crate::cache`]
+    pub fn cached(mut self, ttl: Option<Duration>) -> Self {
+        self.cache_ttl = ttl;
+        self
+    }
+
+    fn fetch(&self) -> Result<Vec<(String, String)>, Error> {
+        let name = self.backend.name();
+        if let Some(ttl) = self.cache_ttl {
+            if let Some(vars) = cache::get_fetched(&name, ttl) {
+                log!(Debug, "{name}: using the cached environment");
+                return Ok(vars);
+            }
+        }
+        let vars = timings::time(Step::Fetch, || name.clone(), || self.backend.fetch())?;
+        log!(Info, "{name}: fetched {} variables", vars.len());
+        if self.cache_ttl.is_some() {
+            cache::put_fetched(&name, &vars);
+        }
+        Ok(vars)
+    }
+}
+
+impl EnvSource for RemoteSource {
+    fn name(&self) -> String {
+        self.backend.name()
+    }
+
+    fn load(&self, _lookup: Lookup) -> Result<Vec<(String, String)>, Error> {
+        let prefetched = self
+            .prefetched
+            .lock()
+            .ok()
+            .and_then(|mut prefetched| prefetched.take());
+        prefetched.unwrap_or_else(|| self.fetch())
+    }
+
+    fn is_secret(&self) -> bool {
+        true
+    }
+
+    fn prefetch(&self) {
+        let vars = self.fetch();
+        if let Ok(mut prefetched) = self.prefetched.lock() {
+            *prefetched = Some(vars);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockBackend(Result<Vec<(String, String)>, String>);
+
+    impl SecretsBackend for MockBackend {
+        fn name(&self) -> String {
+            "mock".to_owned()
+        }
+
+        fn fetch(&self) -> Result<Vec<(String, String)>, Error> {
+            self.0.clone().map_err(Error::Other)
+        }
+    }
+
+    #[test]
+    fn test_remote_source() {
+        let vars = vec![("API_KEY".to_owned(), "secret".to_owned())];
+        let source = RemoteSource::new(MockBackend(Ok(vars.clone())));
+        assert_eq!(source.load(&|_| None).unwrap(), vars);
+        assert!(RemoteSource::new(MockBackend(Err("denied".to_owned())))
+            .load(&|_| None)
+            .is_err());
+
+        let mut settings: HashMap<String, String> = [
+            ("url", "https://secrets.example.com/api/"),
+            ("project", "backend"),
+            ("environment", "production"),
+            ("cache-ttl", "60"),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .collect();
+        let backend = HttpBackend::from_settings("prod", &settings).unwrap();
+        assert_eq!(
+            backend.name(),
+            "https://secrets.example.com/api/projects/backend/environments/production/secrets"
+        );
+        assert_eq!(backend.cache_ttl(), Duration::from_secs(60));
+        settings.insert("token".to_owned(), "secret".to_owned());
+        assert!(HttpBackend::from_settings("prod", &settings).is_err());
+        settings.remove("token");
+        settings.remove("project");
+        assert!(HttpBackend::from_settings("prod", &settings).is_err());
+    }
+
+    struct SlowBackend(&'static str);
+
+    impl SecretsBackend for SlowBackend {
+        fn name(&self) -> String {
+            self.0.to_owned()
+        }
+
+        fn fetch(&self) -> Result<Vec<(String, String)>, Error> {
+            std::thread::sleep(Duration::from_millis(300));
+            Ok(vec![(self.0.to_owned(), "1".to_owned())])
+        }
+    }
+
+    #[test]
+    fn test_concurrent_fetch() {
+        let mut registry = crate::SourceRegistry::new();
+        for name in ["C", "A", "B"] {
+            registry.push(RemoteSource::new(SlowBackend(name)));
+        }
+        let started = std::time::Instant::now();
+        let vars = registry.resolve(&|_| None).unwrap();
+        assert!(started.elapsed() < Duration::from_millis(600));
+        assert_eq!(vars.keys().collect::<Vec<_>>(), ["C", "A", "B"]);
+    }
+
+    #[cfg(feature = "remote")]
+    #[test]
+    fn test_http_backend() {
+        use std::{
+            io::{BufRead, BufReader, Write},
+            net::TcpListener,
+            thread,
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            for line in BufReader::new(&stream).lines() {
+                let line = line.unwrap();
+                if line.is_empty() {
+                    break;
+                }
+                request.push(line);
+            }
+            let body = r#"{"API_KEY": "secret", "DB": {"HOST": "db", "PORT": 5432}}"#;
+            write!(
+                &stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            )
+            .unwrap();
+            request
+        });
+        std::env::set_var("ENW_TEST_REMOTE_TOKEN", "t0ken");
+        let backend =
+            HttpBackend::new(url, "backend", "dev").with_token_var("ENW_TEST_REMOTE_TOKEN");
+        let vars = backend.fetch().unwrap();
+        assert_eq!(
+            vars,
+            [
+                ("API_KEY".to_owned(), "secret".to_owned()),
+                ("DB_HOST".to_owned(), "db".to_owned()),
+                ("DB_PORT".to_owned(), "5432".to_owned()),
+            ]
+        );
+        let request = server.join().unwrap();
+        assert_eq!(
+            request[0],
+            "GET /projects/backend/environments/dev/secrets HTTP/1.1"
+        );
+        assert!(request
+            .iter()
+            .any(|line| line.eq_ignore_ascii_case("authorization: Bearer t0ken")));
+    }
+}
@@ -0,0 +1,113 @@
+//! Coloring of diagnostics: errors red, warnings yellow and provenance dimmed
+//!
+//! Colors are used when the stream is a terminal, following the `NO_COLOR` and
+//! `CLICOLOR`/`CLICOLOR_FORCE` conventions: `NO_COLOR` disables them, `CLICOLOR=0` disables
+//! them too, and `CLICOLOR_FORCE` enables them even when the stream isn't a terminal.
+
+use std::{
+    borrow::Cow,
+    env,
+    fmt::Display,
+    io::{self, IsTerminal},
+    sync::OnceLock,
+};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Stream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Style {
+    Error,
+    Warning,
+    /// Where something comes from, less important than the message itself
+    Dim,
+}
+
+impl Style {
+    fn sgr(self) -> &'static str {
+        match self {
+            Style::Error => "1;31",
+            Style::Warning => "1;33",
+            Style::Dim => "2",
+        }
+    }
+}
+
+/// Whether colors are used, given the values of `NO_COLOR`, `CLICOLOR` and `CLICOLOR_FORCE`,
+/// and whether the stream is a terminal
+fn use_color(
+    no_color: Option<&str>,
+    clicolor: Option<&str>,
+    clicolor_force: Option<&str>,
+    is_terminal: bool,
+) -> bool {
+    let is_set = |value: Option<&str>| value.is_some_and(|value| !value.is_empty());
+    if is_set(no_color) {
+        false
+    } else if is_set(clicolor_force) && clicolor_force != Some("0") {
+        true
+    } else {
+        clicolor != Some("0") && is_terminal
+    }
+}
+
+fn enabled(stream: Stream) -> bool {
+    static STDOUT: OnceLock<bool> = OnceLock::new();
+    static STDERR: OnceLock<bool> = OnceLock::new();
+    let (cell, is_terminal): (_, fn() -> bool) = match stream {
+        Stream::Stdout => (&STDOUT, || io::stdout().is_terminal()),
+        Stream::Stderr => (&STDERR, || io::stderr().is_terminal()),
+    };
+    *cell.get_or_init(|| {
+        let var = |name| env::var(name).ok();
+        let dumb = var("TERM").as_deref() == Some("dumb");
+        use_color(
+            var("NO_COLOR").as_deref(),
+            var("CLICOLOR").as_deref(),
+            var("CLICOLOR_FORCE").as_deref(),
+            is_terminal() && !dumb,
+        )
+    })
+}
+
+/// `text` in `style`, if colors are enabled for `stream`
+pub(crate) fn paint(stream: Stream, style: Style, text: &str) -> Cow<'_, str> {
+    if enabled(stream) {
+        Cow::Owned(format!("\x1b[{}m{text}\x1b[0m", style.sgr()))
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+/// Print a warning to stderr
+pub(crate) fn warning(message: impl Display) {
+    eprintln!(
+        "{}: {message}",
+        paint(Stream::Stderr, Style::Warning, "warning")
+    );
+}
+
+/// Print the error that ends enw to stderr
+pub(crate) fn error(message: impl Display) {
+    eprintln!("{}: {message}", paint(Stream::Stderr, Style::Error, "enw"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_use_color() {
+        assert!(use_color(None, None, None, true));
+        assert!(!use_color(None, None, None, false));
+        assert!(!use_color(Some("1"), None, None, true));
+        assert!(use_color(Some(""), None, None, true));
+        assert!(!use_color(None, Some("0"), None, true));
+        assert!(use_color(None, Some("1"), Some("1"), false));
+        assert!(!use_color(None, None, Some("0"), false));
+        assert!(!use_color(Some("1"), None, Some("1"), true));
+    }
+}
@@ -0,0 +1,174 @@
+//! Resolution of the environment from its sources, with hooks transforming the values
+//!
+//! ```
+//! use enw::{Error, PairsSource, Resolver, SourceRegistry};
+//!
+//! let mut registry = SourceRegistry::new();
+//! registry.push(PairsSource::new(vec![
+//!     ("NAME".to_owned(), " enw ".to_owned()),
+//!     ("PORT".to_owned(), "8080".to_owned()),
+//! ]));
+//! let mut resolver = Resolver::new(registry);
+//! resolver
+//!     .pre_hook("NAME", |_, value, _| Ok(value.trim().to_owned()))
+//!     .post_hook("PORT", |key, value, _| match value.parse::<u16>() {
+//!         Ok(_) => Ok(value),
+//!         Err(_) => Err(Error::Other(format!("{key} is not a port"))),
+//!     });
+//! let env_vars = resolver.resolve(&|_| None).unwrap();
+//! assert_eq!(env_vars["NAME"], "enw");
+//! ```
+
+use indexmap::IndexMap;
+
+use crate::{glob_match, parse::Lookup, source::Resolved, Error, SourceRegistry, Warning};
+
+/// Transforms the value of the variable named by the first argument. The lookup resolves the
+/// other variables, as they are at the time the hook runs.
+pub type Hook = dyn Fn(&str, String, Lookup) -> Result<String, Error>;
+
+/// Hooks with the key patterns they apply to
+pub(crate) type Hooks = [(String, Box<Hook>)];
+
+/// The sources of a [`SourceRegistry`] resolved with hooks applied to the variables whose names
+/// match their patterns, in the order the hooks were added
+///
+/// Pre hooks transform each value as its source defines it, before the sources loaded after it
+/// can reference it, e.g. to decrypt it. Post hooks transform the resolved values, e.g. to
+/// validate them; their lookup sees the values before any post hook ran.
+#[derive(Default)]
+pub struct Resolver {
+    registry: SourceRegistry,
+    pre_hooks: Vec<(String, Box<Hook>)>,
+    post_hooks: Vec<(String, Box<Hook>)>,
+}
+
+impl Resolver {
+    pub fn new(registry: SourceRegistry) -> Self {
+        Resolver {
+            registry,
+            ..Default::default()
+        }
+    }
+
+    /// The sources and the policies applied to them
+    pub fn registry(&mut self) -> &mut SourceRegistry {
+        &mut self.registry
+    }
+
+    /// Add a hook applied to each value defined for the variables matching `pattern`, which may
+    /// contain `*` and `?` wildcards
+    pub fn pre_hook(
+        &mut self,
+        pattern: impl Into<String>,
+        hook: impl Fn(&str, String, Lookup) -> Result<String, Error> + 'static,
+    ) -> &mut Self {
+        self.pre_hooks.push((pattern.into(), Box::new(hook)));
+        self
+    }
+
+    /// Add a hook applied to the resolved value of the variables matching `pattern`
+    pub fn post_hook(
+        &mut self,
+        pattern: impl Into<String>,
+        hook: impl Fn(&str, String, Lookup) -> Result<String, Error> + 'static,
+    ) -> &mut Self {
+        self.post_hooks.push((pattern.into(), Box::new(hook)));
+        self
+    }
+
+    /// Resolve the environment as [`SourceRegistry::resolve`] does, running the hooks
+    pub fn resolve(&self, fallback: Lookup) -> Result<IndexMap<String, String>, Error> {
+        self.resolve_with_warnings(fallback, &mut Vec::new())
+    }
+
+    /// Like [`resolve`](Self::resolve), adding warnings to `warnings` as
+    /// [`SourceRegistry::resolve_with_warnings`] does
+    pub fn resolve_with_warnings(
+        &self,
+        fallback: Lookup,
+        warnings: &mut Vec<Warning>,
+    ) -> Result<IndexMap<String, String>, Error> {
+        let (env_vars, _) = self.resolve_with_origins(fallback, warnings)?;
+        Ok(env_vars)
+    }
+
+    /// Like [`resolve_with_warnings`](Self::resolve_with_warnings), also returning the secret
+    /// source that variables were last defined by, see [`crate::EnvSource::is_secret`]
+    pub(crate) fn resolve_with_origins(
+        &self,
+        fallback: Lookup,
+        warnings: &mut Vec<Warning>,
+    ) -> Result<Resolved, Error> {
+        let (resolved, secret_origins) =
+            self.registry
+                .resolve_hooked(fallback, warnings, &self.pre_hooks)?;
+        if self.post_hooks.is_empty() {
+            return Ok((resolved, secret_origins));
+        }
+        let lookup = |key: &str| resolved.get(key).cloned().or_else(|| fallback(key));
+        let mut env_vars = IndexMap::with_capacity(resolved.len());
+        for (key, value) in &resolved {
+            let value = run_hooks(&self.post_hooks, key, value.clone(), &lookup)?;
+            env_vars.insert(key.clone(), value);
+        }
+        Ok((env_vars, secret_origins))
+    }
+}
+
+/// Pass `value` through the hooks matching `key`
+pub(crate) fn run_hooks(
+    hooks: &Hooks,
+    key: &str,
+    value: String,
+    lookup: Lookup,
+) -> Result<String, Error> {
+    hooks
+        .iter()
+        .filter(|(pattern, _)| glob_match(pattern, key))
+        .try_fold(value, |value, (_, hook)| hook(key, value, lookup))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PairsSource;
+
+    fn pairs(pairs: &[(&str, &str)]) -> PairsSource {
+        PairsSource::new(
+            pairs
+                .iter()
+                .map(|&(key, value)| (key.to_owned(), value.to_owned()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_hooks() {
+        let mut registry = SourceRegistry::new();
+        registry
+            .push(pairs(&[("SECRET", "terces"), ("NAME", "app")]))
+            .push(pairs(&[("COPY", "unused"), ("URL", "http://host")]));
+        let mut resolver = Resolver::new(registry);
+        resolver
+            // Pre hooks run before later sources see the values
+            .pre_hook("SECRET", |_, value, _| Ok(value.chars().rev().collect()))
+            .pre_hook("COPY", |_, _, lookup| {
+                Ok(lookup("SECRET").unwrap_or_default())
+            })
+            .post_hook("*", |_, value, _| Ok(value.to_uppercase()))
+            .post_hook("NAME", |_, value, lookup| {
+                Ok(format!("{value}@{}", lookup("URL").unwrap_or_default()))
+            });
+        let env_vars = resolver.resolve(&|_| None).unwrap();
+        assert_eq!(env_vars["SECRET"], "SECRET");
+        assert_eq!(env_vars["COPY"], "SECRET");
+        assert_eq!(env_vars["NAME"], "APP@http://host");
+
+        resolver.post_hook("URL", |key, value, _| match value.starts_with("https:") {
+            true => Ok(value),
+            false => Err(Error::Other(format!("{key} has to use https"))),
+        });
+        assert!(resolver.resolve(&|_| None).is_err());
+    }
+}
@@ -0,0 +1,174 @@
+//! Re-running COMMAND when it fails, with `--retries N`
+//!
+//! Rather than replacing itself with COMMAND, enw then supervises it, running it as a child and
+//! waiting for it.
+//! COMMAND is run again after exiting with a nonzero status, up to N more times, waiting
+//! `--retry-delay` before the first retry and `--retry-backoff` times longer before each
+//! following one. A COMMAND killed by a signal, e.g. by Ctrl-C, isn't retried.
+
+use std::{process::ExitStatus, thread, time::Duration};
+
+use crate::{os::exit_signal, Error};
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct RetryPolicy {
+    retries: u32,
+    delay: Duration,
+    backoff: f64,
+    /// Whether the env files are read again before each retry
+    reload: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            retries: 0,
+            delay: Duration::from_secs(1),
+            backoff: 2.0,
+            reload: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub(crate) fn new(retries: u32, delay: Option<Duration>, backoff: Option<f64>) -> Self {
+        let default = RetryPolicy::default();
+        RetryPolicy {
+            retries,
+            delay: delay.unwrap_or(default.delay),
+            backoff: backoff.unwrap_or(default.backoff),
+            reload: false,
+        }
+    }
+
+    pub(crate) fn reloading(mut self, reload: bool) -> Self {
+        self.reload = reload;
+        self
+    }
+
+    /// Whether COMMAND is supervised rather than executed in place of enw
+    #[cfg_attr(feature = "no-exec", allow(dead_code))]
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.retries > 0
+    }
+
+    /// The delay before retry number `retry`, counting from 1
+    fn delay_before(&self, retry: u32) -> Duration {
+        let factor = self
+            .backoff
+            .powi(retry.saturating_sub(1).try_into().unwrap_or(i32::MAX));
+        Duration::try_from_secs_f64(self.delay.as_secs_f64() * factor).unwrap_or(Duration::MAX)
+    }
+
+    /// Make attempts until one succeeds or the retries are used up, returning the exit code of
+    /// the last. `attempt` is told whether to reload the environment, and `report` about each
+    /// retry.
+    pub(crate) fn run(
+        &self,
+        mut attempt: impl FnMut(bool) -> Result<ExitStatus, Error>,
+        mut report: impl FnMut(&str),
+    ) -> Result<i32, Error> {
+        let mut retry = 0;
+        loop {
+            let status = attempt(retry > 0 && self.reload)?;
+            let code = match (status.code(), exit_signal(&status)) {
+                (Some(code), _) => code,
+                (None, Some(signal)) => return Ok(128 + signal),
+                (None, None) => return Ok(1),
+            };
+            if code == 0 || retry == self.retries {
+                return Ok(code);
+            }
+            retry += 1;
+            let delay = self.delay_before(retry);
+            report(&format!(
+                "exited with status {code}, retrying in {} ({retry} of {})",
+                format_duration(delay),
+                self.retries
+            ));
+            thread::sleep(delay);
+        }
+    }
+}
+
+/// Parse a duration such as `500ms`, `2s`, `1.5m` or `1h`, in seconds without a unit
+pub(crate) fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("expected a duration such as 500ms or 2s, got {s:?}"))?;
+    let seconds = match unit {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        unit => return Err(format!("unknown unit {unit:?}, expected ms, s, m or h")),
+    };
+    Duration::try_from_secs_f64(seconds).map_err(|err| err.to_string())
+}
+
+fn format_duration(duration: Duration) -> String {
+    match duration.as_millis() {
+        millis if millis < 1000 => format!("{millis}ms"),
+        _ => format!("{:.1}s", duration.as_secs_f64()).replace(".0s", "s"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("2"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_duration("1.5m"), Ok(Duration::from_secs(90)));
+        assert!(parse_duration("1d").is_err());
+        assert!(parse_duration("s").is_err());
+        assert_eq!(format_duration(Duration::from_millis(250)), "250ms");
+        assert_eq!(format_duration(Duration::from_secs(4)), "4s");
+        assert_eq!(format_duration(Duration::from_millis(1500)), "1.5s");
+    }
+
+    #[test]
+    fn test_retries() {
+        let policy = RetryPolicy::new(3, Some(Duration::from_millis(1)), None);
+        assert_eq!(policy.delay_before(1), Duration::from_millis(1));
+        assert_eq!(policy.delay_before(3), Duration::from_millis(4));
+
+        let mut reloads = Vec::new();
+        let mut reports = 0;
+        let code = policy
+            .clone()
+            .reloading(true)
+            .run(
+                |reload| {
+                    reloads.push(reload);
+                    Ok(Command::new("sh").args(["-c", "exit 3"]).status()?)
+                },
+                |_| reports += 1,
+            )
+            .unwrap();
+        assert_eq!(code, 3);
+        assert_eq!(reloads, [false, true, true, true]);
+        assert_eq!(reports, 3);
+
+        let mut attempts = 0;
+        let code = policy
+            .run(
+                |_| {
+                    attempts += 1;
+                    Ok(Command::new("sh").args(["-c", "kill -TERM $$"]).status()?)
+                },
+                |_| (),
+            )
+            .unwrap();
+        assert_eq!((code, attempts), (128 + 15, 1));
+    }
+}
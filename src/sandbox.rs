@@ -0,0 +1,143 @@
+//! Isolation of COMMAND on Linux with `--private-tmp`, `--no-network` and `--cgroup`
+//!
+//! enw sets these up on itself right before executing COMMAND, as the last wrapper before the
+//! application. New namespaces need root, or else a user namespace, in which the invoking user
+//! keeps their own uid and gid. The support is built with the `sandbox` feature.
+
+use std::{io, path::PathBuf};
+
+use crate::Error;
+
+/// Where `--cgroup NAME` looks for cgroups given by name rather than path
+#[cfg_attr(not(all(feature = "sandbox", target_os = "linux")), allow(dead_code))]
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct Sandbox {
+    /// Mount an empty tmpfs on /tmp, seen only by COMMAND
+    private_tmp: bool,
+    /// Run COMMAND in a network namespace of its own, with only a loopback interface that is
+    /// down
+    no_network: bool,
+    /// The directory of an existing cgroup to join
+    cgroup: Option<PathBuf>,
+}
+
+impl Sandbox {
+    pub(crate) fn new(
+        private_tmp: bool,
+        no_network: bool,
+        cgroup: Option<PathBuf>,
+    ) -> Result<Self, Error> {
+        let sandbox = Sandbox {
+            private_tmp,
+            no_network,
+            cgroup,
+        };
+        if sandbox.is_enabled() && !cfg!(all(feature = "sandbox", target_os = "linux")) {
+            return Err(Error::Usage(
+                "enw was built without support for sandboxing, rebuild it on Linux with \
+                 --features sandbox"
+                    .to_owned(),
+            ));
+        }
+        Ok(sandbox)
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.private_tmp || self.no_network || self.cgroup.is_some()
+    }
+
+    /// Set up the isolation for the current process, before it executes COMMAND or starts it
+    /// as a child. New namespaces can only be entered while there is a single thread.
+    #[cfg(all(feature = "sandbox", target_os = "linux"))]
+    pub(crate) fn apply(&self) -> io::Result<()> {
+        use std::{fs, path::Path, ptr};
+
+        let context =
+            |option: &str, err: io::Error| io::Error::new(err.kind(), format!("{option}: {err}"));
+        if let Some(cgroup) = &self.cgroup {
+            let dir = Path::new(CGROUP_ROOT).join(cgroup);
+            // Writing 0 moves the writing process
+            fs::write(dir.join("cgroup.procs"), "0\n")
+                .map_err(|err| context(&format!("--cgroup {}", dir.to_string_lossy()), err))?;
+        }
+        let mut flags = 0;
+        if self.private_tmp {
+            flags |= libc::CLONE_NEWNS;
+        }
+        if self.no_network {
+            flags |= libc::CLONE_NEWNET;
+        }
+        if flags == 0 {
+            return Ok(());
+        }
+        // SAFETY: plain system calls
+        let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+        if uid != 0 {
+            flags |= libc::CLONE_NEWUSER;
+        }
+        // SAFETY: plain system call
+        if unsafe { libc::unshare(flags) } == -1 {
+            return Err(context("unshare", io::Error::last_os_error()));
+        }
+        if uid != 0 {
+            // The user keeps their ids inside the new user namespace
+            fs::write("/proc/self/setgroups", "deny")
+                .and_then(|()| fs::write("/proc/self/uid_map", format!("{uid} {uid} 1")))
+                .and_then(|()| fs::write("/proc/self/gid_map", format!("{gid} {gid} 1")))
+                .map_err(|err| context("user namespace", err))?;
+        }
+        if self.private_tmp {
+            // SAFETY: the strings are static and NUL terminated. The mounts are made
+            // private first, so that the tmpfs isn't propagated to the parent namespace.
+            let result = unsafe {
+                match libc::mount(
+                    ptr::null(),
+                    c"/".as_ptr(),
+                    ptr::null(),
+                    libc::MS_REC | libc::MS_PRIVATE,
+                    ptr::null(),
+                ) {
+                    -1 => -1,
+                    _ => libc::mount(
+                        c"tmpfs".as_ptr(),
+                        c"/tmp".as_ptr(),
+                        c"tmpfs".as_ptr(),
+                        libc::MS_NOSUID | libc::MS_NODEV,
+                        c"mode=1777".as_ptr().cast(),
+                    ),
+                }
+            };
+            if result == -1 {
+                return Err(context("--private-tmp", io::Error::last_os_error()));
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(all(feature = "sandbox", target_os = "linux")))]
+    pub(crate) fn apply(&self) -> io::Result<()> {
+        // Refused by `new` when enabled
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new() {
+        assert!(!Sandbox::new(false, false, None).unwrap().is_enabled());
+        let sandbox = Sandbox::new(true, false, None);
+        if cfg!(all(feature = "sandbox", target_os = "linux")) {
+            assert!(sandbox.unwrap().is_enabled());
+        } else {
+            assert!(sandbox
+                .unwrap_err()
+                .to_string()
+                .contains("--features sandbox"));
+        }
+    }
+}
@@ -0,0 +1,243 @@
+//! Validation of the resolved environment against a schema given with `--schema`
+//!
+//! The schema is a JSON Schema for an object of strings, of which the `properties` and
+//! `required` keywords are supported. Each property may have a `type`, which the value has to
+//! parse as, an `enum`, a `format` of `uri` and a `minimum` and `maximum` for numbers. Other
+//! keywords, such as `description`, are ignored.
+//!
+//! ```json
+//! {
+//!   "properties": {
+//!     "PORT": { "type": "integer", "minimum": 1, "maximum": 65535 },
+//!     "DEBUG": { "type": "boolean" },
+//!     "DATABASE_URL": { "type": "string", "format": "uri" },
+//!     "LOG_LEVEL": { "enum": ["debug", "info", "warn"] }
+//!   },
+//!   "required": ["PORT", "DATABASE_URL"]
+//! }
+//! ```
+
+use std::{fs, path::Path};
+
+use serde_json::{Map, Value};
+
+use crate::{parse::Lookup, Error};
+
+/// Values accepted for `boolean` properties, compared ignoring case
+const BOOLEAN_VALUES: [&str; 8] = ["true", "false", "1", "0", "yes", "no", "on", "off"];
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Type {
+    String,
+    Integer,
+    Number,
+    Boolean,
+}
+
+#[derive(Debug, PartialEq)]
+struct Property {
+    name: String,
+    kind: Type,
+    allowed: Option<Vec<String>>,
+    is_uri: bool,
+    minimum: Option<f64>,
+    maximum: Option<f64>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Schema {
+    properties: Vec<Property>,
+    required: Vec<String>,
+}
+
+impl Schema {
+    pub(crate) fn load(path: &Path) -> Result<Self, Error> {
+        let text = fs::read(path).map_err(|err| Error::io(path, err))?;
+        serde_json::from_slice(&text)
+            .map_err(|err| Error::Other(format!("invalid JSON: {err}")))
+            .and_then(|value| Schema::parse(&value))
+            .map_err(|err| err.in_source(&path.to_string_lossy()))
+    }
+
+    fn parse(value: &Value) -> Result<Self, Error> {
+        let schema = value.as_object().ok_or("expected a JSON object")?;
+        let mut properties = Vec::new();
+        if let Some(value) = schema.get("properties") {
+            let value = value.as_object().ok_or("properties: expected an object")?;
+            for (name, property) in value {
+                let property = property
+                    .as_object()
+                    .ok_or_else(|| format!("{name}: expected an object"))?;
+                properties.push(parse_property(name, property)?);
+            }
+        }
+        let required = match schema.get("required") {
+            Some(Value::Array(names)) => names
+                .iter()
+                .map(|name| name.as_str().map(str::to_owned))
+                .collect::<Option<_>>()
+                .ok_or("required: expected an array of strings")?,
+            Some(_) => return Err("required: expected an array of strings".into()),
+            None => Vec::new(),
+        };
+        Ok(Schema {
+            properties,
+            required,
+        })
+    }
+
+    /// Everything about the variables found by `lookup` that doesn't match the schema
+    pub(crate) fn violations(&self, lookup: Lookup) -> Vec<String> {
+        let mut violations = Vec::new();
+        for name in &self.required {
+            if lookup(name).is_none() {
+                violations.push(format!("{name}: required but not set"));
+            }
+        }
+        for property in &self.properties {
+            if let Some(value) = lookup(&property.name) {
+                if let Err(message) = property.check(&value) {
+                    violations.push(format!("{}: {message}, got {value:?}", property.name));
+                }
+            }
+        }
+        violations
+    }
+}
+
+fn parse_property(name: &str, property: &Map<String, Value>) -> Result<Property, Error> {
+    let error = |message: &str| Error::Other(format!("{name}: {message}"));
+    let kind = match property.get("type").map(|kind| kind.as_str()) {
+        None | Some(Some("string")) => Type::String,
+        Some(Some("integer")) => Type::Integer,
+        Some(Some("number")) => Type::Number,
+        Some(Some("boolean")) => Type::Boolean,
+        Some(Some(kind)) => return Err(error(&format!("unsupported type {kind}"))),
+        Some(None) => return Err(error("type: expected a string")),
+    };
+    let allowed = match property.get("enum") {
+        Some(Value::Array(values)) => Some(
+            values
+                .iter()
+                .map(|value| match value {
+                    Value::String(value) => value.clone(),
+                    value => value.to_string(),
+                })
+                .collect(),
+        ),
+        Some(_) => return Err(error("enum: expected an array")),
+        None => None,
+    };
+    let bound = |keyword: &str| match property.get(keyword) {
+        Some(value) => value
+            .as_f64()
+            .map(Some)
+            .ok_or_else(|| error(&format!("{keyword}: expected a number"))),
+        None => Ok(None),
+    };
+    Ok(Property {
+        name: name.to_owned(),
+        kind,
+        allowed,
+        is_uri: matches!(
+            property.get("format").and_then(Value::as_str),
+            Some("uri" | "url")
+        ),
+        minimum: bound("minimum")?,
+        maximum: bound("maximum")?,
+    })
+}
+
+impl Property {
+    fn check(&self, value: &str) -> Result<(), String> {
+        let number = match self.kind {
+            Type::String => None,
+            Type::Integer => Some(
+                value
+                    .trim()
+                    .parse::<i64>()
+                    .map_err(|_| "expected an integer")? as f64,
+            ),
+            Type::Number => Some(
+                value
+                    .trim()
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|number| number.is_finite())
+                    .ok_or("expected a number")?,
+            ),
+            Type::Boolean => {
+                if !BOOLEAN_VALUES.contains(&value.trim().to_ascii_lowercase().as_str()) {
+                    return Err("expected a boolean".to_owned());
+                }
+                None
+            }
+        };
+        if let Some(number) = number {
+            if let Some(minimum) = self.minimum.filter(|&minimum| number < minimum) {
+                return Err(format!("expected at least {minimum}"));
+            }
+            if let Some(maximum) = self.maximum.filter(|&maximum| number > maximum) {
+                return Err(format!("expected at most {maximum}"));
+            }
+        }
+        if let Some(allowed) = &self.allowed {
+            if !allowed.iter().any(|allowed| allowed == value) {
+                return Err(format!("expected one of {}", allowed.join(", ")));
+            }
+        }
+        if self.is_uri && !is_uri(value) {
+            return Err("expected a URI".to_owned());
+        }
+        Ok(())
+    }
+}
+
+/// Whether `value` looks like `scheme://rest` or `scheme:rest`
+fn is_uri(value: &str) -> bool {
+    value.split_once(':').is_some_and(|(scheme, rest)| {
+        scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+            && scheme
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+            && !rest.is_empty()
+            && !rest.contains(char::is_whitespace)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn test_violations() {
+        let schema = Schema::load(Path::new("tests/data/schema.json")).unwrap();
+        let env: HashMap<_, _> = [
+            ("PORT", "99999"),
+            ("DEBUG", "Yes"),
+            ("DATABASE_URL", "not a url"),
+            ("LOG_LEVEL", "info"),
+            ("RATIO", "0.5"),
+        ]
+        .into_iter()
+        .collect();
+        let violations = schema.violations(&|key| env.get(key).map(|v| v.to_string()));
+        assert_eq!(
+            violations,
+            [
+                "API_KEY: required but not set",
+                "PORT: expected at most 65535, got \"99999\"",
+                "DATABASE_URL: expected a URI, got \"not a url\"",
+            ]
+        );
+        assert!(is_uri("postgres://user@localhost/db"));
+        assert!(is_uri("mailto:me@example.com"));
+        assert!(!is_uri("localhost"));
+        assert!(
+            Schema::parse(&serde_json::json!({"properties": {"A": {"type": "date"}}})).is_err()
+        );
+        assert!(Schema::parse(&serde_json::json!({"required": "A"})).is_err());
+    }
+}
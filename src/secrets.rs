@@ -0,0 +1,63 @@
+//! Secret references in values, resolved when the environment is loaded
+//!
+//! With `--resolve-op`, values that are 1Password secret references such as
+//! `op://vault/item/field` are replaced with the secrets, read with the 1Password CLI, as
+//! `op run` does. The CLI also talks to a Connect server when `OP_CONNECT_HOST` and
+//! `OP_CONNECT_TOKEN` are set.
+
+use std::{
+    io,
+    process::{Command, Stdio},
+};
+
+use crate::{
+    log::log,
+    os::{bytes_to_string, string_to_os},
+    Error,
+};
+
+const OP_SCHEME: &str = "op://";
+const OP_PROGRAM: &str = "op";
+
+/// The source of the secrets read from 1Password, for `--audit-log`
+pub(crate) const OP_SOURCE: &str = "1password";
+
+/// Replace the values of `vars` that are 1Password secret references with the secrets, and
+/// return the names of those variables
+pub(crate) fn resolve_op_references(vars: &mut [(String, String)]) -> Result<Vec<String>, Error> {
+    // Variables such as OP_SERVICE_ACCOUNT_TOKEN may come from the env files themselves
+    let plain: Vec<_> = vars
+        .iter()
+        .filter(|(_, value)| !value.starts_with(OP_SCHEME))
+        .map(|(key, value)| (string_to_os(key), string_to_os(value)))
+        .collect();
+    let mut resolved = Vec::new();
+    for (key, value) in vars.iter_mut() {
+        if !value.starts_with(OP_SCHEME) {
+            continue;
+        }
+        let output = Command::new(OP_PROGRAM)
+            .args(["read", "--no-newline"])
+            .arg(string_to_os(value))
+            .envs(plain.iter().cloned())
+            .stdin(Stdio::inherit())
+            .stderr(Stdio::piped())
+            .output()
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::NotFound => Error::Other(format!(
+                    "{key}: the 1Password CLI `{OP_PROGRAM}` is needed to resolve {value}"
+                )),
+                _ => Error::Other(format!("{key}: {OP_PROGRAM}: {err}")),
+            })?;
+        if !output.status.success() {
+            return Err(Error::Other(format!(
+                "{key}: {OP_PROGRAM} read {value} failed: {}",
+                bytes_to_string(&output.stderr).trim()
+            )));
+        }
+        log!(Info, "{key}: resolved {value}");
+        *value = bytes_to_string(&output.stdout).into_owned();
+        resolved.push(key.clone());
+    }
+    Ok(resolved)
+}
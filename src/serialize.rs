@@ -0,0 +1,178 @@
+//! Writing variables back to dotenv format
+//!
+//! The output is quoted so that parsing it as an env file, with or without `--expand`,
+//! `--command-subst` and `--interpret-escapes`, gives back exactly the same keys and values.
+//! Values containing a newline, a carriage return or a NUL have no representation in the
+//! dotenv dialect and are rejected, as are invalid keys.
+
+use crate::{parse::key_is_valid, Error};
+
+/// Format variables as the lines of a dotenv file
+///
+/// ```
+/// let vars = [("GREETING", "hello world"), ("NAME", "it's me")];
+/// assert_eq!(
+///     enw::to_dotenv_string(vars).unwrap(),
+///     "GREETING='hello world'\nNAME='it\\'s me'\n"
+/// );
+/// ```
+pub fn to_dotenv_string<K: AsRef<str>, V: AsRef<str>>(
+    vars: impl IntoIterator<Item = (K, V)>,
+) -> Result<String, Error> {
+    let mut doc = String::new();
+    for (key, value) in vars {
+        doc.push_str(&format_dotenv(key.as_ref(), value.as_ref())?);
+        doc.push('\n');
+    }
+    Ok(doc)
+}
+
+/// Format a variable as a dotenv line that parses back to the same value. Anything but the
+/// simplest values is single quoted, so that no expansion takes place when read back.
+pub(crate) fn format_dotenv(key: &str, value: &str) -> Result<String, Error> {
+    // `KEY+=` and `KEY^=` would add to the current value
+    if !key_is_valid(key) || key.contains(['=', '\0']) || key.ends_with(['+', '^']) {
+        return Err(format!("{key:?} can not be written as a key in an env file").into());
+    }
+    if value.contains(['\n', '\r', '\0']) {
+        return Err(format!("value of {key} can not be represented in an env file").into());
+    }
+    if value
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-'))
+    {
+        Ok(format!("{key}={value}"))
+    } else {
+        let escaped = value.replace('\\', r"\\").replace('\'', r"\'");
+        Ok(format!("{key}='{escaped}'"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indexmap::IndexMap;
+    use pretty_assertions::assert_eq;
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::parse::{parse_doc_line, parse_env_line, Dialect, ParseOptions};
+
+    fn no_vars(_: &str) -> Option<String> {
+        None
+    }
+
+    fn all_options() -> Vec<ParseOptions> {
+        let mut all = Vec::new();
+        for flags in 0..8 {
+            all.push(ParseOptions {
+                expand: flags & 1 != 0,
+                command_subst: flags & 2 != 0,
+                interpret_escapes: flags & 4 != 0,
+                ..Default::default()
+            });
+        }
+        all
+    }
+
+    #[test]
+    fn test_format_dotenv_roundtrip() {
+        let values = [
+            "",
+            "simple",
+            "with space ",
+            "  leading",
+            r#"quotes ' and " and \' and \\"#,
+            "hash # not a comment",
+            "#leading hash",
+            "$HOME and ${HOME:-x} and $(echo no)",
+            r"\n stays \t literal",
+            "trailing backslash \\",
+            "'",
+            "\"",
+            "=",
+            "\ttab",
+            "unicode \u{3000}",
+        ];
+        for options in all_options() {
+            for value in values {
+                let line = format_dotenv("KEY", value).unwrap();
+                assert_eq!(
+                    parse_env_line(&line, &options, &no_vars).unwrap(),
+                    ("KEY", value.into()),
+                    "{}",
+                    line
+                );
+            }
+        }
+        assert!(format_dotenv("KEY", "line 1\nline 2").is_err());
+        assert!(format_dotenv("KEY", "cr\r").is_err());
+        assert!(format_dotenv("A=B", "value").is_err());
+        assert!(format_dotenv("PATH+", "value").is_err());
+        assert!(format_dotenv("1KEY", "value").is_err());
+    }
+
+    /// Every string of up to three characters from an alphabet of the characters that matter
+    /// to the parser survives the round trip
+    #[test]
+    fn test_exhaustive_roundtrip() {
+        let alphabet = [
+            'a', ' ', '\t', '\'', '"', '\\', '#', '$', '(', ')', '{', '}', '=', '`', 'é',
+            '\u{3000}',
+        ];
+        let mut values = vec![String::new()];
+        let mut previous = values.clone();
+        for _ in 0..3 {
+            previous = previous
+                .iter()
+                .flat_map(|value| alphabet.iter().map(move |c| format!("{value}{c}")))
+                .collect();
+            values.extend(previous.iter().cloned());
+        }
+        let options = all_options();
+        for value in &values {
+            let line = format_dotenv("KEY", value).unwrap();
+            for options in &options {
+                let parsed = parse_doc_line(&line, options, &no_vars)
+                    .expect("a variable")
+                    .unwrap();
+                assert_eq!(parsed, ("KEY", value.into()), "{}", line);
+            }
+        }
+    }
+
+    #[test]
+    fn test_to_dotenv_string() {
+        let vars = IndexMap::from([
+            ("A".to_owned(), "1".to_owned()),
+            ("B".to_owned(), "two words".to_owned()),
+        ]);
+        assert_eq!(to_dotenv_string(&vars).unwrap(), "A=1\nB='two words'\n");
+        assert_eq!(to_dotenv_string(Vec::<(&str, &str)>::new()).unwrap(), "");
+        assert!(to_dotenv_string([("A", "\n")]).is_err());
+    }
+
+    proptest! {
+        /// Whatever can be written parses back the same, in both dotenv dialects
+        #[test]
+        fn prop_roundtrip(
+            key in r"[A-Za-z_]\PC{0,8}",
+            value in any::<String>(),
+            flags in 0..8usize,
+            strict in any::<bool>(),
+        ) {
+            let Ok(line) = format_dotenv(&key, &value) else {
+                prop_assert!(!key_is_valid(&key) || key.contains(['=', '+', '^', '\0'])
+                    || value.contains(['\n', '\r', '\0']));
+                return Ok(());
+            };
+            let options = ParseOptions {
+                dialect: if strict { Dialect::StrictDotenv } else { Dialect::Dotenv },
+                ..all_options().swap_remove(flags)
+            };
+            let parsed = parse_doc_line(&line, &options, &no_vars)
+                .expect("a variable")
+                .unwrap();
+            prop_assert_eq!(parsed, (key.as_str(), value.as_str().into()), "{}", line);
+        }
+    }
+}
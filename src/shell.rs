@@ -0,0 +1,69 @@
+//! Interactive shells started with `enw shell`, with a marker on their prompt
+//!
+//! The rc files of the shell run after enw has set the environment, so the marker is added in
+//! a way they are unlikely to undo: by bash before each prompt, and through `PS1` otherwise.
+//! `ENW_SHELL` is set in any case, for prompts that show the marker themselves, as fish's do.
+
+use std::path::Path;
+
+use crate::output::posix_single_quote;
+
+/// Set in the environment of shells started by `enw shell`
+pub(crate) const SHELL_VAR: &str = "ENW_SHELL";
+pub(crate) const DEFAULT_PROMPT_MARKER: &str = "(enw) ";
+
+/// The variables that make `shell` show `marker` in front of its prompt, given the inherited
+/// `PROMPT_COMMAND` and `PS1`
+pub(crate) fn prompt_vars(
+    shell: &str,
+    marker: &str,
+    prompt_command: Option<String>,
+    ps1: Option<String>,
+) -> Vec<(String, String)> {
+    let mut vars = vec![(SHELL_VAR.to_owned(), "1".to_owned())];
+    let name = Path::new(shell)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    match name.as_str() {
+        "bash" => {
+            let marker = posix_single_quote(marker);
+            let mut command =
+                format!("case \"$PS1\" in {marker}*) ;; *) PS1={marker}\"$PS1\" ;; esac");
+            if let Some(previous) = prompt_command.filter(|previous| !previous.is_empty()) {
+                command = format!("{command}; {previous}");
+            }
+            vars.push(("PROMPT_COMMAND".to_owned(), command));
+        }
+        "fish" => (),
+        name => {
+            let default = match name {
+                "zsh" => "%m%# ",
+                _ => "$ ",
+            };
+            let ps1 = ps1.unwrap_or_else(|| default.to_owned());
+            vars.push(("PS1".to_owned(), format!("{marker}{ps1}")));
+        }
+    }
+    vars
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prompt_vars() {
+        let vars = prompt_vars("/bin/bash", "(enw) ", Some("history -a".to_owned()), None);
+        assert_eq!(vars[0], ("ENW_SHELL".to_owned(), "1".to_owned()));
+        assert_eq!(
+            vars[1].1,
+            "case \"$PS1\" in '(enw) '*) ;; *) PS1='(enw) '\"$PS1\" ;; esac; history -a"
+        );
+        let vars = prompt_vars("zsh", "[app] ", None, None);
+        assert_eq!(vars[1], ("PS1".to_owned(), "[app] %m%# ".to_owned()));
+        let vars = prompt_vars("sh", "(enw) ", None, Some("> ".to_owned()));
+        assert_eq!(vars[1].1, "(enw) > ");
+        assert_eq!(prompt_vars("/usr/bin/fish", "(enw) ", None, None).len(), 1);
+    }
+}
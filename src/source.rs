@@ -0,0 +1,1503 @@
+//! Sources of environment variables, layered in order of precedence
+
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    fs,
+    io::{self, BufRead, Read},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+    time::Instant,
+};
+
+use indexmap::IndexMap;
+use serde_json::Value;
+
+use crate::{
+    cache,
+    coerce::{parse_type_directive, split_annotated_key, Types, TYPE_DIRECTIVE},
+    cond::Conditions,
+    glob_match, gpg,
+    log::log,
+    os::{bytes_to_string, permission_bits, string_to_os},
+    output::DEFAULT_MASK_PATTERNS,
+    parse::{is_continued, key_is_valid, parse_doc_line, Dialect, Lookup, Merge, ParseOptions},
+    procenv,
+    resolver::{run_hooks, Hooks},
+    timings::{self, Step},
+    Error, Warning,
+};
+
+const INCLUDE_DIRECTIVE: &str = "# enw:include ";
+/// How many sources are prefetched at once
+const PREFETCH_THREADS: usize = 4;
+
+/// What a source has prefetched, until it's loaded
+pub(crate) type Prefetched<T> = Mutex<Option<Result<T, Error>>>;
+const UNCLOSED_IF: &str = "enw:if without enw:endif";
+/// Sets the dialect of the rest of the file, e.g. `# enw:dialect strict-dotenv`
+const DIALECT_DIRECTIVE: &str = "# enw:dialect ";
+/// Unsets variables for the rest of the file and the sources after it, e.g.
+/// `# enw:unset DEBUG TRACE`
+const UNSET_DIRECTIVE: &str = "# enw:unset ";
+/// The byte order mark some editors put at the start of UTF-8 files
+const UTF8_BOM: &[u8] = b"\xef\xbb\xbf";
+
+/// The value of a variable that is unset, with `# enw:unset KEY` or `--unset KEY`, rather than
+/// set to a value. No variable can have it, as values of the environment can't contain NUL.
+///
+/// Sources and the registry return unset variables with this value, so that the variable can
+/// be removed from an inherited environment as well, while references to it expand as if it
+/// was never defined.
+pub const UNSET: &str = "\0";
+
+/// The resolved variables, and the secret sources of the variables last defined by one
+pub(crate) type Resolved = (IndexMap<String, String>, IndexMap<String, String>);
+
+/// A provider of environment variables, such as an env file or the command line
+pub trait EnvSource: Send + Sync {
+    /// Short description used in diagnostics, e.g. the path of a file
+    fn name(&self) -> String;
+
+    /// Load the variables of this source in definition order. `lookup` resolves variables from
+    /// the sources loaded before this one, for sources supporting expansion. Variables the
+    /// source unsets have the value [`UNSET`].
+    fn load(&self, lookup: Lookup) -> Result<Vec<(String, String)>, Error>;
+
+    /// Do the slow part of loading that doesn't depend on other sources, such as reading,
+    /// decrypting or fetching, ahead of [`load`](Self::load). The sources of a registry are
+    /// prefetched concurrently, and errors are kept for `load` to report.
+    fn prefetch(&self) {}
+
+    /// Whether the variables of this source replace earlier definitions regardless of the
+    /// [`DuplicatePolicy`], as those given on the command line do
+    fn overrides(&self) -> bool {
+        false
+    }
+
+    /// The file the variables are read from, which has to be private to its owner if it
+    /// defines secrets, see [`PermissionPolicy`]
+    fn path(&self) -> Option<&Path> {
+        None
+    }
+
+    /// Whether the source is loaded without being asked for, like the implicit `.env` file,
+    /// so that the variables it may set are restricted by the [`DangerPolicy`]
+    fn is_implicit(&self) -> bool {
+        false
+    }
+
+    /// Whether the variables come from a secrets backend, such as a secrets service or an
+    /// encrypted file, so that passing them to COMMAND is recorded by `--audit-log`
+    fn is_secret(&self) -> bool {
+        false
+    }
+}
+
+/// What to do when a variable is defined more than once
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum DuplicatePolicy {
+    /// The last definition is used
+    #[default]
+    LastWins,
+    /// The first definition is used and later ones are ignored
+    FirstWins,
+    /// The last definition is used, with a warning
+    Warn,
+    /// Resolving fails
+    Error,
+}
+
+/// What to do when a file defining secrets, variables named like `*TOKEN*`, `*SECRET*`,
+/// `*PASSWORD*` or `*KEY*`, can be read by users other than its owner
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum PermissionPolicy {
+    /// The file is loaded, with a warning
+    #[default]
+    Warn,
+    /// Resolving fails
+    Error,
+    /// The permissions aren't checked
+    Ignore,
+}
+
+/// How the names of variables are normalized
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum KeyCase {
+    /// Names are kept as they are
+    #[default]
+    Preserve,
+    /// Names are converted to ASCII upper case
+    Upper,
+    /// Names are converted to ASCII lower case
+    Lower,
+}
+
+impl KeyCase {
+    pub fn normalize(self, key: &str) -> String {
+        match self {
+            KeyCase::Preserve => key.to_owned(),
+            KeyCase::Upper => key.to_ascii_uppercase(),
+            KeyCase::Lower => key.to_ascii_lowercase(),
+        }
+    }
+}
+
+/// How env files that are symlinks, like a `.env` linked to a shared team config, are treated
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub(crate) enum Symlinks {
+    /// They are followed, with their includes relative to the directory of the link
+    #[default]
+    Follow,
+    /// They are followed, with their includes relative to the directory of the target
+    Resolve,
+    /// Loading them fails, with `--no-follow-symlinks`
+    Refuse,
+}
+
+/// Security-sensitive variables, which implicitly loaded sources may not set, or only with a
+/// warning. Patterns are globs matched against the variable names.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DangerPolicy {
+    refuse: Vec<String>,
+    warn: Vec<String>,
+}
+
+impl Default for DangerPolicy {
+    /// Refuse variables changing how the dynamic linker loads libraries, warn about `PATH`
+    fn default() -> Self {
+        DangerPolicy::new(
+            vec!["LD_*".to_owned(), "DYLD_*".to_owned()],
+            vec!["PATH".to_owned()],
+        )
+    }
+}
+
+impl DangerPolicy {
+    pub fn new(refuse: Vec<String>, warn: Vec<String>) -> Self {
+        DangerPolicy { refuse, warn }
+    }
+
+    /// A policy allowing any variable
+    pub fn allow_all() -> Self {
+        DangerPolicy::new(Vec::new(), Vec::new())
+    }
+
+    pub(crate) fn with_refused(mut self, refuse: Vec<String>) -> Self {
+        self.refuse = refuse;
+        self
+    }
+
+    pub(crate) fn with_warned(mut self, warn: Vec<String>) -> Self {
+        self.warn = warn;
+        self
+    }
+
+    fn refuses(&self, key: &str) -> bool {
+        self.refuse.iter().any(|pattern| glob_match(pattern, key))
+    }
+
+    fn warns(&self, key: &str) -> bool {
+        self.warn.iter().any(|pattern| glob_match(pattern, key))
+    }
+}
+
+/// An ordered list of sources, where later sources take precedence over earlier ones
+#[derive(Default)]
+pub struct SourceRegistry {
+    sources: Vec<Box<dyn EnvSource>>,
+    on_duplicate: DuplicatePolicy,
+    dangerous: DangerPolicy,
+    on_readable_secrets: PermissionPolicy,
+    key_case: KeyCase,
+    report_collisions: bool,
+}
+
+impl SourceRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a source with higher precedence than all sources added before it
+    pub fn push(&mut self, source: impl EnvSource + 'static) -> &mut Self {
+        self.sources.push(Box::new(source));
+        self
+    }
+
+    /// Set how variables defined more than once, in one source or several, are resolved
+    pub fn on_duplicate(&mut self, policy: DuplicatePolicy) -> &mut Self {
+        self.on_duplicate = policy;
+        self
+    }
+
+    /// Set which variables implicitly loaded sources may not set, by default `LD_*` and
+    /// `DYLD_*`, with a warning for `PATH`
+    pub fn on_dangerous(&mut self, policy: DangerPolicy) -> &mut Self {
+        self.dangerous = policy;
+        self
+    }
+
+    /// Set what to do when a file defining secrets can be read by other users, by default warn
+    pub fn on_readable_secrets(&mut self, policy: PermissionPolicy) -> &mut Self {
+        self.on_readable_secrets = policy;
+        self
+    }
+
+    /// Set how the names of variables, and of the variables they reference, are normalized.
+    /// Names that become equal are duplicates.
+    pub fn normalize_keys(&mut self, key_case: KeyCase) -> &mut Self {
+        self.key_case = key_case;
+        self
+    }
+
+    /// Warn about each variable that several sources define with different values, whichever
+    /// the [`DuplicatePolicy`]
+    pub fn report_collisions(&mut self, report: bool) -> &mut Self {
+        self.report_collisions = report;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.sources.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    /// Load all sources in order. References to variables not defined by any earlier source are
+    /// resolved with `fallback`.
+    ///
+    /// Variables are kept in the order they were first defined, while the value is the one from
+    /// the definition selected by the [`DuplicatePolicy`]. Warnings about duplicates are
+    /// dropped, see [`resolve_with_warnings`](Self::resolve_with_warnings).
+    pub fn resolve(&self, fallback: Lookup) -> Result<IndexMap<String, String>, Error> {
+        self.resolve_with_warnings(fallback, &mut Vec::new())
+    }
+
+    /// Like [`resolve`](Self::resolve), adding warnings about duplicates and dangerous variables
+    /// to `warnings`
+    pub fn resolve_with_warnings(
+        &self,
+        fallback: Lookup,
+        warnings: &mut Vec<Warning>,
+    ) -> Result<IndexMap<String, String>, Error> {
+        let (env_vars, _) = self.resolve_hooked(fallback, warnings, &[])?;
+        Ok(env_vars)
+    }
+
+    /// Like [`resolve_with_warnings`](Self::resolve_with_warnings), passing each value loaded
+    /// through the `pre_hooks` matching its key. The variables last defined by a secret source
+    /// are returned as well, with the name of the source, see [`EnvSource::is_secret`].
+    pub(crate) fn resolve_hooked(
+        &self,
+        fallback: Lookup,
+        warnings: &mut Vec<Warning>,
+        pre_hooks: &Hooks,
+    ) -> Result<Resolved, Error> {
+        let mut env_vars: IndexMap<String, String> = IndexMap::new();
+        // The source each variable was last defined by, for diagnostics
+        let mut origins: HashMap<String, String> = HashMap::new();
+        let mut secret_origins: IndexMap<String, String> = IndexMap::new();
+        // Each definition of each variable, for `report_collisions`
+        let mut definitions: IndexMap<String, Vec<(String, String)>> = IndexMap::new();
+        prefetch(&self.sources);
+        for source in &self.sources {
+            let lookup = |key: &str| resolved_var(&env_vars, self.key_case, key, fallback);
+            let name = source.name();
+            let vars = timings::time(Step::Load, || name.clone(), || source.load(&lookup))
+                .map_err(|err| err.in_source(&name))?;
+            log!(Info, "{name}: {} variables", vars.len());
+            let exposed = match (self.on_readable_secrets, source.path()) {
+                (PermissionPolicy::Ignore, _) | (_, None) => None,
+                (_, Some(path)) => exposed_secret(path, &vars),
+            };
+            match (exposed, self.on_readable_secrets) {
+                (Some(warning), PermissionPolicy::Error) => {
+                    return Err(Error::Other(warning.to_string()))
+                }
+                (Some(warning), _) => warnings.push(warning),
+                (None, _) => (),
+            }
+            for (key, value) in vars {
+                let key = self.key_case.normalize(&key);
+                let lookup = |key: &str| resolved_var(&env_vars, self.key_case, key, fallback);
+                let value = match value == UNSET {
+                    true => value,
+                    false => run_hooks(pre_hooks, &key, value, &lookup)
+                        .map_err(|err| err.in_source(&name))?,
+                };
+                let is_set = value != UNSET;
+                if source.is_implicit() && is_set && self.dangerous.refuses(&key) {
+                    return Err(Error::Other(format!(
+                        "{name}: {key} may be dangerous and is not set from implicitly loaded \
+                         files, use --allow-dangerous to allow it"
+                    )));
+                }
+                if source.is_implicit() && is_set && self.dangerous.warns(&key) {
+                    warnings.push(Warning::DangerousVar {
+                        key: key.clone(),
+                        source: name.clone(),
+                    });
+                }
+                if self.report_collisions {
+                    let entry = definitions.entry(key.clone()).or_default();
+                    entry.push((name.clone(), value.clone()));
+                }
+                match origins.get(&key) {
+                    Some(previous) if !source.overrides() => match self.on_duplicate {
+                        DuplicatePolicy::LastWins => (),
+                        DuplicatePolicy::FirstWins => {
+                            log!(Debug, "{key}: from {name} ignored, defined in {previous}");
+                            continue;
+                        }
+                        DuplicatePolicy::Warn => {
+                            warnings.push(duplicate_warning(&key, previous, &name))
+                        }
+                        DuplicatePolicy::Error => {
+                            return Err(Error::Other(
+                                duplicate_warning(&key, previous, &name).to_string(),
+                            ))
+                        }
+                    },
+                    _ => (),
+                }
+                match origins.insert(key.clone(), name.clone()) {
+                    Some(previous) if previous != name => {
+                        log!(Debug, "{key}: from {name}, overriding {previous}")
+                    }
+                    Some(_) => (),
+                    None => log!(Debug, "{key}: from {name}"),
+                }
+                match source.is_secret() {
+                    true => secret_origins.insert(key.clone(), name.clone()),
+                    false => secret_origins.shift_remove(&key),
+                };
+                env_vars.insert(key, value);
+            }
+        }
+        warnings.extend(collisions(definitions));
+        Ok((env_vars, secret_origins))
+    }
+}
+
+/// The value of `key` among the variables resolved so far, or from `fallback` if none of
+/// them defines it
+fn resolved_var(
+    env_vars: &IndexMap<String, String>,
+    key_case: KeyCase,
+    key: &str,
+    fallback: Lookup,
+) -> Option<String> {
+    match env_vars.get(&key_case.normalize(key)) {
+        Some(value) if value == UNSET => None,
+        Some(value) => Some(value.clone()),
+        None => fallback(key),
+    }
+}
+
+/// A warning if the file at `path` defines a secret among `vars` yet can be read by the group
+/// or by anyone, like ssh refuses such private keys. Encrypted files are safe either way.
+fn exposed_secret(path: &Path, vars: &[(String, String)]) -> Option<Warning> {
+    if gpg::is_encrypted(path) {
+        return None;
+    }
+    let (secret, _) = vars.iter().find(|(key, value)| {
+        value != UNSET
+            && DEFAULT_MASK_PATTERNS
+                .iter()
+                .any(|pattern| glob_match(pattern, key))
+    })?;
+    let mode = permission_bits(&fs::metadata(path).ok()?)? & 0o777;
+    (mode & 0o044 != 0).then(|| Warning::ReadableSecret {
+        path: path.to_owned(),
+        key: secret.clone(),
+        mode,
+    })
+}
+
+/// Prefetch `sources` on a few threads, so that slow ones such as remotes and encrypted files
+/// are loaded at the same time rather than one after another
+fn prefetch(sources: &[Box<dyn EnvSource>]) {
+    // A single source is as well loaded right away
+    if sources.len() < 2 {
+        return;
+    }
+    let next = AtomicUsize::new(0);
+    thread::scope(|scope| {
+        for _ in 0..PREFETCH_THREADS.min(sources.len()) {
+            scope.spawn(|| {
+                while let Some(source) = sources.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    source.prefetch();
+                }
+            });
+        }
+    });
+}
+
+/// A warning for each variable of `definitions` defined with different values by different
+/// sources, naming each source once
+fn collisions(definitions: IndexMap<String, Vec<(String, String)>>) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+    for (key, definitions) in definitions {
+        let mut sources: Vec<String> = Vec::new();
+        for (source, _) in &definitions {
+            if !sources.contains(source) {
+                sources.push(source.clone());
+            }
+        }
+        let differ = definitions
+            .iter()
+            .any(|(_, value)| *value != definitions[0].1);
+        if sources.len() > 1 && differ {
+            warnings.push(Warning::KeyCollision { key, sources });
+        }
+    }
+    warnings
+}
+
+fn duplicate_warning(key: &str, previous: &str, name: &str) -> Warning {
+    Warning::DuplicateKey {
+        key: key.to_owned(),
+        previous: previous.to_owned(),
+        source: name.to_owned(),
+    }
+}
+
+/// An env file, including the files it includes
+#[derive(Debug)]
+pub struct FileSource {
+    path: PathBuf,
+    parse_options: ParseOptions,
+    key_prefix: Option<KeyPrefix>,
+    key_map: HashMap<String, String>,
+    implicit: bool,
+    cache: bool,
+    /// The contents read by `prefetch`, taken by `load`
+    prefetched: Prefetched<Vec<u8>>,
+}
+
+impl FileSource {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileSource {
+            path: path.into(),
+            parse_options: ParseOptions::default(),
+            key_prefix: None,
+            key_map: HashMap::new(),
+            implicit: false,
+            cache: false,
+            prefetched: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn with_parse_options(mut self, parse_options: ParseOptions) -> Self {
+        self.parse_options = parse_options;
+        self
+    }
+
+    pub(crate) fn with_renaming(
+        mut self,
+        key_prefix: Option<KeyPrefix>,
+        key_map: HashMap<String, String>,
+    ) -> Self {
+        self.key_prefix = key_prefix;
+        self.key_map = key_map;
+        self
+    }
+
+    /// Mark the file as loaded implicitly, subject to the [`DangerPolicy`]
+    pub(crate) fn implicit(mut self, implicit: bool) -> Self {
+        self.implicit = implicit;
+        self
+    }
+
+    /// Keep the parsed variables in the cache, see [`cache`]
+    pub(crate) fn cached(mut self, cache: bool) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    fn load_cached(&self, lookup: Lookup) -> Result<Vec<(String, String)>, Error> {
+        if let Some(vars) = cache::get(&self.path, &self.parse_options) {
+            log!(Debug, "{}: loaded from cache", self.path.to_string_lossy());
+            return Ok(vars);
+        }
+        // Variables depending on the environment can't be cached
+        let used_lookup = Cell::new(false);
+        let lookup = |key: &str| {
+            used_lookup.set(true);
+            lookup(key)
+        };
+        let mut opened = Vec::new();
+        let vars = load_env_file(
+            &self.path,
+            self.take_prefetched(),
+            &self.parse_options,
+            &lookup,
+            &mut Vec::new(),
+            &mut opened,
+        )?;
+        // Nor can decrypted ones, which are never written to disk
+        let decrypted = opened.iter().any(|path| gpg::is_encrypted(path));
+        if !used_lookup.get() && !self.parse_options.command_subst && !decrypted {
+            cache::put(&self.path, &self.parse_options, &opened, &vars);
+        }
+        Ok(vars)
+    }
+
+    fn take_prefetched(&self) -> Option<Result<Vec<u8>, Error>> {
+        self.prefetched.lock().ok()?.take()
+    }
+}
+
+impl EnvSource for FileSource {
+    fn name(&self) -> String {
+        self.path.to_string_lossy().into_owned()
+    }
+
+    fn path(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+
+    fn load(&self, lookup: Lookup) -> Result<Vec<(String, String)>, Error> {
+        let vars = if self.cache {
+            self.load_cached(lookup)?
+        } else {
+            let (mut stack, mut opened) = (Vec::new(), Vec::new());
+            load_env_file(
+                &self.path,
+                self.take_prefetched(),
+                &self.parse_options,
+                lookup,
+                &mut stack,
+                &mut opened,
+            )?
+        };
+        Ok(vars
+            .into_iter()
+            .map(|(key, value)| {
+                (
+                    rename_key(key, self.key_prefix.as_ref(), &self.key_map),
+                    value,
+                )
+            })
+            .collect())
+    }
+
+    fn prefetch(&self) {
+        // Cached files aren't read again
+        if self.cache && cache::get(&self.path, &self.parse_options).is_some() {
+            return;
+        }
+        let text = read_env_file(&self.path, &self.parse_options);
+        if let Ok(mut prefetched) = self.prefetched.lock() {
+            *prefetched = Some(text);
+        }
+    }
+
+    fn is_implicit(&self) -> bool {
+        self.implicit
+    }
+
+    fn is_secret(&self) -> bool {
+        gpg::is_encrypted(&self.path)
+    }
+}
+
+/// An env document read from standard input
+#[derive(Debug, Default)]
+pub struct StdinSource {
+    parse_options: ParseOptions,
+}
+
+impl StdinSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn with_parse_options(mut self, parse_options: ParseOptions) -> Self {
+        self.parse_options = parse_options;
+        self
+    }
+}
+
+impl EnvSource for StdinSource {
+    fn name(&self) -> String {
+        "<stdin>".to_owned()
+    }
+
+    fn load(&self, lookup: Lookup) -> Result<Vec<(String, String)>, Error> {
+        load_doc(
+            io::stdin().lock(),
+            &self.name(),
+            &self.parse_options,
+            lookup,
+        )
+    }
+}
+
+/// Parse an env document the way enw parses `.env` files, with the default dotenv dialect
+///
+/// Each line is a `KEY=VALUE` assignment, a comment, or a directive such as
+/// `# enw:dialect docker`. Values may be quoted, and are otherwise taken as they are, as
+/// without `--expand`, `--allow-command-subst` and `--interpret-escapes`. `# enw:include`
+/// directives are ignored like other comments, as the document has no location to resolve
+/// paths from. Keys are returned in the order they are defined, with duplicates kept. Errors
+/// are [`Error::Parse`] with the 1-based line number of the entry.
+///
+/// ```
+/// let vars = enw::parse_str("# settings\nHOST=localhost\nGREETING='hello $USER'\n").unwrap();
+/// assert_eq!(
+///     vars,
+///     [
+///         ("HOST".to_owned(), "localhost".to_owned()),
+///         ("GREETING".to_owned(), "hello $USER".to_owned())
+///     ]
+/// );
+/// ```
+pub fn parse_str(doc: &str) -> Result<Vec<(String, String)>, Error> {
+    parse_reader(doc.as_bytes())
+}
+
+/// Parse an env document read from `reader` as [`parse_str`] does. Lines are read as they are
+/// parsed, so an error on an early line is reported without reading the rest.
+pub fn parse_reader(reader: impl BufRead) -> Result<Vec<(String, String)>, Error> {
+    load_doc(reader, "<input>", &ParseOptions::default(), &|_| None)
+}
+
+/// Parse the env document read from `reader`, named `name` in errors, without following
+/// includes
+fn load_doc(
+    reader: impl BufRead,
+    name: &str,
+    options: &ParseOptions,
+    lookup: Lookup,
+) -> Result<Vec<(String, String)>, Error> {
+    let mut vars = Vec::new();
+    let mut types = Types::new();
+    let dialect = Cell::new(options.dialect);
+    let mut conditions = Conditions::default();
+    for entry in doc_lines(reader, &dialect) {
+        let (line_number, line) = entry?;
+        let options = ParseOptions {
+            dialect: dialect.get(),
+            ..options.clone()
+        };
+        let is_directive = conditions
+            .directive(
+                &line,
+                line_number,
+                options.profile.as_deref(),
+                &|key: &str| find_var(&vars, key, lookup),
+            )
+            .map_err(|err| parse_error(name, line_number, err))?;
+        if is_directive || !conditions.is_active() {
+            continue;
+        }
+        push_doc_line(&line, &options, &dialect, lookup, &mut types, &mut vars)
+            .map_err(|err| parse_error(name, line_number, err))?;
+    }
+    if let Some(line_number) = conditions.unclosed() {
+        return Err(parse_error(name, line_number, UNCLOSED_IF.into()));
+    }
+    Ok(vars)
+}
+
+/// A JSON object, with nested objects and arrays flattened to `PARENT_CHILD` and `LIST_0` keys
+#[derive(Debug)]
+pub struct JsonSource {
+    /// The file, or `-` for standard input
+    path: PathBuf,
+    separator: String,
+}
+
+impl JsonSource {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        JsonSource {
+            path: path.into(),
+            separator: "_".to_owned(),
+        }
+    }
+
+    /// Set what joins the keys of nested values, `_` by default
+    pub fn with_separator(mut self, separator: impl Into<String>) -> Self {
+        self.separator = separator.into();
+        self
+    }
+}
+
+impl EnvSource for JsonSource {
+    fn name(&self) -> String {
+        if self.path == Path::new("-") {
+            "<stdin>".to_owned()
+        } else {
+            self.path.to_string_lossy().into_owned()
+        }
+    }
+
+    fn load(&self, _lookup: Lookup) -> Result<Vec<(String, String)>, Error> {
+        let mut text = Vec::new();
+        if self.path == Path::new("-") {
+            io::stdin().lock().read_to_end(&mut text)?;
+        } else {
+            text = fs::read(&self.path).map_err(|err| Error::io(&self.path, err))?;
+        }
+        let value: Value = serde_json::from_slice(&text)
+            .map_err(|err| Error::Other(format!("invalid JSON: {err}")))?;
+        if !value.is_object() {
+            return Err("expected a JSON object".into());
+        }
+        let mut vars = Vec::new();
+        flatten_json(String::new(), value, &self.separator, &mut vars)?;
+        Ok(vars)
+    }
+}
+
+pub(crate) fn flatten_json(
+    key: String,
+    value: Value,
+    separator: &str,
+    vars: &mut Vec<(String, String)>,
+) -> Result<(), Error> {
+    let child_key = |child: &str| match key.as_str() {
+        "" => child.to_owned(),
+        key => format!("{key}{separator}{child}"),
+    };
+    let value = match value {
+        Value::Object(object) => {
+            for (child, value) in object {
+                flatten_json(child_key(&child), value, separator, vars)?;
+            }
+            return Ok(());
+        }
+        Value::Array(items) => {
+            for (index, value) in items.into_iter().enumerate() {
+                flatten_json(child_key(&index.to_string()), value, separator, vars)?;
+            }
+            return Ok(());
+        }
+        Value::Null => String::new(),
+        Value::String(value) => value,
+        value => value.to_string(),
+    };
+    if !key_is_valid(&key) || key.contains('=') {
+        return Err(Error::Other(format!("invalid key: {key}")));
+    }
+    vars.push((key, value));
+    Ok(())
+}
+
+/// Variables given as `NAME=VALUE` pairs, e.g. on the command line
+#[derive(Debug, Default)]
+pub struct PairsSource {
+    vars: Vec<(String, String)>,
+}
+
+impl PairsSource {
+    pub fn new(vars: Vec<(String, String)>) -> Self {
+        PairsSource { vars }
+    }
+}
+
+impl EnvSource for PairsSource {
+    fn name(&self) -> String {
+        "command line".to_owned()
+    }
+
+    fn load(&self, _lookup: Lookup) -> Result<Vec<(String, String)>, Error> {
+        Ok(self.vars.clone())
+    }
+
+    fn overrides(&self) -> bool {
+        true
+    }
+}
+
+/// The environment a running process was started with, read from `/proc/PID/environ` on Linux
+#[derive(Debug)]
+pub struct ProcessSource {
+    pid: u32,
+}
+
+impl ProcessSource {
+    pub fn new(pid: u32) -> Self {
+        ProcessSource { pid }
+    }
+}
+
+impl EnvSource for ProcessSource {
+    fn name(&self) -> String {
+        format!("process {}", self.pid)
+    }
+
+    fn load(&self, _lookup: Lookup) -> Result<Vec<(String, String)>, Error> {
+        procenv::read_environ(self.pid)
+    }
+}
+
+/// Values added to the current values of variables with `--append` and `--prepend`
+#[derive(Debug, Default)]
+pub(crate) struct MergeSource {
+    merges: Vec<(Merge, String, String)>,
+    separator: String,
+}
+
+impl MergeSource {
+    pub(crate) fn new(merges: Vec<(Merge, String, String)>, separator: &str) -> Self {
+        MergeSource {
+            merges,
+            separator: separator.to_owned(),
+        }
+    }
+}
+
+impl EnvSource for MergeSource {
+    fn name(&self) -> String {
+        "command line".to_owned()
+    }
+
+    fn load(&self, lookup: Lookup) -> Result<Vec<(String, String)>, Error> {
+        let mut vars: Vec<(String, String)> = Vec::new();
+        for (merge, key, value) in &self.merges {
+            let current = find_var(&vars, key, lookup);
+            vars.push((key.clone(), merge.apply(current, value, &self.separator)));
+        }
+        Ok(vars)
+    }
+
+    fn overrides(&self) -> bool {
+        true
+    }
+}
+
+/// Renaming applied to the keys of an env file
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) enum KeyPrefix {
+    Strip(String),
+    Add(String),
+}
+
+/// Apply the prefix of the file a key was loaded from, then any `--map` renaming
+pub(crate) fn rename_key(
+    key: String,
+    prefix: Option<&KeyPrefix>,
+    key_map: &HashMap<String, String>,
+) -> String {
+    let key = match prefix {
+        Some(KeyPrefix::Strip(prefix)) => match key.strip_prefix(prefix.as_str()) {
+            Some(stripped) if !stripped.is_empty() => stripped.to_owned(),
+            _ => key,
+        },
+        Some(KeyPrefix::Add(prefix)) => format!("{prefix}{key}"),
+        None => key,
+    };
+    key_map.get(&key).cloned().unwrap_or(key)
+}
+
+/// Read and parse an env file. The variables of files included with `# enw:include PATH`
+/// directives are layered below those of the including file, and relative paths are resolved
+/// from the directory of the including file, or of its target if it is a symlink and
+/// [`Symlinks::Resolve`] is set. Included variables can be referenced in expansions following
+/// the directive.
+/// The contents of the env file at `path`, decrypted if needed
+fn read_env_file(path: &Path, options: &ParseOptions) -> Result<Vec<u8>, Error> {
+    let name = || path.to_string_lossy().into_owned();
+    let is_symlink = fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_symlink());
+    if is_symlink && options.symlinks == Symlinks::Refuse {
+        return Err(Error::Other(format!(
+            "{} is a symlink, not followed with --no-follow-symlinks",
+            path.to_string_lossy()
+        )));
+    }
+    if gpg::is_encrypted(path) {
+        timings::time(Step::Decrypt, name, || gpg::decrypt(path))
+    } else {
+        timings::time(Step::Read, name, || fs::read(path)).map_err(|err| Error::io(path, err))
+    }
+}
+
+/// Load the env file at `path` and the files it includes, using the contents `prefetched` with
+/// [`read_env_file`] if given
+fn load_env_file(
+    path: &Path,
+    prefetched: Option<Result<Vec<u8>, Error>>,
+    options: &ParseOptions,
+    lookup: Lookup,
+    stack: &mut Vec<PathBuf>,
+    opened: &mut Vec<PathBuf>,
+) -> Result<Vec<(String, String)>, Error> {
+    let name = || path.to_string_lossy().into_owned();
+    let text = match prefetched {
+        Some(text) => text?,
+        None => read_env_file(path, options)?,
+    };
+    let is_symlink = fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_symlink());
+    // Pipes such as `<(command)` have no canonical path, but can't be part of a cycle either
+    let canonical = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+    if stack.contains(&canonical) {
+        return Err(format!("include cycle detected at {}", path.to_string_lossy()).into());
+    }
+    let base_dir = match options.symlinks {
+        Symlinks::Resolve if is_symlink => canonical.parent(),
+        _ => path.parent(),
+    }
+    .unwrap_or_else(|| Path::new(""))
+    .to_owned();
+    opened.push(canonical.clone());
+    stack.push(canonical);
+    let mut included: Vec<(String, String)> = Vec::new();
+    let mut vars = Vec::new();
+    let mut types = Types::new();
+    let dialect = Cell::new(options.dialect);
+    let mut conditions = Conditions::default();
+    let started = Instant::now();
+    for entry in doc_lines(io::Cursor::new(text), &dialect) {
+        let (line_number, line) = entry.map_err(|err| Error::io(path, err))?;
+        let options = &ParseOptions {
+            dialect: dialect.get(),
+            ..options.clone()
+        };
+        let lookup = |key: &str| find_var(&included, key, lookup);
+        let is_directive = conditions
+            .directive(
+                &line,
+                line_number,
+                options.profile.as_deref(),
+                &|key: &str| find_var(&vars, key, &lookup),
+            )
+            .map_err(|err| parse_error(&path.to_string_lossy(), line_number, err))?;
+        if is_directive || !conditions.is_active() {
+            continue;
+        }
+        if let Some(include) = line.trim().strip_prefix(INCLUDE_DIRECTIVE) {
+            let include_path = base_dir.join(string_to_os(include.trim()));
+            log!(
+                Info,
+                "{}: including {}",
+                path.to_string_lossy(),
+                include_path.to_string_lossy()
+            );
+            let include_vars = load_env_file(&include_path, None, options, &lookup, stack, opened)?;
+            included.extend(include_vars);
+        } else {
+            push_doc_line(&line, options, &dialect, &lookup, &mut types, &mut vars)
+                .map_err(|err| parse_error(&path.to_string_lossy(), line_number, err))?;
+        }
+    }
+    if let Some(line_number) = conditions.unclosed() {
+        return Err(parse_error(
+            &path.to_string_lossy(),
+            line_number,
+            UNCLOSED_IF.into(),
+        ));
+    }
+    timings::record(Step::Parse, name, started);
+    stack.pop();
+    included.extend(vars);
+    Ok(included)
+}
+
+/// Parse a line of an env document onto `vars`, which take precedence over `lookup` during
+/// expansion. Values are normalized to the types annotated inline or in `types`, which type
+/// directives add to. Dialect directives set `dialect` for the following lines.
+fn push_doc_line(
+    line: &str,
+    options: &ParseOptions,
+    dialect: &Cell<Dialect>,
+    lookup: Lookup,
+    types: &mut Types,
+    vars: &mut Vec<(String, String)>,
+) -> Result<(), Error> {
+    if let Some(annotations) = line.trim().strip_prefix(TYPE_DIRECTIVE) {
+        return parse_type_directive(annotations, types);
+    }
+    if let Some(keys) = line.trim().strip_prefix(UNSET_DIRECTIVE) {
+        for key in keys.split_whitespace() {
+            if !key_is_valid(key) {
+                return Err(format!("invalid key {key:?}").into());
+            }
+            vars.push((key.to_owned(), UNSET.to_owned()));
+        }
+        return Ok(());
+    }
+    if let Some(name) = line.trim().strip_prefix(DIALECT_DIRECTIVE) {
+        let name = name.trim();
+        dialect.set(Dialect::from_name(name).ok_or_else(|| format!("unknown dialect {name:?}"))?);
+        return Ok(());
+    }
+    let parsed = parse_doc_line(line, options, &|key: &str| find_var(vars, key, lookup));
+    if let Some(var) = parsed {
+        let (key, value) = var?;
+        let (key, inline_type) = split_annotated_key(key);
+        let value = match inline_type.or_else(|| types.get(key).copied()) {
+            Some(value_type) => value_type
+                .normalize(&value)
+                .map_err(|message| format!("{key}: {message}"))?,
+            None => value.into_owned(),
+        };
+        vars.push((key.to_owned(), value));
+    }
+    Ok(())
+}
+
+/// The lines of `reader`, without line endings, and with bytes that aren't valid UTF-8 kept.
+/// Lines may end with `\r\n` as well as `\n`, and a UTF-8 byte order mark is skipped.
+fn byte_lines(reader: impl BufRead) -> impl Iterator<Item = io::Result<String>> {
+    reader.split(b'\n').enumerate().map(|(index, line)| {
+        let mut line = line?;
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        let line = match line.strip_prefix(UTF8_BOM) {
+            Some(rest) if index == 0 => rest,
+            _ => &line,
+        };
+        Ok(bytes_to_string(line).into_owned())
+    })
+}
+
+/// The entries of an env document with the 1-based number of the line they start on. Entries
+/// are single lines unless the dialect, which may change while reading, allows continuing them
+/// on the following lines.
+pub(crate) fn doc_lines<'a>(
+    reader: impl BufRead + 'a,
+    dialect: &'a Cell<Dialect>,
+) -> impl Iterator<Item = io::Result<(usize, String)>> + 'a {
+    let mut lines = byte_lines(reader);
+    let mut line_number = 0;
+    std::iter::from_fn(move || {
+        line_number += 1;
+        let start = line_number;
+        let mut entry = match lines.next()? {
+            Ok(line) => line,
+            Err(err) => return Some(Err(err)),
+        };
+        let options = ParseOptions {
+            dialect: dialect.get(),
+            ..Default::default()
+        };
+        while is_continued(&entry, &options) {
+            match lines.next() {
+                Some(Ok(line)) => {
+                    line_number += 1;
+                    entry.push('\n');
+                    entry.push_str(&line);
+                }
+                Some(Err(err)) => return Some(Err(err)),
+                None => break,
+            }
+        }
+        Some(Ok((start, entry)))
+    })
+}
+
+/// Attribute an error of parsing an entry to the line it starts on
+fn parse_error(file: &str, line: usize, err: Error) -> Error {
+    Error::Parse {
+        file: file.to_owned(),
+        line,
+        message: err.to_string(),
+    }
+}
+
+/// The value of the last definition of `key` among `vars`, or from `lookup` if there is none
+pub(crate) fn find_var(vars: &[(String, String)], key: &str, lookup: Lookup) -> Option<String> {
+    match vars.iter().rev().find(|(k, _)| k == key) {
+        Some((_, value)) if value == UNSET => None,
+        Some((_, value)) => Some(value.clone()),
+        None => lookup(key),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_registry_precedence_and_lookup() {
+        let mut registry = SourceRegistry::new();
+        registry
+            .push(PairsSource::new(vec![
+                ("A".to_owned(), "1".to_owned()),
+                ("B".to_owned(), "1".to_owned()),
+            ]))
+            .push(FileSource::new("tests/data/include/base.env"))
+            .push(PairsSource::new(vec![("B".to_owned(), "2".to_owned())]));
+        let env_vars: Vec<_> = registry.resolve(&|_| None).unwrap().into_iter().collect();
+        assert_eq!(
+            env_vars,
+            vec![
+                ("A".to_owned(), "1".to_owned()),
+                ("B".to_owned(), "2".to_owned()),
+                ("BASE".to_owned(), "base".to_owned()),
+                ("OVERRIDDEN".to_owned(), "base".to_owned()),
+            ]
+        );
+        let mut registry = SourceRegistry::new();
+        registry
+            .push(PairsSource::new(vec![
+                ("Z".to_owned(), "1".to_owned()),
+                ("A".to_owned(), "1".to_owned()),
+                ("Z".to_owned(), "2".to_owned()),
+            ]))
+            .push(PairsSource::new(vec![
+                ("M".to_owned(), "1".to_owned()),
+                ("A".to_owned(), "2".to_owned()),
+            ]));
+        let env_vars: Vec<_> = registry.resolve(&|_| None).unwrap().into_iter().collect();
+        assert_eq!(
+            env_vars,
+            vec![
+                ("Z".to_owned(), "2".to_owned()),
+                ("A".to_owned(), "2".to_owned()),
+                ("M".to_owned(), "1".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_duplicate_policy() {
+        let mut registry = SourceRegistry::new();
+        registry
+            .push(FileSource::new("tests/data/include/base.env"))
+            .push(FileSource::new("tests/data/include/.env"))
+            .push(PairsSource::new(vec![(
+                "BASE".to_owned(),
+                "cli".to_owned(),
+            )]));
+        let resolve = |registry: &SourceRegistry| {
+            let mut warnings = Vec::new();
+            registry
+                .resolve_with_warnings(&|_| None, &mut warnings)
+                .map(|vars| {
+                    let values = (vars["BASE"].clone(), vars["OVERRIDDEN"].clone());
+                    (values, !warnings.is_empty())
+                })
+        };
+        let values = |base: &str, overridden: &str| (base.to_owned(), overridden.to_owned());
+        assert_eq!(resolve(&registry).unwrap(), (values("cli", "top"), false));
+        registry.on_duplicate(DuplicatePolicy::Warn);
+        assert_eq!(resolve(&registry).unwrap(), (values("cli", "top"), true));
+        registry.on_duplicate(DuplicatePolicy::FirstWins);
+        assert_eq!(resolve(&registry).unwrap(), (values("cli", "base"), false));
+        registry.on_duplicate(DuplicatePolicy::Error);
+        assert!(resolve(&registry).is_err());
+    }
+
+    #[test]
+    fn test_report_collisions() {
+        let mut registry = SourceRegistry::new();
+        registry
+            .push(FileSource::new("tests/data/include/base.env"))
+            .push(FileSource::new("tests/data/include/.env"))
+            .report_collisions(true);
+        let mut warnings = Vec::new();
+        registry
+            .resolve_with_warnings(&|_| None, &mut warnings)
+            .unwrap();
+        // BASE is defined twice as well, with the same value
+        assert_eq!(
+            warnings,
+            [Warning::KeyCollision {
+                key: "OVERRIDDEN".to_owned(),
+                sources: vec![
+                    "tests/data/include/base.env".to_owned(),
+                    "tests/data/include/.env".to_owned()
+                ],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_normalize_keys() {
+        let mut registry = SourceRegistry::new();
+        registry
+            .push(PairsSource::new(vec![
+                ("Path".to_owned(), "/a".to_owned()),
+                ("db_url".to_owned(), "x".to_owned()),
+            ]))
+            .push(PairsSource::new(vec![("PATH".to_owned(), "/b".to_owned())]));
+        assert_eq!(registry.resolve(&|_| None).unwrap().len(), 3);
+        registry.normalize_keys(KeyCase::Upper);
+        let env_vars: Vec<_> = registry.resolve(&|_| None).unwrap().into_iter().collect();
+        assert_eq!(
+            env_vars,
+            vec![
+                ("PATH".to_owned(), "/b".to_owned()),
+                ("DB_URL".to_owned(), "x".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_json_source() {
+        let vars = JsonSource::new("tests/data/config.json")
+            .load(&|_| None)
+            .unwrap();
+        let vars: Vec<_> = vars.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        assert_eq!(
+            vars,
+            [
+                ("NAME", "app"),
+                ("DB_HOST", "localhost"),
+                ("DB_PORT", "5432"),
+                ("DB_REPLICAS_0", "replica-1"),
+                ("DB_REPLICAS_1", "replica-2"),
+                ("DEBUG", "true"),
+                ("EMPTY", ""),
+            ]
+        );
+        let vars = JsonSource::new("tests/data/config.json")
+            .with_separator("__")
+            .load(&|_| None)
+            .unwrap();
+        assert_eq!(vars[1], ("DB__HOST".to_owned(), "localhost".to_owned()));
+        assert!(JsonSource::new("tests/data/.env")
+            .load(&|_| None)
+            .unwrap_err()
+            .to_string()
+            .starts_with("invalid JSON"));
+    }
+
+    #[test]
+    fn test_danger_policy() {
+        let resolve = |implicit: bool, policy: DangerPolicy| {
+            let mut registry = SourceRegistry::new();
+            registry
+                .push(FileSource::new("tests/data/dangerous.env").implicit(implicit))
+                .on_dangerous(policy);
+            let mut warnings = Vec::new();
+            registry
+                .resolve_with_warnings(&|_| None, &mut warnings)
+                .map(|vars| (vars.len(), warnings.len()))
+        };
+        let err = resolve(true, DangerPolicy::default()).unwrap_err();
+        assert!(err.to_string().contains("LD_PRELOAD"), "{}", err);
+        assert_eq!(resolve(false, DangerPolicy::default()).unwrap(), (2, 0));
+        assert_eq!(resolve(true, DangerPolicy::allow_all()).unwrap(), (2, 0));
+        let warn_only = DangerPolicy::default().with_refused(Vec::new());
+        assert_eq!(resolve(true, warn_only.clone()).unwrap(), (2, 1));
+        let warn_all = warn_only.with_warned(vec!["*".to_owned()]);
+        assert_eq!(resolve(true, warn_all).unwrap(), (2, 2));
+    }
+
+    #[test]
+    fn test_parse_error_line() {
+        let path = std::env::temp_dir().join(format!("enw-source-{}.env", std::process::id()));
+        std::fs::write(&path, "# comment\nA=1\nB='unterminated\n").unwrap();
+        let err = FileSource::new(&path).load(&|_| None).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(err, Error::Parse { line: 3, .. }), "{:?}", err);
+        let err = FileSource::new("no/such/file.env")
+            .load(&|_| None)
+            .unwrap_err();
+        assert!(matches!(err, Error::Io { path: Some(_), .. }), "{:?}", err);
+    }
+
+    #[test]
+    fn test_parse_str() {
+        let vars = parse_str("A=1\n# enw:dialect raw\nB=$A 'two'\nA=3\n").unwrap();
+        let pairs: Vec<_> = vars.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        assert_eq!(pairs, [("A", "1"), ("B", "$A 'two'"), ("A", "3")]);
+        let err = parse_reader(io::Cursor::new("A=1\nB='open\n")).unwrap_err();
+        assert!(
+            matches!(&err, Error::Parse { file, line: 2, .. } if file == "<input>"),
+            "{:?}",
+            err
+        );
+        assert!(parse_str("# enw:include other.env\n").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_unset_directive() {
+        let vars = parse_str("A=1\nB=2\n# enw:unset A B\nB=3\n").unwrap();
+        let pairs: Vec<_> = vars.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        assert_eq!(
+            pairs,
+            [
+                ("A", "1"),
+                ("B", "2"),
+                ("A", UNSET),
+                ("B", UNSET),
+                ("B", "3")
+            ]
+        );
+        assert!(parse_str("# enw:unset 1A\n").is_err());
+
+        let mut registry = SourceRegistry::new();
+        registry
+            .push(PairsSource::new(vec![("A".to_owned(), "1".to_owned())]))
+            .push(PairsSource::new(vec![("A".to_owned(), UNSET.to_owned())]))
+            .push(MergeSource::new(
+                vec![(Merge::Append, "A".to_owned(), "2".to_owned())],
+                ":",
+            ));
+        let fallback = |_: &str| Some("inherited".to_owned());
+        assert_eq!(registry.resolve(&fallback).unwrap()["A"], "2");
+    }
+
+    #[test]
+    fn test_crlf_and_bom() {
+        let path = std::env::temp_dir().join(format!("enw-crlf-{}.env", std::process::id()));
+        std::fs::write(
+            &path,
+            "\u{feff}A=1\r\nB=\"two\"\r\n# comment\r\nC=3\nD='four'  \r\n\r\nE=\r\nF=a \\\r\nb\r\n",
+        )
+        .unwrap();
+        let vars = FileSource::new(&path).load(&|_| None);
+        std::fs::remove_file(&path).unwrap();
+        let expected: Vec<_> = [
+            ("A", "1"),
+            ("B", "two"),
+            ("C", "3"),
+            ("D", "four"),
+            ("E", ""),
+            ("F", "a b"),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .collect();
+        assert_eq!(vars.unwrap(), expected);
+    }
+
+    #[test]
+    fn test_dialect_directive() {
+        let path = std::env::temp_dir().join(format!("enw-dialect-{}.env", std::process::id()));
+        std::fs::write(
+            &path,
+            "A=a#1\n# enw:dialect strict-dotenv\nB=b#2\n# enw:dialect raw\nC= 'c' \n",
+        )
+        .unwrap();
+        let vars = FileSource::new(&path).load(&|_| None);
+        std::fs::write(&path, "# enw:dialect yaml\n").unwrap();
+        let err = FileSource::new(&path).load(&|_| None).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        let expected: Vec<_> = [("A", "a"), ("B", "b#2"), ("C", " 'c' ")]
+            .into_iter()
+            .map(|(key, value)| (key.to_owned(), value.to_owned()))
+            .collect();
+        assert_eq!(vars.unwrap(), expected);
+        assert!(matches!(err, Error::Parse { line: 1, .. }), "{:?}", err);
+    }
+
+    #[test]
+    fn test_type_annotations() {
+        let vars = FileSource::new("tests/data/types.env")
+            .load(&|_| None)
+            .unwrap();
+        let vars: Vec<_> = vars.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        assert_eq!(
+            vars,
+            [
+                ("PORT", "80"),
+                ("DEBUG", "true"),
+                ("RATIO", "0.5"),
+                ("NAME", "app")
+            ]
+        );
+        let path = std::env::temp_dir().join(format!("enw-types-{}.env", std::process::id()));
+        std::fs::write(&path, "# enw:type PORT=int\nPORT=80a\n").unwrap();
+        let err = FileSource::new(&path).load(&|_| None).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert!(
+            err.to_string()
+                .ends_with(":2: PORT: expected an integer, got \"80a\""),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_rename_key() {
+        let key_map: HashMap<_, _> = [("DB_URL".to_owned(), "DATABASE_URL".to_owned())].into();
+        let strip = KeyPrefix::Strip("APP_".to_owned());
+        let add = KeyPrefix::Add("APP_".to_owned());
+        assert_eq!(
+            rename_key("APP_PORT".into(), Some(&strip), &key_map),
+            "PORT"
+        );
+        assert_eq!(rename_key("PORT".into(), Some(&strip), &key_map), "PORT");
+        assert_eq!(rename_key("APP_".into(), Some(&strip), &key_map), "APP_");
+        assert_eq!(rename_key("PORT".into(), Some(&add), &key_map), "APP_PORT");
+        assert_eq!(rename_key("DB_URL".into(), None, &key_map), "DATABASE_URL");
+        assert_eq!(
+            rename_key("APP_DB_URL".into(), Some(&strip), &key_map),
+            "DATABASE_URL"
+        );
+    }
+
+    #[test]
+    fn test_symlinks() {
+        let dir = std::env::temp_dir().join(format!("enw-symlinks-{}", std::process::id()));
+        let shared = dir.join("shared");
+        std::fs::create_dir_all(&shared).unwrap();
+        std::fs::write(shared.join("team.env"), "# enw:include base.env\nB=2\n").unwrap();
+        std::fs::write(shared.join("base.env"), "A=target\n").unwrap();
+        std::fs::write(dir.join("base.env"), "A=link\n").unwrap();
+        std::os::unix::fs::symlink(shared.join("team.env"), dir.join(".env")).unwrap();
+        let load = |symlinks| {
+            FileSource::new(dir.join(".env"))
+                .with_parse_options(ParseOptions {
+                    symlinks,
+                    ..Default::default()
+                })
+                .load(&|_| None)
+        };
+        let (follow, resolve, refuse) = (
+            load(Symlinks::Follow),
+            load(Symlinks::Resolve),
+            load(Symlinks::Refuse),
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+        let vars = |a: &str| {
+            vec![
+                ("A".to_owned(), a.to_owned()),
+                ("B".to_owned(), "2".to_owned()),
+            ]
+        };
+        assert_eq!(follow.unwrap(), vars("link"));
+        assert_eq!(resolve.unwrap(), vars("target"));
+        assert!(refuse.is_err());
+    }
+
+    fn doc_line() -> impl Strategy<Value = String> {
+        prop_oneof![
+            4 => r##"[A-Za-z_ ]{0,3}[+^]?=[a\\'"#$(){}:;` \t]{0,12}"##,
+            1 => "# enw:dialect (dotenv|strict-dotenv|docker|raw|systemd)",
+            1 => "# enw:(if|elif) !?(os=linux|[$][A-Za-z]{1,2})|# enw:else|# enw:endif",
+            1 => "# enw:type [A-Za-z]{1,2}:(int|bool|url)",
+            1 => r"\PC{0,16}",
+        ]
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        /// The same checks as the `parse` fuzz target, on documents close to valid ones
+        #[test]
+        fn prop_load_roundtrip(lines in prop::collection::vec(doc_line(), 0..8)) {
+            let path = std::env::temp_dir().join(format!("enw-prop-{}.env", std::process::id()));
+            let lookup = |key: &str| key.len().is_multiple_of(2).then(|| key.to_lowercase());
+            std::fs::write(&path, lines.join("\n")).unwrap();
+            let loaded = FileSource::new(&path).load(&lookup);
+            let reloaded = match loaded.as_ref().map(|vars| crate::to_dotenv_string(vars.clone())) {
+                Ok(Ok(doc)) => {
+                    std::fs::write(&path, doc).unwrap();
+                    Some(FileSource::new(&path).load(&lookup))
+                }
+                _ => None,
+            };
+            std::fs::remove_file(&path).unwrap();
+            if let Some(reloaded) = reloaded {
+                prop_assert_eq!(loaded.unwrap(), reloaded.unwrap());
+            }
+        }
+    }
+}
@@ -0,0 +1,149 @@
+//! The variables exported by `enw export --diff`, so that they can be unloaded again
+//!
+//! The shell hook runs enw at each prompt, and leaving a project should undo what entering it
+//! did. The variables set, with the values they had before, are therefore kept in the
+//! environment of the shell itself, as a JSON object in `ENW_STATE`.
+
+use indexmap::IndexMap;
+
+use crate::parse::Lookup;
+
+pub(crate) const STATE_VAR: &str = "ENW_STATE";
+
+/// A change to the environment of the shell
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) enum Change {
+    Set(String, String),
+    Unset(String),
+}
+
+/// The variables set by enw, with their values from before, if they were set at all
+#[derive(Debug, Default, Eq, PartialEq)]
+pub(crate) struct Snapshot {
+    previous: IndexMap<String, Option<String>>,
+}
+
+impl Snapshot {
+    /// Read the snapshot from `STATE_VAR`, or an empty one if it is unset or unreadable
+    pub(crate) fn load(lookup: Lookup) -> Self {
+        let previous = lookup(STATE_VAR)
+            .and_then(|state| serde_json::from_str(&state).ok())
+            .unwrap_or_default();
+        Snapshot { previous }
+    }
+
+    /// The value `key` had before enw set it, as variables are resolved against the
+    /// environment enw found rather than the one it left
+    pub(crate) fn original(&self, key: &str, current: Lookup) -> Option<String> {
+        match self.previous.get(key) {
+            Some(original) => original.clone(),
+            None => current(key),
+        }
+    }
+
+    /// How many variables enw has set
+    pub(crate) fn len(&self) -> usize {
+        self.previous.len()
+    }
+
+    fn serialize(&self) -> String {
+        serde_json::to_string(&self.previous).expect("a map of strings serializes")
+    }
+
+    /// The changes turning the current environment into one with `vars` set, restoring the
+    /// variables set before that `vars` no longer holds. The snapshot of the new environment
+    /// is set in `STATE_VAR`, or unset if there's nothing to undo.
+    pub(crate) fn changes(&self, vars: &[(String, String)], current: Lookup) -> Vec<Change> {
+        let mut changes = Vec::new();
+        let mut next = Snapshot::default();
+        for (key, original) in &self.previous {
+            if vars.iter().any(|(k, _)| k == key) {
+                next.previous.insert(key.clone(), original.clone());
+            } else {
+                changes.push(match original {
+                    Some(value) => Change::Set(key.clone(), value.clone()),
+                    None => Change::Unset(key.clone()),
+                });
+            }
+        }
+        for (key, value) in vars {
+            let current = current(key);
+            if current.as_ref() != Some(value) {
+                changes.push(Change::Set(key.clone(), value.clone()));
+            }
+            next.previous.entry(key.clone()).or_insert(current);
+        }
+        let state = next.serialize();
+        if !next.previous.is_empty() {
+            if current(STATE_VAR).as_ref() != Some(&state) {
+                changes.push(Change::Set(STATE_VAR.to_owned(), state));
+            }
+        } else if current(STATE_VAR).is_some() {
+            changes.push(Change::Unset(STATE_VAR.to_owned()));
+        }
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn apply(env: &mut HashMap<String, String>, changes: Vec<Change>) {
+        for change in changes {
+            match change {
+                Change::Set(key, value) => env.insert(key, value),
+                Change::Unset(key) => env.remove(&key),
+            };
+        }
+    }
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_enter_and_leave() {
+        let shell: HashMap<_, _> = [("PATH", "/bin"), ("HOME", "/home/me")]
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        let mut env = shell.clone();
+        let export = |env: &HashMap<String, String>, project: &[(String, String)]| {
+            let lookup = |key: &str| env.get(key).cloned();
+            Snapshot::load(&lookup).changes(project, &lookup)
+        };
+
+        let project = vars(&[("PATH", "/project/bin"), ("DB", "db")]);
+        let changes = export(&env, &project);
+        apply(&mut env, changes);
+        assert_eq!(env["PATH"], "/project/bin");
+        assert!(env.contains_key(STATE_VAR));
+        {
+            let lookup = |key: &str| env.get(key).cloned();
+            let snapshot = Snapshot::load(&lookup);
+            assert_eq!(snapshot.original("PATH", &lookup).as_deref(), Some("/bin"));
+            assert_eq!(snapshot.original("DB", &lookup), None);
+            assert_eq!(
+                snapshot.original("HOME", &lookup).as_deref(),
+                Some("/home/me")
+            );
+        }
+        // Nothing changes at the next prompt
+        assert_eq!(export(&env, &project), []);
+
+        let changes = export(&env, &vars(&[("DB", "other")]));
+        apply(&mut env, changes);
+        assert_eq!(env["PATH"], "/bin");
+        assert_eq!(env["DB"], "other");
+
+        let changes = export(&env, &[]);
+        apply(&mut env, changes);
+        assert_eq!(env, shell);
+    }
+}
@@ -0,0 +1,95 @@
+//! `enw status`, the state of the environment of the current directory for shell prompts
+//!
+//! The status is meant to be shown at each prompt, so it only hashes the implicit env file to
+//! tell whether it's allowed, and only resolves the environment when it is.
+
+use std::path::PathBuf;
+
+use crate::trust::Trust;
+
+#[derive(Debug, Default, Eq, PartialEq)]
+pub(crate) struct Status {
+    /// The implicitly loaded env file of the current directory, if there is one
+    pub(crate) file: Option<PathBuf>,
+    pub(crate) trust: Option<Trust>,
+    /// How many variables the shell hook has set
+    pub(crate) vars: usize,
+    /// Whether the next `enw export --diff` would change the environment, e.g. because a file
+    /// changed since the last one
+    pub(crate) changed: bool,
+}
+
+impl Status {
+    /// `KEY VALUE` lines that stay stable across versions, for scripts and prompts
+    pub(crate) fn format_porcelain(&self) -> String {
+        let file = match &self.file {
+            Some(file) => file.to_string_lossy(),
+            None => "-".into(),
+        };
+        format!(
+            "file {file}\ntrust {}\nvars {}\nchanged {}\n",
+            self.trust_name(),
+            self.vars,
+            if self.changed { "yes" } else { "no" }
+        )
+    }
+
+    pub(crate) fn format_text(&self) -> String {
+        let Some(file) = &self.file else {
+            return format!("no env file, {} variables loaded\n", self.vars);
+        };
+        let trust = match self.trust {
+            Some(Trust::Allowed) | None => "",
+            Some(Trust::NotAllowed) => ", not allowed, run `enw allow`",
+            Some(Trust::Modified) => ", modified since allowed, run `enw allow` again",
+        };
+        let changed = match self.changed {
+            true => ", changed since loaded",
+            false => "",
+        };
+        format!(
+            "{}{trust}, {} variables loaded{changed}\n",
+            file.to_string_lossy(),
+            self.vars
+        )
+    }
+
+    fn trust_name(&self) -> &'static str {
+        match self.trust {
+            None => "-",
+            Some(Trust::Allowed) => "allowed",
+            Some(Trust::NotAllowed) => "not-allowed",
+            Some(Trust::Modified) => "modified",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        let mut status = Status::default();
+        assert_eq!(
+            status.format_porcelain(),
+            "file -\ntrust -\nvars 0\nchanged no\n"
+        );
+        assert_eq!(status.format_text(), "no env file, 0 variables loaded\n");
+        status = Status {
+            file: Some(PathBuf::from("/app/.env")),
+            trust: Some(Trust::Modified),
+            vars: 3,
+            changed: true,
+        };
+        assert_eq!(
+            status.format_porcelain(),
+            "file /app/.env\ntrust modified\nvars 3\nchanged yes\n"
+        );
+        assert_eq!(
+            status.format_text(),
+            "/app/.env, modified since allowed, run `enw allow` again, 3 variables loaded, \
+             changed since loaded\n"
+        );
+    }
+}
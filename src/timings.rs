@@ -0,0 +1,168 @@
+//! How long the steps of resolving the environment take, reported with `--timings`
+//!
+//! Steps are only timed once [`start`] has been called, so that the instrumentation costs
+//! nothing otherwise. Steps nest: the load of a source includes reading and parsing its files,
+//! and the parse of a file includes the files it includes.
+
+use std::{
+    fmt::Write,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use serde_json::{json, Value};
+
+static RECORDING: AtomicBool = AtomicBool::new(false);
+static TIMINGS: Mutex<Vec<Timing>> = Mutex::new(Vec::new());
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Step {
+    /// Reading a file
+    Read,
+    /// Decrypting a file with gpg
+    Decrypt,
+    /// Fetching variables from a secrets backend
+    Fetch,
+    /// Parsing a file that has been read
+    Parse,
+    /// Loading a source, all of the above included
+    Load,
+    /// Resolving the whole environment
+    Resolve,
+}
+
+impl Step {
+    fn name(self) -> &'static str {
+        match self {
+            Step::Read => "read",
+            Step::Decrypt => "decrypt",
+            Step::Fetch => "fetch",
+            Step::Parse => "parse",
+            Step::Load => "load",
+            Step::Resolve => "resolve",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Timing {
+    step: Step,
+    /// The file, source or backend the step is about
+    name: String,
+    duration: Duration,
+}
+
+/// How `--timings` are reported
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum TimingsFormat {
+    Text,
+    Json,
+}
+
+impl TimingsFormat {
+    pub(crate) fn from_name(name: &str) -> Self {
+        match name {
+            "json" => TimingsFormat::Json,
+            _ => TimingsFormat::Text,
+        }
+    }
+}
+
+/// Start recording how long the steps take
+pub(crate) fn start() {
+    RECORDING.store(true, Ordering::Relaxed);
+}
+
+/// Run `f` as `step` about `name`, recording how long it took if recording
+pub(crate) fn time<T>(step: Step, name: impl FnOnce() -> String, f: impl FnOnce() -> T) -> T {
+    if !RECORDING.load(Ordering::Relaxed) {
+        return f();
+    }
+    let started = Instant::now();
+    let result = f();
+    record(step, name, started);
+    result
+}
+
+/// Record `step` about `name` as having run from `started` until now, if recording
+pub(crate) fn record(step: Step, name: impl FnOnce() -> String, started: Instant) {
+    if !RECORDING.load(Ordering::Relaxed) {
+        return;
+    }
+    let timing = Timing {
+        step,
+        name: name(),
+        duration: started.elapsed(),
+    };
+    TIMINGS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .push(timing);
+}
+
+/// The steps recorded so far, in the order they finished
+pub(crate) fn take() -> Vec<Timing> {
+    std::mem::take(&mut TIMINGS.lock().unwrap_or_else(|err| err.into_inner()))
+}
+
+/// A table of the steps, aligned for reading on a terminal
+pub(crate) fn format_text(timings: &[Timing]) -> String {
+    let mut out = String::new();
+    for timing in timings {
+        let millis = timing.duration.as_secs_f64() * 1000.0;
+        let _ = writeln!(
+            out,
+            "timings: {:<8}{millis:>10.3}ms  {}",
+            timing.step.name(),
+            timing.name
+        );
+    }
+    out
+}
+
+pub(crate) fn format_json(timings: &[Timing]) -> Value {
+    let steps: Vec<Value> = timings
+        .iter()
+        .map(|timing| {
+            json!({
+                "step": timing.step.name(),
+                "name": timing.name,
+                "seconds": timing.duration.as_secs_f64(),
+            })
+        })
+        .collect();
+    json!({ "timings": steps })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        let timings = [
+            Timing {
+                step: Step::Read,
+                name: ".env".to_owned(),
+                duration: Duration::from_micros(1500),
+            },
+            Timing {
+                step: Step::Resolve,
+                name: "environment".to_owned(),
+                duration: Duration::from_secs(2),
+            },
+        ];
+        assert_eq!(
+            format_text(&timings),
+            "timings: read         1.500ms  .env\n\
+             timings: resolve   2000.000ms  environment\n"
+        );
+        assert_eq!(
+            format_json(&timings)["timings"][1],
+            json!({"step": "resolve", "name": "environment", "seconds": 2.0})
+        );
+    }
+}
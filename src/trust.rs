@@ -0,0 +1,177 @@
+//! Env files allowed to be loaded automatically, as with `direnv allow`
+//!
+//! The implicit `.env` file and the `.enwrc` of a directory are only loaded once the directory
+//! has been allowed with `enw allow`, so that merely running a command in a freshly cloned
+//! repository can't inject variables into it. Allowing a directory pins the SHA-256 of those
+//! files, and any change to them, such as a pulled commit adding `LD_PRELOAD`, has to be allowed
+//! again.
+
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::{
+    config::CONFIG_FILE_NAME,
+    edit::write_atomically,
+    os::{bytes_to_string, os_to_string, string_to_bytes, string_to_os},
+    Error, DEFAULT_ENV_FILE_NAME, ENCRYPTED_ENV_FILE_NAME, LOCAL_ENV_FILE_NAME,
+};
+
+/// Path of the list of allowed files below the XDG data directory
+const ALLOWED_FILE: &str = "enw/allowed";
+
+/// The files of an allowed directory whose contents are pinned
+const PINNED_FILES: [&str; 4] = [
+    DEFAULT_ENV_FILE_NAME,
+    ENCRYPTED_ENV_FILE_NAME,
+    LOCAL_ENV_FILE_NAME,
+    CONFIG_FILE_NAME,
+];
+
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) enum Trust {
+    Allowed,
+    NotAllowed,
+    /// Allowed, but changed since
+    Modified,
+}
+
+/// The allowed files, one `SHA256 PATH` line each, with the canonical path of the directory.
+/// Lines without a hash, from before files were pinned, are dropped.
+#[derive(Debug, Default)]
+pub(crate) struct TrustStore {
+    files: Vec<(PathBuf, String)>,
+}
+
+impl TrustStore {
+    /// `$XDG_DATA_HOME/enw/allowed`, by default in `~/.local/share`
+    pub(crate) fn path() -> Result<PathBuf, Error> {
+        let data_dir = match env::var_os("XDG_DATA_HOME").filter(|dir| !dir.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => env::var_os("HOME")
+                .map(|home| Path::new(&home).join(".local/share"))
+                .ok_or("neither XDG_DATA_HOME nor HOME is set")?,
+        };
+        Ok(data_dir.join(ALLOWED_FILE))
+    }
+
+    pub(crate) fn load() -> Result<Self, Error> {
+        let path = Self::path()?;
+        let text = match fs::read(&path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(Error::io(path, err)),
+        };
+        let files = bytes_to_string(&text)
+            .lines()
+            .filter_map(|line| line.split_once(' '))
+            .filter(|(hash, _)| hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit()))
+            .map(|(hash, file)| (PathBuf::from(string_to_os(file)), hash.to_owned()))
+            .collect();
+        Ok(TrustStore { files })
+    }
+
+    pub(crate) fn save(&self) -> Result<(), Error> {
+        let path = Self::path()?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|err| Error::io(dir, err))?;
+        }
+        let mut text = String::new();
+        for (file, hash) in &self.files {
+            text.push_str(hash);
+            text.push(' ');
+            text.push_str(&os_to_string(file.as_os_str()));
+            text.push('\n');
+        }
+        write_atomically(&path, &string_to_bytes(&text)).map_err(|err| Error::io(path, err))
+    }
+
+    /// Whether `file` in `dir` is allowed as it is now
+    pub(crate) fn trust(&self, dir: &Path, file: &Path) -> Result<Trust, Error> {
+        let (Ok(dir), Some(name)) = (dir.canonicalize(), file.file_name()) else {
+            return Ok(Trust::NotAllowed);
+        };
+        let Some((_, pinned)) = self.files.iter().find(|(path, _)| *path == dir.join(name)) else {
+            return Ok(Trust::NotAllowed);
+        };
+        if hash_file(file)? == *pinned {
+            Ok(Trust::Allowed)
+        } else {
+            Ok(Trust::Modified)
+        }
+    }
+
+    /// Allow `dir` with the current contents of its env files, returning its canonical path
+    pub(crate) fn allow(&mut self, dir: &Path) -> Result<PathBuf, Error> {
+        let dir = self.deny(dir)?;
+        for name in PINNED_FILES {
+            let file = dir.join(name);
+            if file.is_file() {
+                let hash = hash_file(&file)?;
+                self.files.push((file, hash));
+            }
+        }
+        Ok(dir)
+    }
+
+    /// Revoke `dir`, returning its canonical path
+    pub(crate) fn deny(&mut self, dir: &Path) -> Result<PathBuf, Error> {
+        let dir = dir.canonicalize().map_err(|err| Error::io(dir, err))?;
+        self.files.retain(|(file, _)| file.parent() != Some(&dir));
+        Ok(dir)
+    }
+}
+
+/// The SHA-256 of the contents of `file`, in hex
+fn hash_file(file: &Path) -> Result<String, Error> {
+    let contents = fs::read(file).map_err(|err| Error::io(file, err))?;
+    Ok(format!("{:x}", Sha256::digest(contents)))
+}
+
+/// Fail unless `file` in `dir` is allowed to be loaded automatically
+pub(crate) fn check_allowed(dir: &Path, file: &Path) -> Result<(), Error> {
+    match TrustStore::load()?.trust(dir, file)? {
+        Trust::Allowed => Ok(()),
+        Trust::NotAllowed => Err(Error::NotAllowed(file.to_owned())),
+        Trust::Modified => Err(Error::Modified(file.to_owned())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allow_and_deny() {
+        let dir = env::temp_dir().join(format!("enw-trust-{}", std::process::id()));
+        let file = dir.join(".env");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&file, "A=1\n").unwrap();
+        let mut store = TrustStore::default();
+        assert_eq!(store.trust(&dir, &file).unwrap(), Trust::NotAllowed);
+        let canonical = store.allow(&dir).unwrap();
+        assert!(canonical.is_absolute());
+        store.allow(&dir.join(".")).unwrap();
+        assert_eq!(store.files.len(), 1);
+        assert_eq!(store.trust(&dir, &file).unwrap(), Trust::Allowed);
+        assert_eq!(
+            store
+                .trust(Path::new("tests"), Path::new("tests/.env"))
+                .unwrap(),
+            Trust::NotAllowed
+        );
+
+        fs::write(&file, "A=1\nLD_PRELOAD=/tmp/evil.so\n").unwrap();
+        assert_eq!(store.trust(&dir, &file).unwrap(), Trust::Modified);
+        store.allow(&dir).unwrap();
+        assert_eq!(store.trust(&dir, &file).unwrap(), Trust::Allowed);
+
+        store.deny(&dir).unwrap();
+        assert_eq!(store.trust(&dir, &file).unwrap(), Trust::NotAllowed);
+        assert!(store.allow(Path::new("no/such/dir")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
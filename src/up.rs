@@ -0,0 +1,430 @@
+//! Running the processes of a Procfile together, with `enw up`
+//!
+//! ```text
+//! web: ./server --port $PORT
+//! worker: ./worker
+//! ```
+//!
+//! Directives at the start of an entry configure its process:
+//!
+//! - `!env FILE` loads an env file on top of the shared environment for that process alone,
+//!   e.g. `worker: !env .env.worker ./worker`
+//! - `!after NAME[,NAME]...` starts the process after the named ones and stops it before them,
+//!   e.g. `web: !after db ./server`
+//! - `!stop-signal SIGNAL` stops the process with SIGNAL, such as `INT` or `QUIT`, rather than
+//!   with `TERM`
+//!
+//! Variables can also be assigned inline as in the shell, e.g. `web: PORT=5001 ./server`.
+//!
+//! Each process is run by `sh -c` with the resolved environment, in a process group of its
+//! own, and each line of its output is prefixed with its name. Once a process exits, or enw
+//! receives SIGINT, SIGTERM or SIGHUP, the others are stopped, those that others start after
+//! last. Each is sent its stop signal, and SIGKILL if it is still running after the
+//! `--shutdown-timeout`. enw exits with the status of the process that exited first.
+
+#[cfg(unix)]
+use std::{
+    collections::HashSet,
+    fs, io,
+    os::unix::process::{CommandExt, ExitStatusExt},
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::atomic::{AtomicI32, Ordering},
+    thread::{self, JoinHandle},
+    time::Instant,
+};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::Error;
+#[cfg(unix)]
+use crate::{
+    capture::relay,
+    os::{bytes_to_string, string_to_os},
+};
+
+pub(crate) const DEFAULT_PROCFILE: &str = "Procfile";
+
+/// How long the processes have to exit after their stop signal before they are killed
+pub(crate) const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+#[cfg(unix)]
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+#[cfg(unix)]
+const SHUTDOWN_SIGNALS: [libc::c_int; 3] = [libc::SIGINT, libc::SIGTERM, libc::SIGHUP];
+
+/// The last shutdown signal received, or 0
+#[cfg(unix)]
+static RECEIVED_SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+#[cfg(unix)]
+extern "C" fn on_shutdown_signal(signal: libc::c_int) {
+    RECEIVED_SIGNAL.store(signal, Ordering::SeqCst);
+}
+
+/// A `NAME: COMMAND` line of a Procfile
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(not(unix), allow(dead_code))]
+pub(crate) struct ProcEntry {
+    pub(crate) name: String,
+    pub(crate) command: String,
+    /// Env files loaded for this process only
+    pub(crate) env_files: Vec<PathBuf>,
+    /// The processes started before and stopped after this one
+    after: Vec<String>,
+    stop_signal: i32,
+}
+
+/// A started process and the threads relaying its output
+#[cfg(unix)]
+struct Process<'a> {
+    entry: &'a ProcEntry,
+    child: Child,
+    relays: Vec<JoinHandle<io::Result<()>>>,
+    status: Option<ExitStatus>,
+    /// How many processes deep the process depends on, with those deeper stopped first
+    depth: usize,
+}
+
+#[cfg(unix)]
+pub(crate) fn load_procfile(path: &Path) -> Result<Vec<ProcEntry>, Error> {
+    let text = fs::read(path).map_err(|err| Error::io(path, err))?;
+    parse_procfile(&bytes_to_string(&text), &path.to_string_lossy())
+}
+
+/// Without Unix, there are no process groups or signals to run the processes with
+#[cfg(not(unix))]
+pub(crate) fn load_procfile(_: &Path) -> Result<Vec<ProcEntry>, Error> {
+    Err(Error::Usage("enw up is only supported on Unix".to_owned()))
+}
+
+#[cfg(unix)]
+fn parse_procfile(text: &str, file: &str) -> Result<Vec<ProcEntry>, Error> {
+    let mut entries = Vec::new();
+    let mut names = HashSet::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |message: &str| Error::Parse {
+            file: file.to_owned(),
+            line: index + 1,
+            message: message.to_owned(),
+        };
+        let (name, command) = line
+            .split_once(':')
+            .filter(|(name, command)| {
+                !name.is_empty()
+                    && name
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+                    && !command.trim().is_empty()
+            })
+            .ok_or_else(|| error("expected NAME: COMMAND"))?;
+        if !names.insert(name) {
+            return Err(error(&format!("{name} is defined more than once")));
+        }
+        let mut entry = ProcEntry {
+            name: name.to_owned(),
+            command: String::new(),
+            env_files: Vec::new(),
+            after: Vec::new(),
+            stop_signal: libc::SIGTERM,
+        };
+        let mut command = command.trim();
+        while command.starts_with('!') && !command[1..].starts_with(char::is_whitespace) {
+            let (directive, argument, rest) = command
+                .split_once(char::is_whitespace)
+                .and_then(|(directive, rest)| {
+                    let (argument, rest) = rest.trim_start().split_once(char::is_whitespace)?;
+                    Some((directive, argument, rest))
+                })
+                .ok_or_else(|| error("expected !DIRECTIVE ARGUMENT COMMAND"))?;
+            match directive {
+                "!env" => entry.env_files.push(PathBuf::from(argument)),
+                "!after" => entry.after.extend(argument.split(',').map(str::to_owned)),
+                "!stop-signal" => {
+                    entry.stop_signal = parse_signal(argument)
+                        .ok_or_else(|| error(&format!("unknown signal {argument}")))?
+                }
+                _ => return Err(error(&format!("unknown directive {directive}"))),
+            }
+            command = rest.trim_start();
+        }
+        entry.command = command.to_owned();
+        entries.push(entry);
+    }
+    if entries.is_empty() {
+        return Err(Error::Other(format!("{file}: no processes defined")));
+    }
+    depths(&entries).map_err(|err| err.in_source(file))?;
+    Ok(entries)
+}
+
+/// Parse a signal given by name, with or without `SIG`, or by number
+#[cfg(unix)]
+fn parse_signal(name: &str) -> Option<libc::c_int> {
+    let signal = match name.trim_start_matches("SIG") {
+        "HUP" => libc::SIGHUP,
+        "INT" => libc::SIGINT,
+        "QUIT" => libc::SIGQUIT,
+        "KILL" => libc::SIGKILL,
+        "USR1" => libc::SIGUSR1,
+        "USR2" => libc::SIGUSR2,
+        "TERM" => libc::SIGTERM,
+        "WINCH" => libc::SIGWINCH,
+        number => number.parse().ok().filter(|&n| n > 0 && n < 65)?,
+    };
+    Some(signal)
+}
+
+/// How many processes deep each entry depends on through `!after`, failing for unknown
+/// processes and cycles
+#[cfg(unix)]
+fn depths(entries: &[ProcEntry]) -> Result<Vec<usize>, Error> {
+    fn depth(
+        entries: &[ProcEntry],
+        index: usize,
+        depths: &mut [Option<usize>],
+        visiting: &mut Vec<usize>,
+    ) -> Result<usize, Error> {
+        if let Some(depth) = depths[index] {
+            return Ok(depth);
+        }
+        let entry = &entries[index];
+        if visiting.contains(&index) {
+            return Err(Error::Other(format!(
+                "{} depends on itself through !after",
+                entry.name
+            )));
+        }
+        visiting.push(index);
+        let mut result = 0;
+        for name in &entry.after {
+            let dependency = entries
+                .iter()
+                .position(|entry| &entry.name == name)
+                .ok_or_else(|| Error::Other(format!("{}: no process named {name}", entry.name)))?;
+            result = result.max(depth(entries, dependency, depths, visiting)? + 1);
+        }
+        visiting.pop();
+        depths[index] = Some(result);
+        Ok(result)
+    }
+    let mut depths = vec![None; entries.len()];
+    (0..entries.len())
+        .map(|index| depth(entries, index, &mut depths, &mut Vec::new()))
+        .collect()
+}
+
+/// Run the processes of `entries`, each with its environment, until one exits, returning the
+/// exit code for enw
+#[cfg(unix)]
+pub(crate) fn up(
+    entries: &[(ProcEntry, Vec<(String, String)>)],
+    shutdown_timeout: Duration,
+) -> Result<i32, Error> {
+    for signal in SHUTDOWN_SIGNALS {
+        let handler = on_shutdown_signal as extern "C" fn(libc::c_int);
+        unsafe { libc::signal(signal, handler as libc::sighandler_t) };
+    }
+    let width = entries.iter().map(|(entry, _)| entry.name.len()).max();
+    let procfile: Vec<_> = entries.iter().map(|(entry, _)| entry.clone()).collect();
+    let mut order: Vec<_> = entries.iter().zip(depths(&procfile)?).collect();
+    order.sort_by_key(|(_, depth)| *depth);
+    let mut processes = Vec::new();
+    for ((entry, env), depth) in order {
+        match start(entry, env, width.unwrap_or_default(), depth) {
+            Ok(process) => processes.push(process),
+            Err(err) => {
+                shut_down(&mut processes, shutdown_timeout);
+                return Err(err);
+            }
+        }
+    }
+    let code = loop {
+        let signal = RECEIVED_SIGNAL.load(Ordering::SeqCst);
+        if signal != 0 {
+            eprintln!("enw: received signal {signal}, stopping");
+            break 128 + signal;
+        }
+        if let Some((name, status)) = poll(&mut processes)? {
+            eprintln!("enw: {name} {}, stopping", describe(status));
+            break status
+                .code()
+                .or_else(|| status.signal().map(|signal| 128 + signal))
+                .unwrap_or(1);
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+    shut_down(&mut processes, shutdown_timeout);
+    Ok(code)
+}
+
+#[cfg(unix)]
+fn start<'a>(
+    entry: &'a ProcEntry,
+    env: &[(String, String)],
+    width: usize,
+    depth: usize,
+) -> Result<Process<'a>, Error> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(string_to_os(&entry.command))
+        .env_clear()
+        .envs(
+            env.iter()
+                .map(|(key, value)| (string_to_os(key), string_to_os(value))),
+        )
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // Signaling the group reaches what the shell starts as well
+        .process_group(0)
+        .spawn()
+        .map_err(|err| Error::exec("sh".to_owned(), err))?;
+    let prefix = format!("{:width$} | ", entry.name);
+    let mut relays = Vec::new();
+    if let Some(pipe) = child.stdout.take() {
+        let prefix = prefix.clone();
+        relays.push(thread::spawn(move || {
+            relay(pipe, io::stdout(), &prefix, None)
+        }));
+    }
+    if let Some(pipe) = child.stderr.take() {
+        relays.push(thread::spawn(move || {
+            relay(pipe, io::stderr(), &prefix, None)
+        }));
+    }
+    Ok(Process {
+        entry,
+        child,
+        relays,
+        status: None,
+        depth,
+    })
+}
+
+/// The name and status of a process that has exited since the last poll
+#[cfg(unix)]
+fn poll(processes: &mut [Process]) -> Result<Option<(String, ExitStatus)>, Error> {
+    for process in processes {
+        if process.status.is_none() {
+            if let Some(status) = process.child.try_wait()? {
+                process.status = Some(status);
+                return Ok(Some((process.entry.name.clone(), status)));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Stop the processes still running, those others depend on last, with their stop signals
+/// and after `timeout` with SIGKILL
+#[cfg(unix)]
+fn shut_down(processes: &mut [Process], timeout: Duration) {
+    let max_depth = processes.iter().map(|process| process.depth).max();
+    for depth in (0..=max_depth.unwrap_or_default()).rev() {
+        let in_wave = |process: &Process| process.depth == depth;
+        signal_groups(processes, in_wave, None);
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline
+            && poll(processes).is_ok_and(|_| {
+                processes
+                    .iter()
+                    .any(|process| in_wave(process) && process.status.is_none())
+            })
+        {
+            thread::sleep(POLL_INTERVAL);
+        }
+        signal_groups(processes, in_wave, Some(libc::SIGKILL));
+        for process in processes.iter_mut().filter(|process| in_wave(process)) {
+            if process.status.is_none() {
+                process.status = process.child.wait().ok();
+            }
+        }
+    }
+    for process in processes {
+        for relay in process.relays.drain(..) {
+            let _ = relay.join();
+        }
+    }
+}
+
+/// Send `signal`, by default their stop signal, to the process groups selected by `filter`,
+/// which may outlive the processes that started them
+#[cfg(unix)]
+fn signal_groups(
+    processes: &[Process],
+    filter: impl Fn(&Process) -> bool,
+    signal: Option<libc::c_int>,
+) {
+    for process in processes.iter().filter(|process| filter(process)) {
+        if let Ok(pid) = libc::pid_t::try_from(process.child.id()) {
+            let signal = signal.unwrap_or(process.entry.stop_signal);
+            unsafe { libc::kill(-pid, signal) };
+        }
+    }
+}
+
+#[cfg(unix)]
+fn describe(status: ExitStatus) -> String {
+    match (status.code(), status.signal()) {
+        (Some(code), _) => format!("exited with status {code}"),
+        (None, Some(signal)) => format!("was killed by signal {signal}"),
+        (None, None) => "exited".to_owned(),
+    }
+}
+
+/// Refused by [`load_procfile`]
+#[cfg(not(unix))]
+pub(crate) fn up(_: &[(ProcEntry, Vec<(String, String)>)], _: Duration) -> Result<i32, Error> {
+    Err(Error::Usage("enw up is only supported on Unix".to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_procfile() {
+        let entries = parse_procfile(
+            "# services\nweb: !after worker !stop-signal SIGINT ./server --port 80\n\nworker:!env a.env  !env b.env ./worker\n",
+            "P",
+        )
+        .unwrap();
+        assert_eq!(
+            entries,
+            [
+                ProcEntry {
+                    name: "web".to_owned(),
+                    command: "./server --port 80".to_owned(),
+                    env_files: Vec::new(),
+                    after: vec!["worker".to_owned()],
+                    stop_signal: libc::SIGINT,
+                },
+                ProcEntry {
+                    name: "worker".to_owned(),
+                    command: "./worker".to_owned(),
+                    env_files: vec![PathBuf::from("a.env"), PathBuf::from("b.env")],
+                    after: Vec::new(),
+                    stop_signal: libc::SIGTERM,
+                },
+            ]
+        );
+        assert!(parse_procfile("web ./server", "P").is_err());
+        assert!(parse_procfile("web:", "P").is_err());
+        assert!(parse_procfile("web: !env .env", "P").is_err());
+        assert!(parse_procfile("a: x\na: y", "P").is_err());
+        assert!(parse_procfile("# nothing", "P").is_err());
+        assert!(parse_procfile("a: !after b x", "P").is_err());
+        assert!(parse_procfile("a: !after b x\nb: !after a y", "P").is_err());
+        assert!(parse_procfile("a: !stop-signal FOO x", "P").is_err());
+        assert!(parse_procfile("a: !ignore x y", "P").is_err());
+        assert!(parse_procfile("a: ! false", "P").is_ok());
+        let entries = parse_procfile("a: !after b,c x\nb: !after c y\nc: z", "P").unwrap();
+        assert_eq!(depths(&entries).unwrap(), [2, 1, 0]);
+        assert_eq!(parse_signal("15"), Some(libc::SIGTERM));
+    }
+}
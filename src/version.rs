@@ -0,0 +1,64 @@
+//! The report of `--version-json`, telling tooling which version of enw is installed and what
+//! it supports
+
+use std::env::consts;
+
+use serde_json::{json, Value};
+
+use crate::{
+    cli::subcommand_names,
+    output::{FORMAT_NAMES, SHELL_NAMES},
+    parse::DIALECT_NAMES,
+};
+
+/// The optional cargo features, with whether this binary was built with them
+const FEATURES: [(&str, bool); 3] = [
+    ("remote", cfg!(feature = "remote")),
+    ("sandbox", cfg!(feature = "sandbox")),
+    ("no-exec", cfg!(feature = "no-exec")),
+];
+
+pub(crate) fn version_report() -> Value {
+    let features: Vec<&str> = FEATURES
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| *name)
+        .collect();
+    json!({
+        "name": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "target": { "os": consts::OS, "arch": consts::ARCH },
+        "features": features,
+        "dialects": DIALECT_NAMES,
+        "formats": FORMAT_NAMES,
+        "shells": SHELL_NAMES,
+        "subcommands": subcommand_names(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_report() {
+        let report = version_report();
+        assert_eq!(report["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(
+            report["features"].as_array().unwrap().is_empty(),
+            !cfg!(any(
+                feature = "remote",
+                feature = "sandbox",
+                feature = "no-exec"
+            ))
+        );
+        assert!(report["dialects"]
+            .as_array()
+            .unwrap()
+            .contains(&json!("strict-dotenv")));
+        assert!(report["subcommands"]
+            .as_array()
+            .unwrap()
+            .contains(&json!("print")));
+    }
+}
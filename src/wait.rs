@@ -0,0 +1,207 @@
+//! Waiting for the services COMMAND depends on, with `--wait-for`
+//!
+//! Before COMMAND, or the processes of `enw up`, are started, each `tcp://HOST:PORT` has to
+//! accept a connection and each `http://HOST[:PORT]/PATH` has to answer a GET with a 2xx or 3xx
+//! status, within the `--wait-timeout`.
+
+use std::{
+    fmt,
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpStream, ToSocketAddrs},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{log::log, Error};
+
+pub(crate) const DEFAULT_WAIT_TIMEOUT: Duration = Duration::from_secs(30);
+const RETRY_INTERVAL: Duration = Duration::from_millis(250);
+/// How long a single attempt may take
+const ATTEMPT_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) enum WaitTarget {
+    Tcp {
+        host: String,
+        port: u16,
+    },
+    Http {
+        host: String,
+        port: u16,
+        path: String,
+    },
+}
+
+impl WaitTarget {
+    pub(crate) fn parse(url: &str) -> Result<Self, String> {
+        let (scheme, rest) = url
+            .split_once("://")
+            .ok_or_else(|| format!("expected tcp://HOST:PORT or http://HOST/PATH, got {url}"))?;
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, "/"),
+        };
+        let default_port = match scheme {
+            "tcp" => None,
+            "http" => Some(80),
+            _ => return Err(format!("{url}: only tcp:// and http:// are supported")),
+        };
+        let (host, port) = match authority.strip_prefix('[').and_then(|a| a.split_once(']')) {
+            Some((host, rest)) => (host, rest.strip_prefix(':')),
+            None => match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+        let port = match port {
+            Some(port) => port
+                .parse()
+                .map_err(|_| format!("{url}: invalid port {port}"))?,
+            None => default_port.ok_or_else(|| format!("{url}: expected a port"))?,
+        };
+        if host.is_empty() {
+            return Err(format!("{url}: expected a host"));
+        }
+        Ok(match scheme {
+            "tcp" => WaitTarget::Tcp {
+                host: host.to_owned(),
+                port,
+            },
+            _ => WaitTarget::Http {
+                host: host.to_owned(),
+                port,
+                path: path.to_owned(),
+            },
+        })
+    }
+
+    /// Check once whether the service responds
+    fn check(&self) -> io::Result<()> {
+        match self {
+            WaitTarget::Tcp { host, port } => connect(host, *port).map(drop),
+            WaitTarget::Http { host, port, path } => {
+                let mut stream = connect(host, *port)?;
+                stream.set_read_timeout(Some(ATTEMPT_TIMEOUT))?;
+                stream.set_write_timeout(Some(ATTEMPT_TIMEOUT))?;
+                write!(
+                    stream,
+                    "GET {path} HTTP/1.0\r\nHost: {host}\r\nConnection: close\r\n\r\n"
+                )?;
+                let mut status_line = String::new();
+                BufReader::new(stream).read_line(&mut status_line)?;
+                let status = status_line.split_whitespace().nth(1).unwrap_or_default();
+                if status.starts_with(['2', '3']) && status.len() == 3 {
+                    Ok(())
+                } else {
+                    Err(io::Error::other(format!(
+                        "answered {:?}",
+                        status_line.trim_end()
+                    )))
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for WaitTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bracketed = |host: &str| {
+            if host.contains(':') {
+                format!("[{host}]")
+            } else {
+                host.to_owned()
+            }
+        };
+        match self {
+            WaitTarget::Tcp { host, port } => write!(f, "tcp://{}:{port}", bracketed(host)),
+            WaitTarget::Http { host, port, path } => {
+                write!(f, "http://{}:{port}{path}", bracketed(host))
+            }
+        }
+    }
+}
+
+fn connect(host: &str, port: u16) -> io::Result<TcpStream> {
+    let mut last_err = io::Error::new(io::ErrorKind::NotFound, "no address found");
+    for addr in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, ATTEMPT_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = err,
+        }
+    }
+    Err(last_err)
+}
+
+/// Wait until all `targets` respond, failing after `timeout`
+pub(crate) fn wait_for(targets: &[WaitTarget], timeout: Duration) -> Result<(), Error> {
+    let deadline = Instant::now() + timeout;
+    for target in targets {
+        log!(Info, "waiting for {target}");
+        loop {
+            match target.check() {
+                Ok(()) => break,
+                Err(err) if Instant::now() >= deadline => {
+                    return Err(Error::Other(format!(
+                        "timed out after {}s waiting for {target}: {err}",
+                        timeout.as_secs_f64()
+                    )))
+                }
+                Err(err) => log!(Debug, "{target}: {err}"),
+            }
+            thread::sleep(RETRY_INTERVAL);
+        }
+        log!(Info, "{target} is up");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            WaitTarget::parse("tcp://localhost:5432"),
+            Ok(WaitTarget::Tcp {
+                host: "localhost".to_owned(),
+                port: 5432
+            })
+        );
+        assert_eq!(
+            WaitTarget::parse("http://[::1]/health")
+                .unwrap()
+                .to_string(),
+            "http://[::1]:80/health"
+        );
+        assert!(WaitTarget::parse("tcp://localhost").is_err());
+        assert!(WaitTarget::parse("https://example.com").is_err());
+        assert!(WaitTarget::parse("localhost:80").is_err());
+    }
+
+    #[test]
+    fn test_wait_for() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = String::new();
+            BufReader::new(&stream).read_line(&mut request).unwrap();
+            stream
+                .write_all(b"HTTP/1.0 204 No Content\r\n\r\n")
+                .unwrap();
+            request
+        });
+        let target = WaitTarget::parse(&format!("http://127.0.0.1:{port}/health")).unwrap();
+        wait_for(&[target], Duration::from_secs(5)).unwrap();
+        assert_eq!(server.join().unwrap(), "GET /health HTTP/1.0\r\n");
+
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = closed.local_addr().unwrap().port();
+        drop(closed);
+        let target = WaitTarget::parse(&format!("tcp://127.0.0.1:{port}")).unwrap();
+        assert!(wait_for(&[target], Duration::ZERO).is_err());
+    }
+}
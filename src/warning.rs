@@ -0,0 +1,178 @@
+//! The warnings enw reports while resolving the environment, in classes that can be selected
+//! with `--warn` and `--no-warn`
+
+use std::{fmt, path::PathBuf};
+
+use crate::{ignore::IGNORE_FILE_NAME, DEFAULT_ENV_FILE_NAME};
+
+/// The names of the classes of warnings, as accepted by `--warn` and `--no-warn`
+pub(crate) const WARNING_KINDS: [&str; 9] = [
+    "file-not-found",
+    "no-env-file",
+    "ignored-file",
+    "dup-key",
+    "dangerous-var",
+    "readable-secret",
+    "case-collision",
+    "not-saved",
+    "key-collision",
+];
+
+/// Something suspicious found while resolving the environment, that doesn't stop it
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Warning {
+    /// An env file given explicitly does not exist
+    FileNotFound { path: PathBuf },
+    /// A directory given explicitly has no `.env` file
+    NoEnvFile { dir: PathBuf },
+    /// The `.env` file of a directory given explicitly is ignored by `.enwignore`
+    IgnoredFile { path: PathBuf },
+    /// A variable is defined more than once, with `--on-duplicate warn`
+    DuplicateKey {
+        key: String,
+        /// The source that defined it before
+        previous: String,
+        source: String,
+    },
+    /// An implicitly loaded file sets a variable such as `LD_PRELOAD`
+    DangerousVar { key: String, source: String },
+    /// An env file defining a secret can be read by other users
+    ReadableSecret {
+        path: PathBuf,
+        /// The first variable of the file that looks like a secret
+        key: String,
+        /// The permission bits of the file
+        mode: u32,
+    },
+    /// Two variables differ only in case, such as `Path` and `PATH`
+    CaseCollision { key: String, other: String },
+    /// A variable with a name or value that can't be written to the file of `--save-env`
+    NotSaved { key: String, path: PathBuf },
+    /// A variable is defined with different values by several sources, with
+    /// `enw check --collisions`
+    KeyCollision {
+        key: String,
+        /// The sources defining it, in the order they are loaded
+        sources: Vec<String>,
+    },
+}
+
+impl Warning {
+    /// The class of the warning, one of [`WARNING_KINDS`]
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Warning::FileNotFound { .. } => "file-not-found",
+            Warning::NoEnvFile { .. } => "no-env-file",
+            Warning::IgnoredFile { .. } => "ignored-file",
+            Warning::DuplicateKey { .. } => "dup-key",
+            Warning::DangerousVar { .. } => "dangerous-var",
+            Warning::ReadableSecret { .. } => "readable-secret",
+            Warning::CaseCollision { .. } => "case-collision",
+            Warning::NotSaved { .. } => "not-saved",
+            Warning::KeyCollision { .. } => "key-collision",
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::FileNotFound { path } => {
+                write!(f, "{} does not exist", path.to_string_lossy())
+            }
+            Warning::NoEnvFile { dir } => {
+                write!(
+                    f,
+                    "no {DEFAULT_ENV_FILE_NAME} file found in {}",
+                    dir.to_string_lossy()
+                )
+            }
+            Warning::IgnoredFile { path } => {
+                write!(
+                    f,
+                    "{} is ignored by {IGNORE_FILE_NAME}",
+                    path.to_string_lossy()
+                )
+            }
+            Warning::DuplicateKey {
+                key,
+                previous,
+                source,
+            } if previous == source => write!(f, "{key} is defined more than once in {source}"),
+            Warning::DuplicateKey {
+                key,
+                previous,
+                source,
+            } => write!(f, "{key} is defined in {previous} and again in {source}"),
+            Warning::DangerousVar { key, source } => {
+                write!(f, "{source} sets {key}, which may be dangerous")
+            }
+            Warning::ReadableSecret { path, key, mode } => write!(
+                f,
+                "{} defines {key} but can be read by other users (mode {mode:o}), \
+                 restrict it with chmod 600 or use --no-perms-check",
+                path.to_string_lossy()
+            ),
+            Warning::CaseCollision { key, other } => {
+                write!(f, "{key} and {other} differ only in case")
+            }
+            Warning::NotSaved { key, path } => {
+                write!(f, "not saving {key} to {}", path.to_string_lossy())
+            }
+            Warning::KeyCollision { key, sources } => write!(
+                f,
+                "{key} is defined with different values in {}",
+                sources.join(", ")
+            ),
+        }
+    }
+}
+
+/// Which warnings are reported, from `--warn` and `--no-warn`
+#[derive(Clone, Debug, Default)]
+pub(crate) struct WarningFilter {
+    /// Only these classes are reported, unless empty
+    only: Vec<String>,
+    ignored: Vec<String>,
+}
+
+impl WarningFilter {
+    pub(crate) fn new(only: Vec<String>, ignored: Vec<String>) -> Self {
+        WarningFilter { only, ignored }
+    }
+
+    pub(crate) fn shows(&self, warning: &Warning) -> bool {
+        let kind = warning.kind();
+        (self.only.is_empty() || self.only.iter().any(|only| only == kind))
+            && !self.ignored.iter().any(|ignored| ignored == kind)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter() {
+        let duplicate = Warning::DuplicateKey {
+            key: "A".to_owned(),
+            previous: "a.env".to_owned(),
+            source: "b.env".to_owned(),
+        };
+        let missing = Warning::FileNotFound {
+            path: PathBuf::from("missing.env"),
+        };
+        assert_eq!(
+            duplicate.to_string(),
+            "A is defined in a.env and again in b.env"
+        );
+        assert!(WARNING_KINDS.contains(&duplicate.kind()));
+        let all = WarningFilter::default();
+        assert!(all.shows(&duplicate) && all.shows(&missing));
+        let no_dup = WarningFilter::new(Vec::new(), vec!["dup-key".to_owned()]);
+        assert!(!no_dup.shows(&duplicate) && no_dup.shows(&missing));
+        let only_dup = WarningFilter::new(vec!["dup-key".to_owned()], Vec::new());
+        assert!(only_dup.shows(&duplicate) && !only_dup.shows(&missing));
+    }
+}
@@ -14,6 +14,14 @@ type BoxError = Box<dyn std::error::Error>;
 
 #[test]
 fn test_cli() -> Result<(), BoxError> {
+    // Env files are only loaded automatically from allowed directories
+    let data_home = env::temp_dir().join(format!("enw-test-data-{}", std::process::id()));
+    env::set_var("XDG_DATA_HOME", &data_home);
+    for dir in ["tests", "tests/data/enwrc"] {
+        let status = Command::new("target/debug/enw").args(["allow", dir]).status()?;
+        assert!(status.success());
+    }
+
     in_directory(&env::current_dir()?.join("tests"), || {
         let args = vec![
             "-i",
@@ -63,7 +71,7 @@ fn test_cli() -> Result<(), BoxError> {
 
 
     in_directory(&env::current_dir()?.join("tests"), || {
-        let args = vec!["-f", "not_found.env"];
+        let args = vec!["--lenient", "-f", "not_found.env"];
         let actual = Command::new("../target/debug/enw").args(args).output()?;
         assert!(actual.status.success());
         let stderr = String::from_utf8_lossy(&actual.stderr);
@@ -104,6 +112,1378 @@ fn test_cli() -> Result<(), BoxError> {
         Ok(())
     })?;
 
+    in_directory(&env::current_dir()?.join("tests"), || {
+        let args = vec!["-i", "-n", "--sort", "-f", "./data/input_01.txt"].into_iter();
+        let actual = Command::new("../target/debug/enw").args(args).output()?;
+        assert!(actual.status.success());
+        let stdout = String::from_utf8_lossy(&actual.stdout);
+        assert_eq!(
+            stdout,
+            std::fs::read_to_string("data/sorted_output_for_input_01.txt")?
+        );
+        Ok(())
+    })?;
+
+    in_directory(&env::current_dir()?.join("tests"), || {
+        let args = vec!["-i", "-n", "--export", "a=it\\'s", "b=c d"];
+        let actual = Command::new("../target/debug/enw").args(args).output()?;
+        assert!(actual.status.success());
+        let stdout = String::from_utf8_lossy(&actual.stdout);
+        assert_eq!(stdout, "export a='it'\\''s'\nexport b='c d'\n");
+        Ok(())
+    })?;
+
+    in_directory(&env::current_dir()?.join("tests"), || {
+        let args = vec!["-i", "-n", "-0", "b=c d", "a=x\\'y"];
+        let actual = Command::new("../target/debug/enw").args(args).output()?;
+        assert!(actual.status.success());
+        let stdout = String::from_utf8_lossy(&actual.stdout);
+        assert_eq!(stdout, "b=c d\0a=x'y\0");
+        Ok(())
+    })?;
+
+    in_directory(&env::current_dir()?.join("tests"), || {
+        let args = vec!["-i", "-n", "--kv-sep", "\\t", "--quote-values", "never", "b=c d", "a=1"];
+        let actual = Command::new("../target/debug/enw").args(args).output()?;
+        assert!(actual.status.success());
+        let stdout = String::from_utf8_lossy(&actual.stdout);
+        assert_eq!(stdout, "b\tc d\na\t1\n");
+
+        let args = vec!["-i", "-n", "-0", "--kv-sep", ": ", "b=c d"];
+        let actual = Command::new("../target/debug/enw").args(args).output()?;
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "b: c d\0");
+
+        let args = vec!["-i", "-n", "--entry-sep", ",", "--quote-values", "always", "a=1", "b=2"];
+        let actual = Command::new("../target/debug/enw").args(args).output()?;
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "a=\"1\",b=\"2\",");
+        Ok(())
+    })?;
+
+    in_directory(&env::current_dir()?.join("tests"), || {
+        let args = vec!["-i", "-n", "-f", "./data/include"];
+        let actual = Command::new("../target/debug/enw").args(args).output()?;
+        assert!(actual.status.success());
+        let stdout = String::from_utf8_lossy(&actual.stdout);
+        assert_eq!(stdout, "BASE=base\nOVERRIDDEN=top\nMIDDLE=middle\n");
+
+        let args = vec!["-i", "-n", "-f", "./data/include_cycle/a.env"];
+        let actual = Command::new("../target/debug/enw").args(args).output()?;
+        assert!(!actual.status.success());
+        let stderr = String::from_utf8_lossy(&actual.stderr);
+        assert!(stderr.contains("include cycle detected"), "{}", stderr);
+
+        let args = vec!["-i", "-n", "--on-duplicate", "error", "-f", "./data/include"];
+        let actual = Command::new("../target/debug/enw").args(args).output()?;
+        assert_eq!(actual.status.code(), Some(125));
+        let stderr = String::from_utf8_lossy(&actual.stderr);
+        assert!(stderr.contains("BASE is defined more than once"), "{}", stderr);
+
+        let args = vec!["-vv", "-i", "-n", "-f", "./data/include", "MIDDLE=cli"];
+        let actual = Command::new("../target/debug/enw").args(args).output()?;
+        assert!(actual.status.success());
+        let stderr = String::from_utf8_lossy(&actual.stderr);
+        assert!(stderr.contains("info: ./data/include/.env: including"), "{}", stderr);
+        assert!(
+            stderr.contains("debug: MIDDLE: from command line, overriding ./data/include/.env"),
+            "{}",
+            stderr
+        );
+        Ok(())
+    })?;
+
+    in_directory(&env::current_dir()?.join("tests"), || {
+        let args = vec!["--only", "APP_*", "--exclude", "*_SECRET", "APP_PORT=1", "env"];
+        let actual = Command::new("../target/debug/enw")
+            .env("APP_SECRET", "hunter2")
+            .env("APP_HOST", "localhost")
+            .args(args)
+            .output()?;
+        assert!(actual.status.success());
+        let stdout = String::from_utf8_lossy(&actual.stdout);
+        assert_eq!(stdout, "APP_HOST=localhost\nAPP_PORT=1\n");
+        Ok(())
+    })?;
+
+    in_directory(&env::current_dir()?.join("tests"), || {
+        let run = |args: &[&str]| {
+            Command::new("../target/debug/enw")
+                .env_clear()
+                .env("PORT", "80")
+                .args(args)
+                .output()
+        };
+        let actual = run(&["-i", "-n", "print", "a=b"])?;
+        assert!(actual.status.success());
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "a=b\n");
+
+        let actual = run(&["-n", "print", "a=b", "env"])?;
+        assert!(!actual.status.success());
+
+        let actual = run(&["-i", "-n", "exec", "a=b", "env"])?;
+        assert!(actual.status.success());
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "a=b\n");
+
+        let actual = run(&["-n", "diff", "PORT=8080", "a=b"])?;
+        assert!(actual.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&actual.stdout),
+            "-PORT=80\n+PORT=8080\n+a=b\n"
+        );
+
+        let actual = run(&["-n", "check", "--no-perms-check", "-f", "./data/input_01.txt"])?;
+        assert!(actual.status.success());
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "");
+        let actual = run(&["-n", "check", "-f", "not_found.env"])?;
+        assert!(!actual.status.success());
+        let actual = run(&["-n", "-f", "not_found.env", "true"])?;
+        assert_eq!(actual.status.code(), Some(125));
+        assert!(String::from_utf8_lossy(&actual.stderr).contains("use --optional-file or --lenient"));
+        Ok(())
+    })?;
+
+    in_directory(&env::current_dir()?.join("tests"), || {
+        for shell in ["bash", "zsh", "fish", "powershell"] {
+            let actual = Command::new("../target/debug/enw")
+                .args(["completions", shell])
+                .output()?;
+            assert!(actual.status.success(), "{}", shell);
+            let stdout = String::from_utf8_lossy(&actual.stdout);
+            assert!(stdout.contains("no-env-file"), "{}", shell);
+        }
+        Ok(())
+    })?;
+
+    in_directory(&env::current_dir()?.join("tests"), || {
+        let path = env::temp_dir().join(format!("enw-lint-{}.env", std::process::id()));
+        std::fs::write(&path, "b=1 \nA=x y\n")?;
+        let actual = Command::new("../target/debug/enw")
+            .arg("lint")
+            .arg(&path)
+            .output()?;
+        assert!(!actual.status.success());
+        assert_eq!(String::from_utf8_lossy(&actual.stdout).lines().count(), 4);
+
+        let actual = Command::new("../target/debug/enw")
+            .args(["lint", "--fix"])
+            .arg(&path)
+            .output()?;
+        assert!(actual.status.success());
+        assert_eq!(std::fs::read_to_string(&path)?, "A='x y'\nB=1\n");
+        std::fs::remove_file(&path)?;
+        Ok(())
+    })?;
+
+    in_directory(&env::current_dir()?.join("tests"), || {
+        let path = env::temp_dir().join(format!("enw-set-{}.env", std::process::id()));
+        std::fs::write(&path, "# db\nHOST=\"db\" # primary\nPORT=1\n")?;
+        let actual = Command::new("../target/debug/enw")
+            .args(["set", "-f"])
+            .arg(&path)
+            .args(["HOST=db.internal", "USER=it\\'s me"])
+            .output()?;
+        assert!(actual.status.success());
+        assert_eq!(
+            std::fs::read_to_string(&path)?,
+            "# db\nHOST=\"db.internal\" # primary\nPORT=1\nUSER='it\\'s me'\n"
+        );
+        std::fs::remove_file(&path)?;
+        Ok(())
+    })?;
+
+    in_directory(&env::current_dir()?.join("tests"), || {
+        let actual = Command::new("../target/debug/enw")
+            .env("PORT", "80")
+            .args(["-n", "get", "PORT"])
+            .output()?;
+        assert!(actual.status.success());
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "80\n");
+
+        let actual = Command::new("../target/debug/enw")
+            .args(["-n", "-f", "./data/include", "get", "MIDDLE"])
+            .output()?;
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "middle\n");
+
+        let actual = Command::new("../target/debug/enw")
+            .env("PORT", "80")
+            .args(["-n", "-i", "get", "PORT"])
+            .output()?;
+        assert_eq!(actual.status.code(), Some(1));
+        assert_eq!(String::from_utf8_lossy(&actual.stderr), "enw: PORT is not set\n");
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "");
+        Ok(())
+    })?;
+
+    in_directory(&env::current_dir()?.join("tests/data/ignored"), || {
+        let actual = Command::new("../../../target/debug/enw").arg("-i").output()?;
+        assert!(actual.status.success());
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "");
+
+        let actual = Command::new("../../../target/debug/enw")
+            .args(["-i", "-f", "."])
+            .output()?;
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "");
+        assert_eq!(
+            String::from_utf8_lossy(&actual.stderr),
+            "warning: ./.env is ignored by .enwignore\n"
+        );
+
+        let actual = Command::new("../../../target/debug/enw")
+            .args(["-i", "-f", ".env"])
+            .output()?;
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "IGNORED=yes\n");
+        Ok(())
+    })?;
+
+    in_directory(&env::current_dir()?.join("tests"), || {
+        let actual = Command::new("../target/debug/enw")
+            .env("KEEP_ME", "kept")
+            .env("KEEP_OVERRIDDEN", "inherited")
+            .env("DROP_ME", "dropped")
+            .args(["-n", "--pristine", "--keep", "KEEP_*", "KEEP_OVERRIDDEN=cli"])
+            .arg("env")
+            .output()?;
+        assert!(actual.status.success(), "{:?}", actual);
+        assert_eq!(
+            String::from_utf8_lossy(&actual.stdout),
+            "KEEP_ME=kept\nKEEP_OVERRIDDEN=cli\n"
+        );
+        Ok(())
+    })?;
+
+    in_directory(&env::current_dir()?.join("tests"), || {
+        let actual = Command::new("../target/debug/enw")
+            .args(["-n", "--umask", "027", "--rlimit", "nofile=64:128", "--nice", "3"])
+            .args(["/bin/sh", "-c", "umask; ulimit -Sn; ulimit -Hn"])
+            .output()?;
+        assert!(actual.status.success(), "{:?}", actual);
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "0027\n64\n128\n");
+
+        let actual = Command::new("../target/debug/enw")
+            .args(["-n", "--rlimit", "bogus=1", "true"])
+            .output()?;
+        assert_eq!(actual.status.code(), Some(125));
+
+        // Switching users without -i would leak the inherited environment
+        let actual = Command::new("../target/debug/enw")
+            .args(["-n", "--user", "root", "true"])
+            .output()?;
+        assert_eq!(actual.status.code(), Some(125));
+        let stderr = String::from_utf8_lossy(&actual.stderr);
+        assert!(stderr.contains("--preserve-env"), "{}", stderr);
+        Ok(())
+    })?;
+
+    {
+        let enw = env::current_dir()?.join("target/debug/enw");
+        let dir = env::temp_dir().join(format!("enw-untrusted-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join(".env"), "INJECTED=1\n")?;
+        let run = |args: &[&str]| Command::new(&enw).current_dir(&dir).args(args).output();
+        let actual = run(&["-i", "env"])?;
+        assert_eq!(actual.status.code(), Some(125));
+        let stderr = String::from_utf8_lossy(&actual.stderr);
+        assert!(stderr.contains("enw allow"), "{}", stderr);
+        assert_eq!(run(&["-i", "-n", "env"])?.stdout, b"");
+
+        assert!(run(&["allow"])?.status.success());
+        assert_eq!(run(&["-i", "env"])?.stdout, b"INJECTED=1\n");
+        std::fs::write(dir.join(".env"), "INJECTED=2\n")?;
+        let actual = run(&["-i", "env"])?;
+        assert_eq!(actual.status.code(), Some(125));
+        let stderr = String::from_utf8_lossy(&actual.stderr);
+        assert!(stderr.contains("has changed"), "{}", stderr);
+        assert!(run(&["allow"])?.status.success());
+        assert_eq!(run(&["-i", "env"])?.stdout, b"INJECTED=2\n");
+        assert!(run(&["deny"])?.status.success());
+        assert!(!run(&["-i", "env"])?.status.success());
+
+        std::fs::write(dir.join(".env"), "LD_PRELOAD=/tmp/evil.so\n")?;
+        assert!(run(&["allow"])?.status.success());
+        let actual = run(&["-i", "env"])?;
+        assert_eq!(actual.status.code(), Some(125));
+        let stderr = String::from_utf8_lossy(&actual.stderr);
+        assert!(stderr.contains("--allow-dangerous"), "{}", stderr);
+        // Safety overrides are only taken from the command line
+        let actual = Command::new(&enw)
+            .current_dir(&dir)
+            .env("ENW_ALLOW_DANGEROUS", "1")
+            .args(["-i", "env"])
+            .output()?;
+        assert_eq!(actual.status.code(), Some(125));
+        let actual = run(&["-i", "--allow-dangerous", "env"])?;
+        assert_eq!(actual.stdout, b"LD_PRELOAD=/tmp/evil.so\n");
+        let actual = run(&["-i", "-f", ".env", "-n", "env"])?;
+        assert_eq!(actual.stdout, b"LD_PRELOAD=/tmp/evil.so\n");
+        std::fs::remove_dir_all(&dir)?;
+    }
+
+    {
+        let enw = env::current_dir()?.join("target/debug/enw");
+        let run = |args: &[&str]| Command::new(&enw).args(args).output();
+        let actual = run(&["-i", "--max-value-size", "3", "BIG=1234", "env"])?;
+        assert_eq!(actual.status.code(), Some(125));
+        let stderr = String::from_utf8_lossy(&actual.stderr);
+        assert!(stderr.contains("BIG is 4 bytes"), "{}", stderr);
+        let actual = run(&["-i", "--max-value-size", "3", "--truncate", "BIG", "BIG=1234", "env"])?;
+        assert_eq!(actual.stdout, b"BIG=123\n");
+        let actual = run(&["-i", "--max-vars", "1", "A=1", "B=2", "env"])?;
+        assert_eq!(actual.status.code(), Some(125));
+        // Too large to be passed to enw itself, so it comes from a file
+        let huge = env::temp_dir().join(format!("enw-huge-{}.env", std::process::id()));
+        std::fs::write(&huge, format!("HUGE={}\n", "x".repeat(200_000)))?;
+        let actual = run(&["-i", "-n", "-f", huge.to_str().unwrap(), "env"])?;
+        std::fs::remove_file(&huge)?;
+        assert_eq!(actual.status.code(), Some(125));
+        let stderr = String::from_utf8_lossy(&actual.stderr);
+        assert!(stderr.contains("HUGE is 200000 bytes"), "{}", stderr);
+    }
+
+    {
+        let actual = Command::new("target/debug/enw")
+            .args(["-i", "-n", "--from-json", "tests/data/config.json"])
+            .args(["--json-separator", "__", "DEBUG=false", "env"])
+            .output()?;
+        let expected = "NAME=app\nDB__HOST=localhost\nDB__PORT=5432\nDB__REPLICAS__0=replica-1\n\
+                        DB__REPLICAS__1=replica-2\nDEBUG=false\nEMPTY=\n";
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), expected);
+    }
+
+    in_directory(&env::current_dir()?.join("tests"), || {
+        let actual = Command::new("../target/debug/enw")
+            .env("XYZZY", "123")
+            .args(["export", "sh", "--diff"])
+            .output()?;
+        let stdout = String::from_utf8_lossy(&actual.stdout);
+        assert!(stdout.contains("export MY_URL='localhost'\n"), "{}", stdout);
+        assert!(!stdout.contains("export XYZZY="), "{}", stdout);
+
+        let hook = Command::new("../target/debug/enw").args(["hook", "bash"]).output()?;
+        let script = format!(
+            "{}_enw_hook\necho \"$XYZZY $MY_URL\"\ncd ..\n_enw_hook\necho \"${{XYZZY-unset}} $MY_URL\"",
+            String::from_utf8_lossy(&hook.stdout)
+        );
+        let actual = Command::new("bash")
+            .args(["-c", &script])
+            .env_remove("XYZZY")
+            .env("MY_URL", "before")
+            .output()?;
+        assert_eq!(
+            String::from_utf8_lossy(&actual.stdout),
+            "123 localhost\nunset before\n"
+        );
+        Ok(())
+    })?;
+
+    {
+        let schema = ["-i", "-n", "--schema", "tests/data/schema.json", "PORT=80", "API_KEY=x"];
+        let actual = Command::new("target/debug/enw")
+            .args(schema)
+            .args(["DEBUG=maybe", "LOG_LEVEL=trace", "env"])
+            .output()?;
+        assert_eq!(actual.status.code(), Some(125));
+        assert_eq!(
+            String::from_utf8_lossy(&actual.stderr),
+            "enw: the environment does not match tests/data/schema.json:\n  \
+             DEBUG: expected a boolean, got \"maybe\"\n  \
+             LOG_LEVEL: expected one of debug, info, warn, got \"trace\"\n"
+        );
+        let actual = Command::new("target/debug/enw")
+            .args(schema)
+            .args(["DEBUG=off", "env"])
+            .output()?;
+        assert!(actual.status.success());
+    }
+
+    {
+        let enw = env::current_dir()?.join("target/debug/enw");
+        let cache_home = env::temp_dir().join(format!("enw-cache-{}", std::process::id()));
+        let file = env::temp_dir().join(format!("enw-cached-{}.env", std::process::id()));
+        std::fs::write(&file, "CACHED=1\n")?;
+        // Files modified just now are not cached
+        let old = std::time::SystemTime::now() - std::time::Duration::from_secs(60);
+        std::fs::File::options().write(true).open(&file)?.set_modified(old)?;
+        let run = |args: &[&str]| {
+            Command::new(&enw)
+                .env("XDG_CACHE_HOME", &cache_home)
+                .args(["-i", "-n", "-vv", "--cache", "-f"])
+                .arg(&file)
+                .args(args)
+                .output()
+        };
+        let cached = |actual: &std::process::Output| {
+            String::from_utf8_lossy(&actual.stderr).contains("loaded from cache")
+        };
+        let actual = run(&["env"])?;
+        assert_eq!(actual.stdout, b"CACHED=1\n");
+        assert!(!cached(&actual));
+        let actual = run(&["env"])?;
+        assert_eq!(actual.stdout, b"CACHED=1\n");
+        assert!(cached(&actual));
+
+        std::fs::write(&file, "CACHED=22\n")?;
+        std::fs::File::options().write(true).open(&file)?.set_modified(old)?;
+        let actual = run(&["env"])?;
+        assert_eq!(actual.stdout, b"CACHED=22\n");
+        assert!(!cached(&actual));
+
+        let status = Command::new(&enw)
+            .env("XDG_CACHE_HOME", &cache_home)
+            .args(["cache", "clear"])
+            .status()?;
+        assert!(status.success());
+        assert!(!cache_home.join("enw").exists());
+        std::fs::remove_file(&file)?;
+        std::fs::remove_dir_all(&cache_home)?;
+    }
+
+    {
+        // A stand-in for the 1Password CLI, printing the reference it's given reversed
+        let bin = env::temp_dir().join(format!("enw-op-{}", std::process::id()));
+        std::fs::create_dir_all(&bin)?;
+        let op = bin.join("op");
+        std::fs::write(
+            &op,
+            "#!/bin/sh\n[ \"$3\" = op://vault/missing ] && { echo 'not found' >&2; exit 1; }\n\
+             printf %s \"$3\" | rev\n",
+        )?;
+        std::fs::set_permissions(&op, std::os::unix::fs::PermissionsExt::from_mode(0o755))?;
+        let path = format!("{}:/usr/bin:/bin", bin.to_string_lossy());
+        let run = |args: &[&str]| {
+            Command::new("target/debug/enw")
+                .env("PATH", &path)
+                .args(["-i", "-n", "--resolve-op"])
+                .args(args)
+                .output()
+        };
+        let actual = run(&["DB_PASSWORD=op://vault/db/password", "A=op", "env"])?;
+        assert_eq!(
+            String::from_utf8_lossy(&actual.stdout),
+            "DB_PASSWORD=drowssap/bd/tluav//:po\nA=op\n"
+        );
+        let actual = run(&["KEY=op://vault/missing", "env"])?;
+        assert_eq!(actual.status.code(), Some(125));
+        let stderr = String::from_utf8_lossy(&actual.stderr);
+        assert!(stderr.contains("KEY: op read op://vault/missing failed: not found"), "{}", stderr);
+        std::fs::remove_dir_all(&bin)?;
+    }
+
+    in_directory(&env::current_dir()?.join("tests"), || {
+        let actual = Command::new("../target/debug/enw")
+            .args(["-i", "-n", "-f", "data/secret.env.gpg", "--cache"])
+            .output()?;
+        assert!(actual.status.success(), "{:?}", actual);
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "SECRET=\"from gpg\"\n");
+        Ok(())
+    })?;
+
+    {
+        let path = env::temp_dir().join(format!("enw-retry-{}.env", std::process::id()));
+        std::fs::write(&path, "N=1\n")?;
+        let script = format!("echo $N; echo N=2 > '{}'; exit 3", path.display());
+        let actual = Command::new("target/debug/enw")
+            .args(["-i", "-n", "--retries", "2", "--retry-delay", "10ms"])
+            .arg("--reload-on-retry")
+            .arg("-f")
+            .arg(&path)
+            .args(["sh", "-c", &script])
+            .output()?;
+        std::fs::remove_file(&path)?;
+        assert_eq!(actual.status.code(), Some(3));
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "1\n2\n2\n");
+        assert!(String::from_utf8_lossy(&actual.stderr).contains("retrying in 20ms (2 of 2)"));
+    }
+
+    {
+        let log = env::temp_dir().join(format!("enw-log-{}.log", std::process::id()));
+        let actual = Command::new("target/debug/enw")
+            .args(["-i", "-n", "--log-prefix", "[api] ", "--log-file"])
+            .arg(&log)
+            .args(["sh", "-c", "echo out; echo err >&2"])
+            .output()?;
+        assert!(actual.status.success());
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "[api] out\n");
+        assert_eq!(String::from_utf8_lossy(&actual.stderr), "[api] err\n");
+        let logged = std::fs::read_to_string(&log)?;
+        std::fs::remove_file(&log)?;
+        assert_eq!(logged.lines().count(), 2);
+        assert!(logged
+            .lines()
+            .all(|line| line.ends_with("Z [api] out") || line.ends_with("Z [api] err")));
+    }
+
+    {
+        let procfile = env::temp_dir().join(format!("enw-Procfile-{}", std::process::id()));
+        let worker_env = env::temp_dir().join(format!("enw-worker-{}.env", std::process::id()));
+        std::fs::write(&worker_env, "GREETING=hi\n")?;
+        std::fs::write(
+            &procfile,
+            format!(
+                "web: echo $GREETING; sleep 1; exit 3\nworker: !env {} echo $GREETING; sleep 10\n",
+                worker_env.display()
+            ),
+        )?;
+        let started = std::time::Instant::now();
+        let actual = Command::new("target/debug/enw")
+            .args(["-i", "-n", "up", "--procfile"])
+            .arg(&procfile)
+            .arg("GREETING=hello")
+            .output()?;
+        std::fs::remove_file(&procfile)?;
+        std::fs::remove_file(&worker_env)?;
+        assert_eq!(actual.status.code(), Some(3));
+        let stdout = String::from_utf8_lossy(&actual.stdout);
+        let mut lines: Vec<_> = stdout.lines().collect();
+        lines.sort();
+        assert_eq!(lines, ["web    | hello", "worker | hi"]);
+        assert!(String::from_utf8_lossy(&actual.stderr).contains("web exited with status 3"));
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("tcp://{}", listener.local_addr()?);
+        let actual = Command::new("target/debug/enw")
+            .args(["-i", "-n", "--wait-for", &url, "echo", "up"])
+            .output()?;
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "up\n");
+        drop(listener);
+        let actual = Command::new("target/debug/enw")
+            .args(["-i", "-n", "--wait-for", &url, "--wait-timeout", "0", "true"])
+            .output()?;
+        assert_eq!(actual.status.code(), Some(125));
+        assert!(String::from_utf8_lossy(&actual.stderr).contains("timed out after 0s waiting"));
+    }
+
+    {
+        let procfile = env::temp_dir().join(format!("enw-Procfile-stop-{}", std::process::id()));
+        std::fs::write(
+            &procfile,
+            "db: !stop-signal INT trap 'echo stopped; exit' INT; while :; do sleep 0.1; done\n\
+             web: !after db trap 'echo stopped; exit' TERM; while :; do sleep 0.1; done\n\
+             once: sleep 1\n",
+        )?;
+        let actual = Command::new("target/debug/enw")
+            .args(["-i", "-n", "up", "--shutdown-timeout", "3s", "--procfile"])
+            .arg(&procfile)
+            .output()?;
+        std::fs::remove_file(&procfile)?;
+        assert!(actual.status.success(), "{:?}", actual);
+        assert_eq!(
+            String::from_utf8_lossy(&actual.stdout),
+            "web  | stopped\ndb   | stopped\n"
+        );
+    }
+
+    {
+        let args = vec!["print", "-i", "-n", "--normalize-keys", "upper", "db_url=x", "Home=y"];
+        let actual = Command::new("target/debug/enw").args(args).output()?;
+        assert!(actual.status.success());
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "DB_URL=x\nHOME=y\n");
+
+        let actual = Command::new("target/debug/enw")
+            .args(["-n", "Path=/bin", "true"])
+            .output()?;
+        let stderr = String::from_utf8_lossy(&actual.stderr);
+        assert!(stderr.contains("Path and PATH differ only in case"), "{}", stderr);
+    }
+
+    {
+        let path = env::temp_dir().join(format!("enw-merge-{}.env", std::process::id()));
+        std::fs::write(&path, "LIST+=b\nLIST^=a\n")?;
+        let actual = Command::new("target/debug/enw")
+            .args(["print", "-n", "--only", "LIST", "--append", "LIST=c", "-f"])
+            .arg(&path)
+            .env("LIST", "x")
+            .output()?;
+        std::fs::remove_file(&path)?;
+        assert!(actual.status.success());
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "LIST=\"a:x:b:c\"\n");
+    }
+
+    {
+        let print = |args: &[&str]| -> Result<String, BoxError> {
+            let actual = Command::new("target/debug/enw")
+                .args(["print", "-i", "-n", "-f", "tests/data/conditional.env"])
+                .args(args)
+                .env("CI", "true")
+                .output()?;
+            assert!(actual.status.success());
+            Ok(String::from_utf8_lossy(&actual.stdout).into_owned())
+        };
+        assert_eq!(
+            print(&["--profile", "prod"])?,
+            "NAME=app\nOPEN=xdg-open\nLOG_LEVEL=warn\nINTERACTIVE=yes\n"
+        );
+        assert_eq!(
+            print(&["--keep", "CI"])?,
+            "CI=true\nNAME=app\nOPEN=xdg-open\nLOG_LEVEL=info\n"
+        );
+    }
+
+    {
+        let actual = Command::new("target/debug/enw")
+            .arg("--version-json")
+            .output()?;
+        assert!(actual.status.success());
+        let report: serde_json::Value = serde_json::from_slice(&actual.stdout)?;
+        assert_eq!(report["version"], env!("CARGO_PKG_VERSION"));
+        assert!(report["formats"]
+            .as_array()
+            .is_some_and(|formats| formats.contains(&"k8s-secret".into())));
+    }
+
+    {
+        let stderr = |envs: &[(&str, &str)]| -> Result<String, BoxError> {
+            let actual = Command::new("target/debug/enw")
+                .args(["check", "-f", "tests/data/not_found"])
+                .envs(envs.iter().copied())
+                .output()?;
+            Ok(String::from_utf8_lossy(&actual.stderr).into_owned())
+        };
+        assert!(stderr(&[])?.starts_with("warning: "));
+        assert!(stderr(&[("CLICOLOR_FORCE", "1")])?.starts_with("\x1b[1;33mwarning\x1b[0m: "));
+        assert!(stderr(&[("CLICOLOR_FORCE", "1"), ("NO_COLOR", "1")])?.starts_with("warning: "));
+    }
+
+    {
+        let actual = Command::new("target/debug/enw")
+            .args(["print", "-i", "--timings=json", "-f", "tests/data/types.env"])
+            .output()?;
+        assert!(actual.status.success());
+        let report: serde_json::Value = serde_json::from_slice(&actual.stderr)?;
+        let steps: Vec<_> = report["timings"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|timing| (timing["step"].as_str(), timing["name"].as_str()))
+            .collect();
+        assert!(steps.contains(&(Some("parse"), Some("tests/data/types.env"))));
+        assert_eq!(steps.last(), Some(&(Some("resolve"), Some("environment"))));
+    }
+
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = env::temp_dir().join(format!("enw-perms-{}.env", std::process::id()));
+        std::fs::write(&path, "API_TOKEN=secret\n")?;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644))?;
+        let check = |args: &[&str]| {
+            Command::new("target/debug/enw")
+                .args(["check", "-n", "-f"])
+                .arg(&path)
+                .args(args)
+                .output()
+        };
+        let readable = check(&[])?;
+        let strict = check(&["--strict-perms"])?;
+        let skipped = check(&["--no-perms-check"])?;
+        // Neither the check is skipped nor the secrets saved for inherited variables
+        let saved = env::temp_dir().join(format!("enw-perms-saved-{}.env", std::process::id()));
+        let inherited = Command::new("target/debug/enw")
+            .env("ENW_NO_PERMS_CHECK", "1")
+            .env("ENW_SAVE_ENV", &saved)
+            .args(["check", "-n", "-f"])
+            .arg(&path)
+            .output()?;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+        let private = check(&["--strict-perms"])?;
+        std::fs::remove_file(&path)?;
+        assert!(!readable.status.success());
+        assert!(String::from_utf8_lossy(&readable.stderr)
+            .contains("defines API_TOKEN but can be read by other users (mode 644)"));
+        assert!(!strict.status.success());
+        assert!(skipped.status.success());
+        assert!(private.status.success());
+        assert!(!inherited.status.success());
+        assert!(String::from_utf8_lossy(&inherited.stderr).contains("defines API_TOKEN"));
+        assert!(!saved.exists());
+    }
+
+    {
+        let config_home = env::temp_dir().join(format!("enw-xdg-{}", std::process::id()));
+        std::fs::create_dir_all(config_home.join("enw"))?;
+        std::fs::write(
+            config_home.join("enw/global.env"),
+            "EDITOR=vi\nPORT=1\n",
+        )?;
+        let print = |args: &[&str]| {
+            Command::new("target/debug/enw")
+                .args(["print", "-i", "-n", "-f", "tests/data/types.env", "--only", "EDITOR", "--only", "PORT"])
+                .args(args)
+                .env("XDG_CONFIG_HOME", &config_home)
+                .output()
+        };
+        let global = print(&[])?;
+        let no_global = print(&["--no-global"])?;
+        std::fs::remove_dir_all(&config_home)?;
+        assert_eq!(String::from_utf8_lossy(&global.stdout), "EDITOR=vi\nPORT=80\n");
+        assert_eq!(String::from_utf8_lossy(&no_global.stdout), "PORT=80\n");
+    }
+
+    {
+        let enw = env::current_dir()?.join("target/debug/enw");
+        let dir = env::temp_dir().join(format!("enw-local-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join(".env"), "A=1\nB=1\n")?;
+        std::fs::write(dir.join(".env.local"), "B=2\n")?;
+        let run = |args: &[&str]| Command::new(&enw).current_dir(&dir).args(args).output();
+        assert!(run(&["allow"])?.status.success());
+        let local = run(&["print", "-i"])?;
+        let no_local = run(&["print", "-i", "--no-local"])?;
+        std::fs::write(dir.join(".env.local"), "B=3\n")?;
+        let modified = run(&["print", "-i"])?;
+        std::fs::remove_dir_all(&dir)?;
+        assert_eq!(String::from_utf8_lossy(&local.stdout), "A=1\nB=2\n");
+        assert_eq!(String::from_utf8_lossy(&no_local.stdout), "A=1\nB=1\n");
+        assert!(String::from_utf8_lossy(&modified.stderr).contains("has changed"));
+    }
+
+    {
+        let run = |args: &[&str]| {
+            Command::new("target/debug/enw")
+                .args(["print", "-i", "-n", "--on-duplicate", "warn", "-f", "tests/data/include"])
+                .args(["--lenient", "-f", "tests/data/not_found.env"])
+                .args(args)
+                .output()
+        };
+        let all = run(&[])?;
+        let only_dup = run(&["--warn", "dup-key"])?;
+        let no_dup = run(&["--no-warn", "dup-key", "--no-warn", "file-not-found"])?;
+        let as_errors = run(&["--warnings-as-errors", "--no-warn", "dup-key"])?;
+        let stderr = |output: &std::process::Output| String::from_utf8_lossy(&output.stderr).into_owned();
+        assert!(all.status.success());
+        assert!(stderr(&all).contains("BASE is defined more than once"));
+        assert!(stderr(&all).contains("not_found.env does not exist"));
+        assert!(stderr(&only_dup).contains("BASE is defined more than once"));
+        assert!(!stderr(&only_dup).contains("does not exist"));
+        assert_eq!(stderr(&no_dup), "");
+        assert_eq!(as_errors.status.code(), Some(125));
+        assert!(stderr(&as_errors).contains("1 warning treated as an error"), "{}", stderr(&as_errors));
+        assert!(!stderr(&as_errors).contains("BASE"));
+    }
+
+    {
+        let run = |args: &[&str]| Command::new("target/debug/enw").args(args).output();
+        let piped = run(&["-i", "-n", "-c", "echo $GREETING | tr a-z A-Z", "GREETING=hello"])?;
+        assert!(piped.status.success());
+        assert_eq!(String::from_utf8_lossy(&piped.stdout), "HELLO\n");
+        let globbed = run(&["run", "-n", "--shell-cmd", "echo tests/data/input_0*.txt"])?;
+        assert_eq!(String::from_utf8_lossy(&globbed.stdout), "tests/data/input_01.txt\n");
+        assert_eq!(run(&["-n", "-c", "exit 3"])?.status.code(), Some(3));
+        let extra = run(&["run", "-n", "-c", "true", "echo"])?;
+        assert_eq!(extra.status.code(), Some(125));
+    }
+
+    {
+        let dir = env::temp_dir().join(format!("enw-daemon-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let (pid_file, log_file) = (dir.join("enw.pid"), dir.join("enw.log"));
+        let started = Command::new("target/debug/enw")
+            .args(["-n", "--daemon", "--pid-file"])
+            .arg(&pid_file)
+            .arg("--log-file")
+            .arg(&log_file)
+            .args(["GREETING=hello", "sh", "-c", "echo $GREETING; exec sleep 30"])
+            .output()?;
+        assert!(started.status.success(), "{}", String::from_utf8_lossy(&started.stderr));
+        let mut log = String::new();
+        for _ in 0..100 {
+            log = std::fs::read_to_string(&log_file).unwrap_or_default();
+            if !log.is_empty() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+        let again = Command::new("target/debug/enw")
+            .args(["-n", "--daemon", "--pid-file"])
+            .arg(&pid_file)
+            .arg("true")
+            .output()?;
+        let stop = |pid_file: &Path| Command::new("target/debug/enw").arg("stop").arg("--pid-file").arg(pid_file).output();
+        let stopped = stop(&pid_file)?;
+        let pid_file_left = pid_file.exists();
+        let stopped_again = stop(&pid_file)?;
+        std::fs::remove_dir_all(&dir)?;
+        assert!(log.ends_with(" hello\n"), "{}", log);
+        assert!(String::from_utf8_lossy(&again.stderr).contains("already running with pid"));
+        assert!(stopped.status.success(), "{}", String::from_utf8_lossy(&stopped.stderr));
+        assert!(!pid_file_left);
+        assert!(!stopped_again.status.success());
+    }
+
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = env::temp_dir().join(format!("enw-which-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("bin"))?;
+        std::fs::create_dir_all(dir.join("shadowed"))?;
+        for (path, mode) in [("bin/tool", 0o755), ("shadowed/tool", 0o644)] {
+            std::fs::write(dir.join(path), "#!/bin/sh\n")?;
+            std::fs::set_permissions(dir.join(path), std::fs::Permissions::from_mode(mode))?;
+        }
+        std::fs::write(dir.join(".env"), format!("PATH={0}/shadowed:{0}/bin\n", dir.to_string_lossy()))?;
+        let which = |args: &[&str]| {
+            Command::new("target/debug/enw")
+                .args(["which", "-n", "-f"])
+                .arg(dir.join(".env"))
+                .args(args)
+                .output()
+        };
+        let found = which(&["tool"])?;
+        let missing = which(&["no-such-tool"])?;
+        std::fs::remove_dir_all(&dir)?;
+        assert_eq!(
+            String::from_utf8_lossy(&found.stdout),
+            format!("{}/bin/tool\n", dir.to_string_lossy())
+        );
+        assert_eq!(missing.status.code(), Some(127));
+        assert!(String::from_utf8_lossy(&missing.stderr).contains("no-such-tool: not found in PATH"));
+    }
+
+    {
+        let saved = env::temp_dir().join(format!("enw-sorted-{}.env", std::process::id()));
+        let save = |args: &[&str]| -> Result<(String, String), BoxError> {
+            let output = Command::new("target/debug/enw")
+                .args(["-n", "-i", "--save-env"])
+                .arg(&saved)
+                .args(args)
+                .args(["B=1", "C=2", "A=3", "env"])
+                .output()?;
+            let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+            Ok((std::fs::read_to_string(&saved)?, stdout))
+        };
+        let unsorted = save(&[])?;
+        let sorted = save(&["--sorted-env"])?;
+        std::fs::remove_file(&saved)?;
+        assert_eq!(unsorted.0, "B=1\nC=2\nA=3\n");
+        assert_eq!(unsorted.1, "B=1\nC=2\nA=3\n");
+        assert_eq!(sorted.0, "A=3\nB=1\nC=2\n");
+        assert_eq!(sorted.1, "A=3\nB=1\nC=2\n");
+    }
+
+    {
+        // Variables that can't be written to an env file are skipped rather than failing the run
+        let saved = env::temp_dir().join(format!("enw-multiline-{}.env", std::process::id()));
+        let actual = Command::new("target/debug/enw")
+            .env("ML", "x\ny")
+            .args(["-n", "--save-env"])
+            .arg(&saved)
+            .args(["A=1", "true"])
+            .output()?;
+        let contents = std::fs::read_to_string(&saved)?;
+        std::fs::remove_file(&saved)?;
+        assert!(actual.status.success(), "{:?}", actual);
+        assert!(String::from_utf8_lossy(&actual.stderr).contains("warning: not saving ML to "));
+        assert!(contents.ends_with("A=1\n"), "{contents}");
+        assert!(!contents.contains("ML="), "{contents}");
+    }
+
+    {
+        let path = env::temp_dir().join(format!("enw-unset-{}.env", std::process::id()));
+        std::fs::write(&path, "DEBUG=1\n# enw:unset HOME_DIR DEBUG\n")?;
+        let run = |args: &[&str]| {
+            Command::new("target/debug/enw")
+                .env("HOME_DIR", "/home/me")
+                .env("EDITOR", "vi")
+                .args(["-n", "-f"])
+                .arg(&path)
+                .args(args)
+                .output()
+        };
+        let child = run(&["-u", "EDITOR", "sh", "-c", "echo ${HOME_DIR-unset} ${DEBUG-unset} ${EDITOR-unset}"])?;
+        let set_again = run(&["--unset", "EDITOR", "EDITOR=nano", "sh", "-c", "echo $EDITOR"])?;
+        let export = run(&["export", "bash"])?;
+        std::fs::remove_file(&path)?;
+        assert_eq!(String::from_utf8_lossy(&child.stdout), "unset unset unset\n");
+        assert_eq!(String::from_utf8_lossy(&set_again.stdout), "nano\n");
+        assert_eq!(String::from_utf8_lossy(&export.stdout), "unset DEBUG\nunset HOME_DIR\n");
+    }
+
+    {
+        // Each -e KEY=VALUE pair takes 23 bytes of arguments with 8 byte pointers
+        let actual = Command::new("target/debug/enw")
+            .args(["-n", "--format", "docker-args", "--chunk-size=46", "A=1", "B=2", "C=3"])
+            .output()?;
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "-e A=1 -e B=2\n-e C=3\n");
+        let actual = Command::new("target/debug/enw")
+            .args(["-n", "--format", "docker-args", "--chunk-size", "A=1"])
+            .output()?;
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "-e A=1\n");
+        let actual = Command::new("target/debug/enw")
+            .args(["-n", "--chunk-size=100", "A=1"])
+            .output()?;
+        assert!(String::from_utf8_lossy(&actual.stderr).contains("requires --format docker-args"));
+    }
+
+    if cfg!(target_os = "linux") {
+        let mut service = Command::new("/bin/sleep")
+            .arg("10")
+            .env_clear()
+            .env("PORT", "80")
+            .env("DEBUG", "1")
+            .spawn()?;
+        let actual = Command::new("target/debug/enw")
+            .args(["-n", "diff", "--pid", &service.id().to_string(), "PORT=8080", "DEBUG=1"])
+            .output();
+        let from_pid = Command::new("target/debug/enw")
+            .args(["-n", "-i", "--from-pid", &service.id().to_string(), "PORT=8080", "env"])
+            .output();
+        service.kill()?;
+        service.wait()?;
+        assert_eq!(String::from_utf8_lossy(&actual?.stdout), "-PORT=80\n+PORT=8080\n");
+        assert_eq!(String::from_utf8_lossy(&from_pid?.stdout), "DEBUG=1\nPORT=8080\n");
+    }
+
+    {
+        use std::{io::Write, process::Stdio};
+
+        let mut shell = Command::new("target/debug/enw")
+            .env("SHELL", "sh")
+            .env("PS1", "> ")
+            .args(["-n", "shell", "--prompt", "[app] ", "FOO=bar"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        shell
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(b"echo \"$PS1|$ENW_SHELL|$FOO\"\n")?;
+        let actual = shell.wait_with_output()?;
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "[app] > |1|bar\n");
+    }
+
+    {
+        let enw = env::current_dir()?.join("target/debug/enw");
+        let dir = env::temp_dir().join(format!("enw-status-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join(".env"), "APP_PORT=8080\n")?;
+        let status = |state: &str| {
+            Command::new(&enw)
+                .current_dir(&dir)
+                .env("ENW_STATE", state)
+                .env("APP_PORT", "8080")
+                .args(["status", "--porcelain"])
+                .output()
+        };
+        let untrusted = status("")?;
+        assert!(Command::new(&enw).current_dir(&dir).arg("allow").status()?.success());
+        let loaded = status(r#"{"APP_PORT":null}"#)?;
+        std::fs::write(dir.join(".env"), "APP_PORT=9090\n")?;
+        let modified = status(r#"{"APP_PORT":null}"#)?;
+        assert!(Command::new(&enw).current_dir(&dir).arg("allow").status()?.success());
+        let changed = status(r#"{"APP_PORT":null}"#)?;
+        std::fs::remove_dir_all(&dir)?;
+        let file = dir.join(".env");
+        let file = file.to_string_lossy();
+        assert_eq!(
+            String::from_utf8_lossy(&untrusted.stdout),
+            format!("file {file}\ntrust not-allowed\nvars 0\nchanged no\n")
+        );
+        assert_eq!(
+            String::from_utf8_lossy(&loaded.stdout),
+            format!("file {file}\ntrust allowed\nvars 1\nchanged no\n")
+        );
+        assert_eq!(
+            String::from_utf8_lossy(&modified.stdout),
+            format!("file {file}\ntrust modified\nvars 1\nchanged no\n")
+        );
+        assert_eq!(
+            String::from_utf8_lossy(&changed.stdout),
+            format!("file {file}\ntrust allowed\nvars 1\nchanged yes\n")
+        );
+    }
+
+    {
+        let path = env::temp_dir().join(format!("enw-optional-{}.env", std::process::id()));
+        std::fs::write(&path, "A=1\n")?;
+        let actual = Command::new("target/debug/enw")
+            .args(["-n", "--lenient", "--optional-file", "maybe-missing.env", "-f", "missing.env"])
+            .arg("--optional-file")
+            .arg(&path)
+            .args(["-i", "env"])
+            .output()?;
+        std::fs::remove_file(&path)?;
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "A=1\n");
+        let stderr = String::from_utf8_lossy(&actual.stderr);
+        assert!(stderr.contains("missing.env does not exist"), "{}", stderr);
+        assert!(!stderr.contains("maybe-missing.env"), "{}", stderr);
+    }
+
+    {
+        let path = env::temp_dir().join(format!("enw-graph-{}.env", std::process::id()));
+        std::fs::write(&path, "URL=http://$HOST:${PORT:-80}/$APP_PATH\nHOST=localhost\n")?;
+        let actual = Command::new("target/debug/enw").arg("graph").arg(&path).output()?;
+        let dot = Command::new("target/debug/enw").args(["graph", "--dot"]).arg(&path).output()?;
+        std::fs::remove_file(&path)?;
+        assert_eq!(actual.status.code(), Some(125));
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "URL -> HOST, PORT, APP_PATH\nHOST\n");
+        let stderr = String::from_utf8_lossy(&actual.stderr);
+        assert!(stderr.contains("APP_PATH is referenced by URL but not defined"), "{}", stderr);
+        assert!(!stderr.contains("PORT is"), "{}", stderr);
+        assert!(String::from_utf8_lossy(&dot.stdout).contains("  \"URL\" -> \"APP_PATH\";\n"));
+    }
+
+    {
+        // A stand-in for gpg, "encrypting" by writing the recipients on the first line
+        let dir = env::temp_dir().join(format!("enw-encrypt-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let gpg = dir.join("gpg");
+        std::fs::write(
+            &gpg,
+            "#!/bin/sh\nif [ \"$4\" = --decrypt ]; then\n\
+             for id in $(head -n 1 \"$6\"); do echo \"[GNUPG:] ENC_TO $id 1 0\" >&2; done\n\
+             tail -n +2 \"$6\"\nelse\nshift 2; echo \"$@\" | sed 's/--recipient //g'; cat\nfi\n",
+        )?;
+        std::fs::set_permissions(&gpg, std::os::unix::fs::PermissionsExt::from_mode(0o755))?;
+        let path = format!("{}:/usr/bin:/bin", dir.to_string_lossy());
+        let file = dir.join("prod.env");
+        std::fs::write(&file, "A=1\n")?;
+        let run = |args: &[&str]| {
+            Command::new("target/debug/enw")
+                .env("PATH", &path)
+                .env("EDITOR", "sed -i s/1/2/")
+                .env_remove("VISUAL")
+                .args(args)
+                .output()
+        };
+        let encrypted = dir.join("prod.env.gpg");
+        let actual = run(&["encrypt", &file.to_string_lossy()])?;
+        assert!(actual.status.success(), "{:?}", actual);
+        assert!(file.exists());
+        // An encrypted file is never overwritten unless forced
+        let actual = run(&["encrypt", "--recipient", "K1", &file.to_string_lossy()])?;
+        assert_eq!(actual.status.code(), Some(125));
+        assert!(String::from_utf8_lossy(&actual.stderr).contains("use --force"));
+        let actual = run(&["encrypt", "--force", "--remove", "--recipient", "K1", "--recipient", "K2", &file.to_string_lossy()])?;
+        assert!(actual.status.success(), "{:?}", actual);
+        assert!(!file.exists());
+        assert_eq!(std::fs::read_to_string(&encrypted)?, "K1 K2\nA=1\n");
+        let actual = run(&["edit", &encrypted.to_string_lossy()])?;
+        assert!(actual.status.success(), "{:?}", actual);
+        assert_eq!(std::fs::read_to_string(&encrypted)?, "K1 K2\nA=2\n");
+        let actual = run(&["decrypt", &encrypted.to_string_lossy()])?;
+        assert!(actual.status.success(), "{:?}", actual);
+        assert_eq!(std::fs::read_to_string(&file)?, "A=2\n");
+        let mode = std::os::unix::fs::PermissionsExt::mode(&std::fs::metadata(&file)?.permissions());
+        assert_eq!(mode & 0o777, 0o600);
+        // The plaintext is never overwritten
+        let actual = run(&["decrypt", &encrypted.to_string_lossy()])?;
+        assert_eq!(actual.status.code(), Some(125));
+        std::fs::remove_dir_all(&dir)?;
+    }
+
+    {
+        let log = env::temp_dir().join(format!("enw-audit-{}.log", std::process::id()));
+        let audit_log = format!("--audit-log={}", log.display());
+        let actual = Command::new("target/debug/enw")
+            .args(["-i", "-n", &audit_log, "A=1", "true"])
+            .output()?;
+        assert!(actual.status.success(), "{:?}", actual);
+        // Nothing is recorded without secrets
+        assert!(!log.exists());
+        let actual = Command::new("target/debug/enw")
+            .args(["-i", "-n", "-f", "tests/data/secret.env.gpg", &audit_log, "A=1"])
+            .args(["sh", "-c", "test \"$SECRET\" = 'from gpg'"])
+            .output()?;
+        assert!(actual.status.success(), "{:?}", actual);
+        let record = std::fs::read_to_string(&log)?;
+        std::fs::remove_file(&log)?;
+        assert!(record.ends_with('\n') && record.lines().count() == 1, "{}", record);
+        assert!(record.contains(r#""command":["sh","-c","test \"$SECRET\" = 'from gpg'"]"#), "{}", record);
+        assert!(record.contains(r#""keys":["SECRET"],"sources":["tests/data/secret.env.gpg"]"#), "{}", record);
+        assert!(!record.contains("from gpg\""), "{}", record);
+    }
+
+    {
+        let dir = env::temp_dir().join(format!("enw-option-vars-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join("a.env"), "B=1\nA=1\n")?;
+        std::fs::write(dir.join("b.env"), "A=2\n")?;
+        let files = format!("{}:{}", dir.join("a.env").display(), dir.join("b.env").display());
+        let run = |args: &[&str]| {
+            Command::new("target/debug/enw")
+                .env("ENW_FILES", &files)
+                .env("ENW_SORT", "1")
+                .env("ENW_ON_DUPLICATE", "error")
+                .args(args)
+                .output()
+        };
+        let actual = run(&["print", "-i", "--on-duplicate", "last-wins"])?;
+        assert!(actual.status.success(), "{:?}", actual);
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "A=2\nB=1\n");
+        let actual = run(&["print", "-i"])?;
+        assert_eq!(actual.status.code(), Some(125));
+        // The files given replace those of ENW_FILES
+        let actual = run(&["print", "-i", "-f", &dir.join("b.env").to_string_lossy()])?;
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "A=2\n");
+        // Counted options are repeated
+        let actual = Command::new("target/debug/enw")
+            .env("ENW_VERBOSE", "1")
+            .args(["-n", "-i", "A=1", "true"])
+            .output()?;
+        assert!(actual.status.success(), "{:?}", actual);
+        assert!(String::from_utf8_lossy(&actual.stderr).contains("info: 1 variables resolved"));
+        // Subcommands of their own still run with options set from variables
+        for args in [["completions", "bash"], ["hook", "bash"]] {
+            let actual = run(&args)?;
+            assert!(actual.status.success(), "{:?} {:?}", args, actual);
+        }
+        let actual = Command::new("target/debug/enw")
+            .env("ENW_SORT", "1")
+            .arg("lint")
+            .arg(dir.join("b.env"))
+            .output()?;
+        assert!(actual.status.success(), "{:?}", actual);
+        std::fs::remove_dir_all(&dir)?;
+    }
+
+    {
+        // A command with '=' in its name
+        let dir = env::temp_dir().join(format!("enw-inline-vars-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let script = dir.join("mode=fast");
+        std::fs::write(&script, "#!/bin/sh\necho \"${A:-unset} $1\"\n")?;
+        std::fs::set_permissions(&script, std::os::unix::fs::PermissionsExt::from_mode(0o755))?;
+        let script = script.to_string_lossy();
+        let run = |args: &[&str]| Command::new("target/debug/enw").arg("-i").args(args).output();
+        let actual = run(&["A=1", "--", &script, "B=2"])?;
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "1 B=2\n");
+        let actual = run(&["--", "--", &script])?;
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "unset \n");
+        let actual = run(&["--no-inline-vars", &script, "A=1"])?;
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "unset A=1\n");
+        std::fs::remove_dir_all(&dir)?;
+    }
+
+    {
+        let run = |args: &[&str]| Command::new("target/debug/enw").args(args).output();
+        let actual = run(&["print", "--var", "A=1", "-i", "B=2", "--var", "A=3", "--var=C=4"])?;
+        assert!(actual.status.success(), "{:?}", actual);
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "A=3\nB=2\nC=4\n");
+        // Arguments after COMMAND are its own
+        let actual = run(&["-i", "--var", "A=1", "sh", "-c", "echo $A $0", "--var"])?;
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "1 --var\n");
+        let actual = run(&["-i", "--var", "A", "true"])?;
+        assert_eq!(actual.status.code(), Some(125));
+        assert!(String::from_utf8_lossy(&actual.stderr).contains("expected NAME=VALUE: A"));
+    }
+
+    {
+        let run = |args: &[&str]| {
+            Command::new("target/debug/enw")
+                .env("INHERITED", "yes")
+                .args(["print", "-n"])
+                .args(args)
+                .output()
+        };
+        let actual = run(&["A=1", "B=two words", "B", "INHERITED", "A"])?;
+        assert!(actual.status.success(), "{:?}", actual);
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "two words\nyes\n1\n");
+        let actual = run(&["-i", "A=1", "A", "INHERITED", "MISSING"])?;
+        assert_eq!(actual.status.code(), Some(1));
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "1\n");
+        assert!(String::from_utf8_lossy(&actual.stderr).contains("INHERITED, MISSING are not set"));
+        let actual = run(&["-i", "MISSING"])?;
+        assert_eq!(actual.status.code(), Some(1));
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "");
+    }
+
+    in_directory(&env::current_dir()?.join("tests/data/include"), || {
+        let run = |args: &[&str]| {
+            Command::new("../../../target/debug/enw")
+                .args(["check", "-n", "-i", "-f", "base.env", "-f", ".env"])
+                .args(args)
+                .output()
+        };
+        let actual = run(&[])?;
+        assert!(actual.status.success(), "{:?}", actual);
+        let actual = run(&["--collisions"])?;
+        assert_eq!(actual.status.code(), Some(125));
+        let stderr = String::from_utf8_lossy(&actual.stderr);
+        assert!(
+            stderr.contains("OVERRIDDEN is defined with different values in base.env, .env\n"),
+            "{}",
+            stderr
+        );
+        assert!(!stderr.contains("BASE is"), "{}", stderr);
+        assert!(stderr.contains("1 problem found"), "{}", stderr);
+        Ok(())
+    })?;
+
+    {
+        // A mistyped option of a subcommand is a usage error rather than a COMMAND to run
+        let run = |args: &[&str]| Command::new("target/debug/enw").args(args).output();
+        for args in [&["-n", "check", "--colisions"][..], &["-n", "diff", "--pidd", "1"], &["print", "--sortt"]] {
+            let actual = run(args)?;
+            assert_eq!(actual.status.code(), Some(125), "{:?}", args);
+            assert!(String::from_utf8_lossy(&actual.stderr).contains("unexpected argument"));
+        }
+        // Which is run after `--` or with `enw run`
+        for args in [&["-n", "--", "diff", "/dev/null", "/dev/null"][..], &["-n", "run", "diff", "/dev/null", "/dev/null"]] {
+            assert!(run(args)?.status.success(), "{:?}", args);
+        }
+    }
+
+    in_directory(&env::current_dir()?.join("tests/data/enwrc"), || {
+        let actual = Command::new("../../../target/debug/enw")
+            .args(["-n", "--remote", "prod"])
+            .output()?;
+        assert!(!actual.status.success());
+        assert!(String::from_utf8_lossy(&actual.stderr).contains("no [remote.prod] section"));
+        Ok(())
+    })?;
+
+    in_directory(&env::current_dir()?.join("tests/data/enwrc"), || {
+        let actual = Command::new("../../../target/debug/enw")
+            .args(["-i", "env"])
+            .output()?;
+        assert!(actual.status.success());
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "DB=postgres\n");
+
+        let actual = Command::new("../../../target/debug/enw")
+            .args(["-i", "-n", "env"])
+            .output()?;
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "");
+        Ok(())
+    })?;
+
+    in_directory(&env::current_dir()?.join("tests"), || {
+        let actual = Command::new("../target/debug/enw")
+            .args(["-n", "--dialect", "systemd", "-f", "data/systemd.env"])
+            .args(["--format", "systemd"])
+            .output()?;
+        assert!(actual.status.success(), "{:?}", actual);
+        assert_eq!(
+            String::from_utf8_lossy(&actual.stdout),
+            "GREETING=\"hello\n  world\"\nPATH_LIST=/a:/b\nQUOTED=\"\\$HOME is literal\"\n"
+        );
+        Ok(())
+    })?;
+
+    in_directory(&env::current_dir()?.join("tests"), || {
+        let actual = Command::new("bash")
+            .args(["-c", "../target/debug/enw -i -n -f <(printf 'A=1\\nB=2\\n')"])
+            .output()?;
+        assert!(actual.status.success());
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "A=1\nB=2\n");
+        Ok(())
+    })?;
+
+    in_directory(&env::current_dir()?.join("tests"), || {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let path = env::temp_dir().join(format!("enw-bytes-{}.env", std::process::id()));
+        std::fs::write(&path, b"A=caf\xe9\nC='\xff \xfe'\n")?;
+        let actual = Command::new("../target/debug/enw")
+            .args(["-i", "-n", "-f"])
+            .arg(&path)
+            .arg(OsStr::from_bytes(b"B=\xe9t\xe9"))
+            .arg("env")
+            .output()?;
+        assert!(actual.status.success());
+        assert_eq!(actual.stdout, b"A=caf\xe9\nC=\xff \xfe\nB=\xe9t\xe9\n");
+        std::fs::remove_file(&path)?;
+        Ok(())
+    })?;
+
+    in_directory(&env::current_dir()?.join("tests"), || {
+        use std::os::unix::fs::PermissionsExt;
+
+        let enw = env::current_dir()?.join("../target/debug/enw").canonicalize()?;
+        let script = env::temp_dir().join(format!("enw-shebang-{}.sh", std::process::id()));
+        std::fs::write(
+            &script,
+            format!(
+                "#!{} -S -i -n GREETING='hello world' /bin/sh\necho \"$GREETING $1\"\n",
+                enw.display()
+            ),
+        )?;
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755))?;
+        let actual = Command::new(&script).arg("again").output()?;
+        assert!(actual.status.success(), "{:?}", actual);
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "hello world again\n");
+        std::fs::remove_file(&script)?;
+        Ok(())
+    })?;
+
+    in_directory(&env::current_dir()?.join("tests"), || {
+        use std::os::unix::fs::PermissionsExt;
+
+        let enw = env::current_dir()?.join("../target/debug/enw").canonicalize()?;
+        let dir = env::temp_dir().join(format!("enw-here-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join(".env"), "GREETING=from script dir\n")?;
+        let status = Command::new(&enw).arg("allow").arg(&dir).status()?;
+        assert!(status.success());
+        let script = dir.join("script.sh");
+        std::fs::write(
+            &script,
+            format!(
+                "#!{} -S --here -i /bin/sh\necho \"$GREETING\"\n",
+                enw.display()
+            ),
+        )?;
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755))?;
+        let actual = Command::new(&script).output()?;
+        assert!(actual.status.success(), "{:?}", actual);
+        assert_eq!(String::from_utf8_lossy(&actual.stdout), "from script dir\n");
+
+        let actual = Command::new("../target/debug/enw")
+            .args(["--here", "-i", "/bin/sh", "-c", "true"])
+            .output()?;
+        assert!(!actual.status.success());
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    })?;
+
+    // Differential test against the system env(1)
+    in_directory(&env::current_dir()?.join("tests"), || {
+        let cases: Vec<Vec<&str>> = vec![
+            vec![],
+            vec!["-i"],
+            vec!["-i", "a=b", "c=d e", "a=f"],
+            vec!["XYZZY=override", "new=1", "w=x=y"],
+            vec!["-i", "x='quoted'", "y=\"", "env"],
+            vec!["HOME=/elsewhere", "env"],
+            vec!["-0", "a=b"],
+            vec!["-i", "-0", "a=line 1\nline 2"],
+            vec!["-", "a=b"],
+            vec!["-", "a=b", "env"],
+            vec!["-i", "--", "a=b", "env"],
+            vec!["--", "a=b"],
+            vec!["a=b", "-"],
+            vec!["-i", "no-such-command-xyzzy"],
+            vec!["-i", "/"],
+            vec!["--no-such-option"],
+        ];
+        for case in cases {
+            let expected = Command::new("env")
+                .env_clear()
+                .env("XYZZY", "123")
+                .env("HOME", "/home/xyzzy")
+                .args(&case)
+                .output()?;
+            let actual = Command::new("../target/debug/enw")
+                .env_clear()
+                .env("XYZZY", "123")
+                .env("HOME", "/home/xyzzy")
+                .arg("--posix")
+                .args(&case)
+                .output()?;
+            assert_eq!(actual.status.code(), expected.status.code(), "{:?}", case);
+            assert_eq!(
+                String::from_utf8_lossy(&actual.stdout),
+                String::from_utf8_lossy(&expected.stdout),
+                "{:?}",
+                case
+            );
+        }
+        Ok(())
+    })?;
+
+    std::fs::remove_dir_all(&data_home)?;
     Ok(())
 }
 